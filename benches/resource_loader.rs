@@ -0,0 +1,96 @@
+//! Measures how long `ResourceLoader` takes on each UI poll as the number of
+//! loaded resources grows.
+//!
+//! Run with `cargo bench --bench resource_loader`.
+
+use basedrop::Shared;
+use meadowlark::backend::resource_loader::{PcmKey, ResourceLoader};
+use meadowlark_core_types::time::SampleRate;
+use pcm_loader::{PcmRAM, ResampleQuality};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const NUM_RESOURCES: [usize; 4] = [10, 100, 1_000, 5_000];
+const NUM_POLLS: u32 = 1_000;
+
+fn main() {
+    let dir = std::env::temp_dir().join("meadowlark-resource-loader-bench");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    for num_resources in NUM_RESOURCES {
+        let mut resource_loader = ResourceLoader::new(SampleRate(44_100.0));
+
+        // Keep half of the resources in use, like the clips in a large project.
+        let mut in_use: Vec<Shared<PcmRAM>> = Vec::new();
+        for i in 0..num_resources {
+            let (pcm, res) = resource_loader.load_pcm(&PcmKey {
+                path: write_wav(&dir, i),
+                resample_to_project_sr: false,
+                resample_quality: ResampleQuality::Linear,
+                doppler_stretch_ratio: 1.0,
+            });
+            res.unwrap();
+
+            if i % 2 == 0 {
+                in_use.push(pcm);
+            }
+        }
+
+        // Force the slowest path, where every collection has to look for
+        // resources to evict.
+        resource_loader.set_memory_budget(0);
+
+        let every_poll = time_polls(&mut resource_loader, |r| r.collect());
+        let scheduled = time_polls(&mut resource_loader, |r| r.collect_if_due());
+
+        println!(
+            "{:>5} resources: collect() every poll {:>10.2?}/poll, collect_if_due() {:>10.2?}/poll",
+            num_resources, every_poll, scheduled
+        );
+    }
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+/// Returns the average time of one poll of the resource loader.
+fn time_polls(resource_loader: &mut ResourceLoader, collect: fn(&mut ResourceLoader)) -> Duration {
+    let start = Instant::now();
+    for _ in 0..NUM_POLLS {
+        let _ = resource_loader.poll();
+        collect(resource_loader);
+    }
+    start.elapsed() / NUM_POLLS
+}
+
+/// Write a short mono 16 bit WAV file.
+fn write_wav(dir: &Path, i: usize) -> PathBuf {
+    const NUM_FRAMES: u32 = 256;
+
+    let path = dir.join(format!("{}.wav", i));
+    if path.exists() {
+        return path;
+    }
+
+    let data_len = NUM_FRAMES * 2;
+
+    let mut file = File::create(&path).unwrap();
+    file.write_all(b"RIFF").unwrap();
+    file.write_all(&(36 + data_len).to_le_bytes()).unwrap();
+    file.write_all(b"WAVEfmt ").unwrap();
+    file.write_all(&16u32.to_le_bytes()).unwrap();
+    file.write_all(&1u16.to_le_bytes()).unwrap(); // PCM
+    file.write_all(&1u16.to_le_bytes()).unwrap(); // mono
+    file.write_all(&44_100u32.to_le_bytes()).unwrap();
+    file.write_all(&(44_100u32 * 2).to_le_bytes()).unwrap();
+    file.write_all(&2u16.to_le_bytes()).unwrap();
+    file.write_all(&16u16.to_le_bytes()).unwrap();
+    file.write_all(b"data").unwrap();
+    file.write_all(&data_len.to_le_bytes()).unwrap();
+    for n in 0..NUM_FRAMES {
+        file.write_all(&((n as i16) * 64).to_le_bytes()).unwrap();
+    }
+
+    path
+}
@@ -0,0 +1,223 @@
+//! Streaming of long audio files from disk with [`creek`].
+//!
+//! Short files are decoded into RAM up front by the `ResourceLoader`. Files
+//! over the streaming threshold are instead read a little ahead of the
+//! playhead on a separate thread, so playing a long recording does not need
+//! to decode the whole file first or keep all of it in memory.
+//!
+//! [`creek`]: https://github.com/MeadowlarkDAW/creek
+
+use basedrop::{Owned, Shared};
+use creek::{ReadDiskStream, ReadStreamOptions, SeekMode, SymphoniaDecoder};
+use pcm_loader::PcmRAM;
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+/// Files at least this large are streamed from disk instead of being loaded
+/// into RAM.
+pub const DEFAULT_STREAM_THRESHOLD_BYTES: u64 = 64 * 1024 * 1024;
+
+/// The number of blocks to read ahead of the playhead.
+const NUM_LOOK_AHEAD_BLOCKS: usize = 8;
+
+#[derive(Debug)]
+pub enum DiskStreamError {
+    Open {
+        path: PathBuf,
+        error: String,
+    },
+    /// The file is not at the sample rate of the project. Streams are not
+    /// resampled, so the file must be loaded into RAM instead.
+    NeedsResample {
+        path: PathBuf,
+        sample_rate: Option<u32>,
+    },
+    Read {
+        path: PathBuf,
+        error: String,
+    },
+}
+
+impl Error for DiskStreamError {}
+
+impl fmt::Display for DiskStreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiskStreamError::Open { path, error } => {
+                write!(f, "Failed to open {:?} for streaming: {}", path, error)
+            }
+            DiskStreamError::NeedsResample { path, sample_rate } => match sample_rate {
+                Some(sr) => write!(f, "Cannot stream {:?} at its sample rate of {} Hz", path, sr),
+                None => write!(f, "Cannot stream {:?}: unknown sample rate", path),
+            },
+            DiskStreamError::Read { path, error } => {
+                write!(f, "Failed to read {:?} from disk: {}", path, error)
+            }
+        }
+    }
+}
+
+/// An audio file that is read from disk while it plays.
+pub struct DiskStream {
+    stream: ReadDiskStream<SymphoniaDecoder>,
+
+    len_frames: u64,
+    num_channels: usize,
+
+    /// The frame that will be read next if playback continues without
+    /// jumping. Any other frame requires a seek.
+    next_frame: u64,
+}
+
+impl DiskStream {
+    /// Open the file at `path` and wait until the audio at `start_frame` is
+    /// ready to be played.
+    ///
+    /// Call this from the UI, not the audio thread. `start_frame` is also
+    /// kept in a cache, so jumping back to it (i.e. when the transport loops
+    /// back to the start of a clip) does not underrun.
+    pub fn open(path: &Path, start_frame: u64, project_sr: u32) -> Result<Self, DiskStreamError> {
+        let opts = ReadStreamOptions {
+            num_cache_blocks: NUM_LOOK_AHEAD_BLOCKS,
+            num_look_ahead_blocks: NUM_LOOK_AHEAD_BLOCKS,
+            num_caches: 1,
+            ..Default::default()
+        };
+
+        let mut stream = ReadDiskStream::<SymphoniaDecoder>::new(path, start_frame as usize, opts)
+            .map_err(|e| DiskStreamError::Open { path: path.to_owned(), error: e.to_string() })?;
+
+        let info = stream.info();
+        if info.sample_rate != Some(project_sr) {
+            return Err(DiskStreamError::NeedsResample {
+                path: path.to_owned(),
+                sample_rate: info.sample_rate,
+            });
+        }
+        let len_frames = info.num_frames as u64;
+        let num_channels = usize::from(info.num_channels);
+
+        let read_err = |e: &dyn fmt::Display| DiskStreamError::Read {
+            path: path.to_owned(),
+            error: e.to_string(),
+        };
+
+        stream.cache(0, start_frame as usize).map_err(|e| read_err(&e))?;
+        stream.seek(start_frame as usize, SeekMode::Auto).map_err(|e| read_err(&e))?;
+        stream.block_until_ready().map_err(|e| read_err(&e))?;
+
+        Ok(Self { stream, len_frames, num_channels, next_frame: start_frame })
+    }
+
+    pub fn len_frames(&self) -> u64 {
+        self.len_frames
+    }
+
+    /// Fill the buffers with the audio starting at `frame`. Mono files are
+    /// copied to both channels.
+    ///
+    /// Returns `false` if the data was not read from disk in time, in which
+    /// case the buffers are filled with silence.
+    pub fn fill_stereo_f32(&mut self, frame: u64, buf_l: &mut [f32], buf_r: &mut [f32]) -> bool {
+        let frames = buf_l.len().min(buf_r.len());
+
+        buf_l.fill(0.0);
+        buf_r.fill(0.0);
+
+        if frame >= self.len_frames {
+            return true;
+        }
+
+        if frame != self.next_frame {
+            // The playhead jumped.
+            if self.stream.seek(frame as usize, SeekMode::Auto).is_err() {
+                return false;
+            }
+        }
+        self.next_frame = frame + frames as u64;
+
+        let ready = matches!(self.stream.is_ready(), Ok(true));
+
+        // Keep reading even when the data is not ready, so the stream stays in
+        // step with the playhead.
+        let data = match self.stream.read(frames) {
+            Ok(data) => data,
+            Err(_) => return false,
+        };
+
+        if !ready {
+            return false;
+        }
+
+        let n = data.num_frames().min(frames);
+        buf_l[0..n].copy_from_slice(&data.read_channel(0)[0..n]);
+        if self.num_channels > 1 {
+            buf_r[0..n].copy_from_slice(&data.read_channel(1)[0..n]);
+        } else {
+            buf_r[0..n].copy_from_slice(&data.read_channel(0)[0..n]);
+        }
+
+        true
+    }
+}
+
+/// The audio data that a plugin plays, either decoded into RAM or streamed
+/// from disk.
+pub enum PcmSource {
+    Ram(Shared<PcmRAM>),
+    Stream(Owned<DiskStream>),
+}
+
+impl PcmSource {
+    pub fn len_frames(&self) -> u64 {
+        match self {
+            PcmSource::Ram(pcm) => pcm.len_frames() as u64,
+            PcmSource::Stream(stream) => stream.len_frames(),
+        }
+    }
+
+    /// Fill the buffers with the audio starting at `frame`.
+    ///
+    /// Returns `false` if a stream underran.
+    pub fn fill_stereo_f32(&mut self, frame: u64, buf_l: &mut [f32], buf_r: &mut [f32]) -> bool {
+        match self {
+            PcmSource::Ram(pcm) => {
+                pcm.fill_stereo_f32(frame as isize, buf_l, buf_r);
+                true
+            }
+            PcmSource::Stream(stream) => stream.fill_stereo_f32(frame, buf_l, buf_r),
+        }
+    }
+
+    /// Returns another source that plays the same audio, if that is possible
+    /// without touching the disk.
+    pub fn try_clone(&self) -> Option<Self> {
+        match self {
+            PcmSource::Ram(pcm) => Some(PcmSource::Ram(Shared::clone(pcm))),
+            PcmSource::Stream(_) => None,
+        }
+    }
+}
+
+/// Counts the number of times streams could not be read from disk in time.
+///
+/// The audio thread increments this, and the UI takes the count to report
+/// it to the user.
+#[derive(Clone, Default)]
+pub struct UnderrunCounter {
+    count: Arc<AtomicU32>,
+}
+
+impl UnderrunCounter {
+    pub fn increment(&self) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the number of underruns since this was last called.
+    pub fn take(&self) -> u32 {
+        self.count.swap(0, Ordering::Relaxed)
+    }
+}
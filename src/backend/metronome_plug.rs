@@ -0,0 +1,517 @@
+use basedrop::{Owned, Shared};
+use dropseed::plugin::event::ParamValueEvent;
+use dropseed::plugin::ext::params::{ParamID, ParamInfoFlags};
+use dropseed::plugin::{
+    buffer::EventBuffer, ext, HostInfo, HostRequestChannelSender, HostRequestFlags,
+    PluginActivatedInfo, PluginAudioThread, PluginDescriptor, PluginFactory, PluginInstanceID,
+    PluginMainThread, ProcBuffers, ProcInfo, ProcessStatus,
+};
+use meadowlark_core_types::parameter::{
+    Gradient, ParamF32, ParamF32Handle, Unit, DEFAULT_DB_GRADIENT, DEFAULT_SMOOTH_SECS,
+};
+use meadowlark_core_types::time::{SampleRate, Seconds};
+use pcm_loader::PcmRAM;
+use rtrb::{Consumer, Producer, RingBuffer};
+use std::f32::consts::TAU;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+pub static METRONOME_PLUG_RDN: &str = "app.meadowlark.metronome";
+
+static CLICK_TIME: Seconds = Seconds(40.0 / 1000.0);
+
+/// The pitch of the built-in clicks.
+const DOWNBEAT_CLICK_HZ: f32 = 1760.0;
+const BEAT_CLICK_HZ: f32 = 880.0;
+
+const MSG_BUFFER_SIZE: usize = 64;
+
+const GAIN_PARAM: ParamID = ParamID(0);
+const ENABLED_PARAM: ParamID = ParamID(1);
+
+pub struct MetronomePlugFactory;
+
+impl PluginFactory for MetronomePlugFactory {
+    fn description(&self) -> PluginDescriptor {
+        PluginDescriptor {
+            id: METRONOME_PLUG_RDN.into(),
+            version: "0.1".into(),
+            name: "Metronome".into(),
+            vendor: "Meadowlark".into(),
+            description: String::new(),
+            url: String::new(),
+            manual_url: String::new(),
+            support_url: String::new(),
+            features: String::new(),
+        }
+    }
+
+    fn instantiate(
+        &mut self,
+        host_request_channel: HostRequestChannelSender,
+        _host_info: Shared<HostInfo>,
+        _plugin_id: PluginInstanceID,
+        _coll_handle: &basedrop::Handle,
+    ) -> Result<Box<dyn PluginMainThread>, String> {
+        Ok(Box::new(MetronomePlugMainThread::new(host_request_channel)))
+    }
+}
+
+/// A click of the metronome.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Click {
+    /// The frame of the click. This is on the timeline for the clicks that
+    /// follow the transport, and from the start of the count-in for the
+    /// count-in clicks.
+    pub frame: u64,
+    /// True if this is the first beat of a bar.
+    pub downbeat: bool,
+}
+
+pub struct MetronomePlugHandle {
+    params: ParamsHandle,
+    to_audio_thread_tx: Producer<ProcessMsg>,
+    host_request: HostRequestChannelSender,
+    coll_handle: basedrop::Handle,
+    count_in_finished: Arc<AtomicBool>,
+}
+
+impl MetronomePlugHandle {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.params.enabled.set_value(if enabled { 1.0 } else { 0.0 });
+    }
+
+    pub fn set_gain_db(&mut self, gain_db: f32) {
+        self.params.gain.set_value(gain_db);
+    }
+
+    /// Set the clicks to play while the transport is playing, sorted by
+    /// frame.
+    pub fn set_clicks(&mut self, clicks: Vec<Click>) {
+        self.send(ProcessMsg::SetClicks { clicks: Owned::new(&self.coll_handle, clicks) });
+    }
+
+    /// Set the sounds of the clicks on the first beat of every bar and on the
+    /// other beats. `None` uses the built-in click.
+    pub fn set_sounds(&mut self, downbeat: Option<Shared<PcmRAM>>, beat: Option<Shared<PcmRAM>>) {
+        self.send(ProcessMsg::SetSounds { downbeat, beat });
+    }
+
+    /// Play the given clicks from now on, whether or not the metronome is
+    /// enabled, and then report back with `take_count_in_finished()`.
+    ///
+    /// `len_frames` is the length of the whole count-in.
+    pub fn start_count_in(&mut self, clicks: Vec<Click>, len_frames: u64) {
+        self.count_in_finished.store(false, Ordering::Relaxed);
+        self.send(ProcessMsg::StartCountIn {
+            clicks: Owned::new(&self.coll_handle, clicks),
+            len_frames,
+        });
+        self.host_request.request(HostRequestFlags::PROCESS);
+    }
+
+    pub fn cancel_count_in(&mut self) {
+        self.send(ProcessMsg::CancelCountIn);
+    }
+
+    /// Returns `true` once if the count-in has finished since this was last
+    /// called.
+    pub fn take_count_in_finished(&mut self) -> bool {
+        self.count_in_finished.swap(false, Ordering::Relaxed)
+    }
+
+    fn send(&mut self, msg: ProcessMsg) {
+        if let Err(e) = self.to_audio_thread_tx.push(msg) {
+            log::error!("Metronome plugin failed to send message: {}", e);
+        }
+    }
+}
+
+enum ProcessMsg {
+    SetClicks { clicks: Owned<Vec<Click>> },
+    SetSounds { downbeat: Option<Shared<PcmRAM>>, beat: Option<Shared<PcmRAM>> },
+    StartCountIn { clicks: Owned<Vec<Click>>, len_frames: u64 },
+    CancelCountIn,
+}
+
+#[derive(Clone)]
+struct ParamsHandle {
+    pub gain: ParamF32Handle,
+    pub enabled: ParamF32Handle,
+}
+
+struct Params {
+    pub gain: ParamF32,
+    pub enabled: ParamF32,
+}
+
+impl Params {
+    fn new(sample_rate: SampleRate, max_frames: usize) -> (Self, ParamsHandle) {
+        let (gain, gain_handle) = ParamF32::from_value(
+            -6.0,
+            -6.0,
+            -90.0,
+            6.0,
+            DEFAULT_DB_GRADIENT,
+            Unit::Decibels,
+            DEFAULT_SMOOTH_SECS,
+            sample_rate,
+            max_frames,
+        );
+
+        // The metronome is off until the user turns it on, so it is not heard
+        // in renders.
+        let (enabled, enabled_handle) = ParamF32::from_value(
+            0.0,
+            0.0,
+            0.0,
+            1.0,
+            Gradient::Linear,
+            Unit::Generic,
+            Seconds(0.0),
+            sample_rate,
+            max_frames,
+        );
+
+        (Params { gain, enabled }, ParamsHandle { gain: gain_handle, enabled: enabled_handle })
+    }
+}
+
+pub struct MetronomePlugMainThread {
+    params: ParamsHandle,
+    host_request: HostRequestChannelSender,
+}
+
+impl MetronomePlugMainThread {
+    fn new(host_request: HostRequestChannelSender) -> Self {
+        // These parameters will be re-initialized later with the correct sample_rate
+        // and max_frames when the plugin is activated.
+        let (_params, params_handle) = Params::new(Default::default(), 0);
+
+        Self { params: params_handle, host_request }
+    }
+}
+
+impl PluginMainThread for MetronomePlugMainThread {
+    fn activate(
+        &mut self,
+        sample_rate: SampleRate,
+        _min_frames: u32,
+        max_frames: u32,
+        coll_handle: &basedrop::Handle,
+    ) -> Result<PluginActivatedInfo, String> {
+        let (params, params_handle) = Params::new(sample_rate, max_frames as usize);
+        self.params = params_handle.clone();
+
+        let (to_audio_thread_tx, from_handle_rx) = RingBuffer::<ProcessMsg>::new(MSG_BUFFER_SIZE);
+        let from_handle_rx = Owned::new(coll_handle, from_handle_rx);
+
+        let click_frames = CLICK_TIME.to_nearest_frame_round(sample_rate).0 as usize;
+        let downbeat_click = Owned::new(
+            coll_handle,
+            synth_click(DOWNBEAT_CLICK_HZ, click_frames, sample_rate.0 as f32),
+        );
+        let beat_click =
+            Owned::new(coll_handle, synth_click(BEAT_CLICK_HZ, click_frames, sample_rate.0 as f32));
+
+        let count_in_finished = Arc::new(AtomicBool::new(false));
+
+        Ok(PluginActivatedInfo {
+            audio_thread: Box::new(MetronomePlugAudioThread {
+                params,
+                from_handle_rx,
+                clicks: Owned::new(coll_handle, Vec::new()),
+                count_in: None,
+                count_in_finished: Arc::clone(&count_in_finished),
+                downbeat_click,
+                beat_click,
+                downbeat_sample: None,
+                beat_sample: None,
+                voice: None,
+                sample_buf_l: Owned::new(coll_handle, vec![0.0; max_frames as usize]),
+                sample_buf_r: Owned::new(coll_handle, vec![0.0; max_frames as usize]),
+            }),
+            internal_handle: Some(Box::new(MetronomePlugHandle {
+                params: params_handle,
+                to_audio_thread_tx,
+                host_request: self.host_request.clone(),
+                coll_handle: coll_handle.clone(),
+                count_in_finished,
+            })),
+        })
+    }
+
+    fn audio_ports_ext(&mut self) -> Result<ext::audio_ports::PluginAudioPortsExt, String> {
+        Ok(ext::audio_ports::PluginAudioPortsExt::stereo_out())
+    }
+
+    // --- Parameters ---------------------------------------------------------------------------------
+
+    fn num_params(&mut self) -> u32 {
+        2
+    }
+
+    fn param_info(&mut self, param_index: usize) -> Result<ext::params::ParamInfo, ()> {
+        match param_index {
+            0 => Ok(ext::params::ParamInfo::new(
+                GAIN_PARAM,
+                ParamInfoFlags::default_float(),
+                "gain".into(),
+                String::new(),
+                -90.0,
+                6.0,
+                -6.0,
+            )),
+            1 => Ok(ext::params::ParamInfo::new(
+                ENABLED_PARAM,
+                ParamInfoFlags::default_float() | ParamInfoFlags::IS_STEPPED,
+                "enabled".into(),
+                String::new(),
+                0.0,
+                1.0,
+                0.0,
+            )),
+            _ => Err(()),
+        }
+    }
+
+    fn param_value(&self, param_id: ParamID) -> Result<f64, ()> {
+        match param_id {
+            GAIN_PARAM => Ok(f64::from(self.params.gain.value())),
+            ENABLED_PARAM => Ok(f64::from(self.params.enabled.value())),
+            _ => Err(()),
+        }
+    }
+
+    fn param_value_to_text(&self, param_id: ParamID, value: f64) -> Result<String, ()> {
+        match param_id {
+            GAIN_PARAM => Ok(format!("{:.2} dB", value)),
+            ENABLED_PARAM => Ok(String::from(if value >= 0.5 { "on" } else { "off" })),
+            _ => Err(()),
+        }
+    }
+
+    fn param_text_to_value(&self, param_id: ParamID, text: &str) -> Result<f64, ()> {
+        match param_id {
+            GAIN_PARAM => text.parse().map_err(|_| ()),
+            ENABLED_PARAM => match text {
+                "on" => Ok(1.0),
+                "off" => Ok(0.0),
+                _ => Err(()),
+            },
+            _ => Err(()),
+        }
+    }
+}
+
+/// A short sine burst with a fast exponential decay.
+fn synth_click(freq: f32, frames: usize, sample_rate: f32) -> Vec<f32> {
+    let decay = -5.0 / frames.max(1) as f32;
+    (0..frames)
+        .map(|i| (TAU * freq * i as f32 / sample_rate).sin() * (decay * i as f32).exp())
+        .collect()
+}
+
+struct CountIn {
+    clicks: Owned<Vec<Click>>,
+    len_frames: u64,
+    /// The number of frames since the start of the count-in.
+    frame: u64,
+}
+
+/// The click that is currently sounding.
+#[derive(Clone, Copy)]
+struct Voice {
+    downbeat: bool,
+    /// The number of frames since the start of the click.
+    playhead: u64,
+}
+
+pub struct MetronomePlugAudioThread {
+    params: Params,
+
+    from_handle_rx: Owned<Consumer<ProcessMsg>>,
+
+    clicks: Owned<Vec<Click>>,
+    count_in: Option<CountIn>,
+    count_in_finished: Arc<AtomicBool>,
+
+    downbeat_click: Owned<Vec<f32>>,
+    beat_click: Owned<Vec<f32>>,
+    downbeat_sample: Option<Shared<PcmRAM>>,
+    beat_sample: Option<Shared<PcmRAM>>,
+
+    voice: Option<Voice>,
+
+    sample_buf_l: Owned<Vec<f32>>,
+    sample_buf_r: Owned<Vec<f32>>,
+}
+
+impl MetronomePlugAudioThread {
+    fn poll(&mut self, in_events: &EventBuffer) {
+        for e in in_events.iter() {
+            if let Some(param_value) = e.as_event::<ParamValueEvent>() {
+                match ParamID(param_value.param_id()) {
+                    GAIN_PARAM => self.params.gain.set_value(param_value.value() as f32),
+                    ENABLED_PARAM => self.params.enabled.set_value(param_value.value() as f32),
+                    _ => {}
+                }
+            }
+        }
+
+        while let Ok(msg) = self.from_handle_rx.pop() {
+            match msg {
+                ProcessMsg::SetClicks { clicks } => {
+                    // The old clicks are dropped by the collector, not on the
+                    // audio thread.
+                    self.clicks = clicks;
+                }
+                ProcessMsg::SetSounds { downbeat, beat } => {
+                    self.downbeat_sample = downbeat;
+                    self.beat_sample = beat;
+                    self.voice = None;
+                }
+                ProcessMsg::StartCountIn { clicks, len_frames } => {
+                    self.count_in = Some(CountIn { clicks, len_frames, frame: 0 });
+                }
+                ProcessMsg::CancelCountIn => {
+                    self.count_in = None;
+                }
+            }
+        }
+    }
+
+    fn len_frames(&self, downbeat: bool) -> u64 {
+        match (downbeat, &self.downbeat_sample, &self.beat_sample) {
+            (true, Some(pcm), _) | (false, _, Some(pcm)) => pcm.len_frames() as u64,
+            (true, None, _) => self.downbeat_click.len() as u64,
+            (false, _, None) => self.beat_click.len() as u64,
+        }
+    }
+
+    /// Add the sounding click to the buffers, starting at `offset` frames
+    /// into the buffers.
+    fn render_voice(&mut self, buf_l: &mut [f32], buf_r: &mut [f32], offset: usize) {
+        let Voice { downbeat, playhead } = match self.voice {
+            Some(voice) => voice,
+            None => return,
+        };
+
+        let len = self.len_frames(downbeat);
+        let frames = ((buf_l.len() - offset) as u64).min(len.saturating_sub(playhead)) as usize;
+
+        let sample = if downbeat { &self.downbeat_sample } else { &self.beat_sample };
+        match sample {
+            Some(pcm) => {
+                let sample_buf_l = &mut self.sample_buf_l[0..frames];
+                let sample_buf_r = &mut self.sample_buf_r[0..frames];
+                pcm.fill_stereo_f32(playhead as isize, sample_buf_l, sample_buf_r);
+
+                for i in 0..frames {
+                    buf_l[offset + i] += sample_buf_l[i];
+                    buf_r[offset + i] += sample_buf_r[i];
+                }
+            }
+            None => {
+                let click = if downbeat { &self.downbeat_click } else { &self.beat_click };
+                let click = &click[playhead as usize..playhead as usize + frames];
+
+                for i in 0..frames {
+                    buf_l[offset + i] += click[i];
+                    buf_r[offset + i] += click[i];
+                }
+            }
+        }
+
+        let playhead = playhead + frames as u64;
+        self.voice = if playhead < len { Some(Voice { downbeat, playhead }) } else { None };
+    }
+
+    /// Play the clicks between `start` and `start + frames` in the buffers.
+    fn render_clicks(
+        &mut self,
+        clicks: &[Click],
+        start: u64,
+        buf_l: &mut [f32],
+        buf_r: &mut [f32],
+    ) {
+        let end = start + buf_l.len() as u64;
+        let first = clicks.partition_point(|click| click.frame < start);
+
+        let mut offset = 0;
+        for click in clicks[first..].iter().take_while(|click| click.frame < end) {
+            let click_offset = (click.frame - start) as usize;
+
+            // Play the end of the previous click up to the new one.
+            self.render_voice(&mut buf_l[..click_offset], &mut buf_r[..click_offset], offset);
+
+            self.voice = Some(Voice { downbeat: click.downbeat, playhead: 0 });
+            offset = click_offset;
+        }
+
+        self.render_voice(buf_l, buf_r, offset);
+    }
+}
+
+impl PluginAudioThread for MetronomePlugAudioThread {
+    fn start_processing(&mut self) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn stop_processing(&mut self) {}
+
+    fn process(
+        &mut self,
+        proc_info: &ProcInfo,
+        buffers: &mut ProcBuffers,
+        in_events: &EventBuffer,
+        _out_events: &mut EventBuffer,
+    ) -> ProcessStatus {
+        self.poll(in_events);
+
+        let (mut buf_l, mut buf_r) = buffers.audio_out[0].stereo_f32_mut().unwrap();
+
+        let buf_l_part = &mut buf_l[0..proc_info.frames];
+        let buf_r_part = &mut buf_r[0..proc_info.frames];
+
+        buf_l_part.fill(0.0);
+        buf_r_part.fill(0.0);
+
+        let enabled = self.params.enabled.smoothed(proc_info.frames)[0] >= 0.5;
+        let transport = &proc_info.transport;
+
+        if let Some(mut count_in) = self.count_in.take() {
+            // The count-in is heard even when the metronome is disabled.
+            self.render_clicks(&count_in.clicks, count_in.frame, buf_l_part, buf_r_part);
+
+            count_in.frame += proc_info.frames as u64;
+            if count_in.frame >= count_in.len_frames {
+                self.count_in_finished.store(true, Ordering::Relaxed);
+            } else {
+                self.count_in = Some(count_in);
+            }
+        } else if enabled && transport.is_playing() {
+            let clicks = std::mem::take(&mut *self.clicks);
+            self.render_clicks(&clicks, transport.playhead_frame(), buf_l_part, buf_r_part);
+            *self.clicks = clicks;
+        } else {
+            self.render_voice(buf_l_part, buf_r_part, 0);
+        }
+
+        let gain = self.params.gain.smoothed(proc_info.frames);
+        for i in 0..proc_info.frames {
+            buf_l_part[i] *= gain.values[i];
+            buf_r_part[i] *= gain.values[i];
+        }
+
+        if self.voice.is_none() && self.count_in.is_none() && !(enabled && transport.is_playing()) {
+            return ProcessStatus::Sleep;
+        }
+
+        ProcessStatus::Continue
+    }
+
+    fn param_flush(&mut self, in_events: &EventBuffer, _out_events: &mut EventBuffer) {
+        self.poll(in_events);
+    }
+}
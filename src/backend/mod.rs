@@ -25,6 +25,9 @@
 //! [`Rusty DAW Engine`]: https://github.com/RustyDAW/rusty-daw-engine
 //! [`CLAP`]: https://github.com/free-audio/clap
 
+pub mod disk_stream;
+pub mod metronome_plug;
+pub mod render;
 pub mod resource_loader;
 pub mod sample_browser_plug;
 pub mod system_io;
@@ -0,0 +1,704 @@
+//! Offline rendering (bouncing) of the audio graph to an audio file.
+//!
+//! The engine is activated inside of a stream that is driven by its own
+//! thread, which pulls the engine audio thread as fast as it can instead of
+//! waiting on an audio device.
+//!
+//! When exporting stems, the graph output is made wide enough to hold every
+//! stem next to the full mix, and each stem is written to its own file in the
+//! same pass.
+
+use crossbeam::channel::{self, Receiver};
+use flacenc::bitsink::ByteSink;
+use flacenc::component::{BitRepr, StreamInfo};
+use flacenc::source::{Context, Fill, FrameBuf};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::system_io::{self, SystemIOStreamHandle, ThreadStreamEngine};
+
+/// The number of frames processed at a time while rendering.
+pub const RENDER_BLOCK_FRAMES: u32 = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderFormat {
+    Wav,
+    Flac,
+}
+
+impl RenderFormat {
+    /// Returns the format that matches the extension of `path`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "wav" | "wave" => Some(RenderFormat::Wav),
+            "flac" => Some(RenderFormat::Flac),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            RenderFormat::Wav => "wav",
+            RenderFormat::Flac => "flac",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderBitDepth {
+    Int16,
+    Int24,
+    /// Only supported by WAV.
+    Float32,
+}
+
+impl RenderBitDepth {
+    pub fn bits(&self) -> u16 {
+        match self {
+            RenderBitDepth::Int16 => 16,
+            RenderBitDepth::Int24 => 24,
+            RenderBitDepth::Float32 => 32,
+        }
+    }
+}
+
+/// A stem that is written to its own file when exporting stems.
+#[derive(Debug, Clone)]
+pub struct RenderStem {
+    /// The name of the file without the extension.
+    pub file_name: String,
+
+    /// The signals that are mixed together into this stem, i.e. one for every
+    /// mixer channel in a group.
+    pub inputs: Vec<RenderStemInput>,
+}
+
+/// A signal that is read from its own range of graph output channels and
+/// mixed into a stem.
+#[derive(Debug, Clone)]
+pub struct RenderStemInput {
+    /// The gain applied to each channel of this input (i.e. the faders and pan
+    /// of the mixer channels it passes through when exporting post-fader).
+    pub channel_gains: Vec<f32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderSettings {
+    /// The file to render to, or the directory to write the files to when
+    /// exporting stems.
+    pub path: PathBuf,
+    pub format: RenderFormat,
+    pub bit_depth: RenderBitDepth,
+    pub sample_rate: u32,
+    pub num_channels: u16,
+
+    /// Add triangular dither when converting to an integer bit depth.
+    pub dither: bool,
+
+    /// The number of frames to render, not including the tail.
+    pub num_frames: u64,
+
+    /// The number of extra frames to render after the end of the range so
+    /// that reverb and delay tails are not cut off.
+    pub tail_frames: u64,
+
+    /// The stems to export. If this is empty, then the whole output of the
+    /// graph is rendered to `path`.
+    ///
+    /// Otherwise the first `num_channels` graph output channels still hold the
+    /// full mix, and the inputs of all stems follow in order, each in its own
+    /// `num_channels` graph output channels (see `stem_input_channel()`).
+    pub stems: Vec<RenderStem>,
+}
+
+impl RenderSettings {
+    /// The number of output channels the audio graph needs for this render.
+    pub fn graph_out_channels(&self) -> u16 {
+        let num_inputs: usize = self.stems.iter().map(|stem| stem.inputs.len()).sum();
+        self.num_channels * (num_inputs as u16 + 1)
+    }
+
+    /// The first graph output channel of the `i`th stem input, counting the
+    /// inputs of all stems in order.
+    pub fn stem_input_channel(num_channels: u16, i: usize) -> u16 {
+        (i as u16 + 1) * num_channels
+    }
+
+    /// The path of the file that the given stem is written to.
+    pub fn stem_path(&self, stem: &RenderStem) -> PathBuf {
+        self.path.join(format!("{}.{}", stem.file_name, self.format.extension()))
+    }
+}
+
+#[derive(Debug)]
+pub enum RenderError {
+    Io { path: PathBuf, error: std::io::Error },
+    Wav { path: PathBuf, error: hound::Error },
+    Flac { path: PathBuf, error: String },
+    UnsupportedBitDepth { format: RenderFormat, bit_depth: RenderBitDepth },
+}
+
+impl Error for RenderError {}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::Io { path, error } => {
+                write!(f, "Failed to write rendered audio to {:?}: {}", path, error)
+            }
+            RenderError::Wav { path, error } => {
+                write!(f, "Failed to write WAV file {:?}: {}", path, error)
+            }
+            RenderError::Flac { path, error } => {
+                write!(f, "Failed to write FLAC file {:?}: {}", path, error)
+            }
+            RenderError::UnsupportedBitDepth { format, bit_depth } => {
+                write!(
+                    f,
+                    "{}-bit {:?} is not supported for {} files",
+                    bit_depth.bits(),
+                    bit_depth,
+                    format.extension()
+                )
+            }
+        }
+    }
+}
+
+/// How a render ended.
+#[derive(Debug)]
+pub enum RenderOutcome {
+    Finished { path: PathBuf },
+    Cancelled,
+    Failed(RenderError),
+}
+
+/// Used by the UI to start, monitor, and cancel a render.
+pub struct OfflineRenderHandle {
+    started: Arc<AtomicBool>,
+    cancelled: Arc<AtomicBool>,
+    frames_rendered: Arc<AtomicU64>,
+    total_frames: u64,
+    from_render_rx: Receiver<RenderOutcome>,
+}
+
+impl OfflineRenderHandle {
+    /// Start rendering.
+    ///
+    /// Call this once the audio graph has been restored in the engine and the
+    /// transport has been moved to the start of the range.
+    pub fn start(&self) {
+        self.started.store(true, Ordering::Relaxed);
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// The progress of the render in the range `[0.0, 1.0]`.
+    pub fn progress(&self) -> f32 {
+        if self.total_frames == 0 {
+            return 1.0;
+        }
+        (self.frames_rendered.load(Ordering::Relaxed) as f64 / self.total_frames as f64) as f32
+    }
+
+    /// Returns how the render ended, or `None` if it is still running.
+    pub fn poll(&self) -> Option<RenderOutcome> {
+        self.from_render_rx.try_recv().ok()
+    }
+}
+
+/// Start a stream that renders the output of the engine to a file instead of
+/// to an audio device.
+///
+/// Activate the engine in the returned stream handle, and then call
+/// `OfflineRenderHandle::start()` once the audio graph is ready.
+pub fn spawn_offline_render_stream(
+    settings: RenderSettings,
+    graph_in_channels: u16,
+) -> Result<(SystemIOStreamHandle, OfflineRenderHandle), RenderError> {
+    if settings.format == RenderFormat::Flac && settings.bit_depth == RenderBitDepth::Float32 {
+        return Err(RenderError::UnsupportedBitDepth {
+            format: settings.format,
+            bit_depth: settings.bit_depth,
+        });
+    }
+
+    let outputs = if settings.stems.is_empty() {
+        vec![RenderOutput::new(settings.path.clone(), vec![(0, Vec::new())], &settings)?]
+    } else {
+        std::fs::create_dir_all(&settings.path)
+            .map_err(|error| RenderError::Io { path: settings.path.clone(), error })?;
+
+        let mut outputs = Vec::with_capacity(settings.stems.len());
+        let mut input_i = 0;
+        for stem in settings.stems.iter() {
+            let mut inputs = Vec::with_capacity(stem.inputs.len());
+            for input in stem.inputs.iter() {
+                let first_channel =
+                    RenderSettings::stem_input_channel(settings.num_channels, input_i);
+                inputs.push((usize::from(first_channel), input.channel_gains.clone()));
+                input_i += 1;
+            }
+
+            match RenderOutput::new(settings.stem_path(stem), inputs, &settings) {
+                Ok(output) => outputs.push(output),
+                Err(e) => {
+                    for output in outputs.drain(..) {
+                        output.writer.discard();
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        outputs
+    };
+
+    let started = Arc::new(AtomicBool::new(false));
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let frames_rendered = Arc::new(AtomicU64::new(0));
+    let total_frames = settings.num_frames + settings.tail_frames;
+    let (to_ui_tx, from_render_rx) = channel::bounded::<RenderOutcome>(1);
+
+    log::info!("Starting offline render to {:?}...", &settings.path);
+
+    let stream_handle = {
+        let started = Arc::clone(&started);
+        let cancelled = Arc::clone(&cancelled);
+        let frames_rendered = Arc::clone(&frames_rendered);
+
+        system_io::spawn_thread_stream(
+            settings.sample_rate,
+            RENDER_BLOCK_FRAMES,
+            graph_in_channels,
+            settings.graph_out_channels(),
+            None,
+            move |engine, run| {
+                let outcome = run_render(
+                    engine,
+                    outputs,
+                    &settings,
+                    &run,
+                    &started,
+                    &cancelled,
+                    &frames_rendered,
+                );
+                let _ = to_ui_tx.send(outcome);
+            },
+        )
+    };
+
+    Ok((
+        stream_handle,
+        OfflineRenderHandle { started, cancelled, frames_rendered, total_frames, from_render_rx },
+    ))
+}
+
+/// A file that the sum of one or more ranges of the graph output channels is
+/// written to.
+struct RenderOutput {
+    writer: AudioFileWriter,
+    /// The first graph output channel of every range, and the gain of each of
+    /// its channels. If the gains are empty, then no gain is applied.
+    inputs: Vec<(usize, Vec<f32>)>,
+    buffer: Vec<f32>,
+}
+
+impl RenderOutput {
+    fn new(
+        path: PathBuf,
+        inputs: Vec<(usize, Vec<f32>)>,
+        settings: &RenderSettings,
+    ) -> Result<Self, RenderError> {
+        Ok(Self {
+            writer: AudioFileWriter::new(path, settings)?,
+            inputs,
+            buffer: vec![0.0; RENDER_BLOCK_FRAMES as usize * usize::from(settings.num_channels)],
+        })
+    }
+
+    /// Mix this output's channels out of an interleaved block of the graph
+    /// output and write them to the file.
+    fn write(
+        &mut self,
+        block: &[f32],
+        graph_out_channels: usize,
+        num_channels: usize,
+        ditherer: Option<&mut Ditherer>,
+    ) -> Result<(), RenderError> {
+        if graph_out_channels == num_channels
+            && matches!(self.inputs.as_slice(), [(0, gains)] if gains.is_empty())
+        {
+            return self.writer.write(block, ditherer);
+        }
+
+        let frames = block.len() / graph_out_channels;
+        let buffer = &mut self.buffer[0..frames * num_channels];
+        buffer.fill(0.0);
+
+        for (first_channel, channel_gains) in self.inputs.iter() {
+            for (out_frame, in_frame) in
+                buffer.chunks_exact_mut(num_channels).zip(block.chunks_exact(graph_out_channels))
+            {
+                let in_frame = &in_frame[*first_channel..*first_channel + num_channels];
+                for (ch, (out_s, in_s)) in out_frame.iter_mut().zip(in_frame.iter()).enumerate() {
+                    *out_s += *in_s * channel_gains.get(ch).copied().unwrap_or(1.0);
+                }
+            }
+        }
+
+        self.writer.write(buffer, ditherer)
+    }
+}
+
+fn discard_outputs(outputs: Vec<RenderOutput>) {
+    for output in outputs {
+        output.writer.discard();
+    }
+}
+
+fn run_render(
+    mut engine: ThreadStreamEngine,
+    mut outputs: Vec<RenderOutput>,
+    settings: &RenderSettings,
+    run: &AtomicBool,
+    started: &AtomicBool,
+    cancelled: &AtomicBool,
+    frames_rendered: &AtomicU64,
+) -> RenderOutcome {
+    let num_channels = usize::from(settings.num_channels);
+    let graph_out_channels = usize::from(settings.graph_out_channels());
+    let total_frames = settings.num_frames + settings.tail_frames;
+
+    let mut audio_buffer = vec![0.0; RENDER_BLOCK_FRAMES as usize * graph_out_channels];
+    let mut ditherer = if settings.dither { Some(Ditherer::default()) } else { None };
+
+    let mut frames_done: u64 = 0;
+    while frames_done < total_frames {
+        if cancelled.load(Ordering::Relaxed) || !run.load(Ordering::Relaxed) {
+            discard_outputs(outputs);
+            log::info!("Offline render cancelled");
+            return RenderOutcome::Cancelled;
+        }
+
+        if !started.load(Ordering::Relaxed) {
+            // Don't process anything until the audio graph is ready, so that the
+            // first block starts exactly where the transport was moved to.
+            std::thread::sleep(Duration::from_millis(1));
+            continue;
+        }
+
+        let frames = (total_frames - frames_done).min(u64::from(RENDER_BLOCK_FRAMES)) as usize;
+        let block = &mut audio_buffer[0..frames * graph_out_channels];
+
+        engine.process(graph_out_channels, block);
+
+        let res = outputs.iter_mut().try_for_each(|output| {
+            output.write(block, graph_out_channels, num_channels, ditherer.as_mut())
+        });
+        if let Err(e) = res {
+            discard_outputs(outputs);
+            return RenderOutcome::Failed(e);
+        }
+
+        frames_done += frames as u64;
+        frames_rendered.store(frames_done, Ordering::Relaxed);
+    }
+
+    let mut result = Ok(());
+    for output in outputs {
+        if result.is_ok() {
+            result = output.writer.finish();
+        } else {
+            output.writer.discard();
+        }
+    }
+
+    match result {
+        Ok(()) => {
+            log::info!("Finished offline render to {:?}", &settings.path);
+            RenderOutcome::Finished { path: settings.path.clone() }
+        }
+        Err(e) => RenderOutcome::Failed(e),
+    }
+}
+
+/// Triangular (TPDF) dither with an amplitude of one least significant bit.
+#[derive(Default)]
+struct Ditherer {
+    rng_state: u32,
+}
+
+impl Ditherer {
+    fn next_random(&mut self) -> f32 {
+        // Xorshift
+        if self.rng_state == 0 {
+            self.rng_state = 0x9E37_79B9;
+        }
+        self.rng_state ^= self.rng_state << 13;
+        self.rng_state ^= self.rng_state >> 17;
+        self.rng_state ^= self.rng_state << 5;
+        self.rng_state as f32 / u32::MAX as f32
+    }
+
+    /// Returns the dither noise, in units of the least significant bit.
+    fn noise(&mut self) -> f32 {
+        self.next_random() - self.next_random()
+    }
+}
+
+enum AudioFileWriter {
+    Wav { path: PathBuf, writer: hound::WavWriter<BufWriter<File>>, bit_depth: RenderBitDepth },
+    Flac(Box<FlacWriter>),
+}
+
+impl AudioFileWriter {
+    fn new(path: PathBuf, settings: &RenderSettings) -> Result<Self, RenderError> {
+        match settings.format {
+            RenderFormat::Wav => {
+                let spec = hound::WavSpec {
+                    channels: settings.num_channels,
+                    sample_rate: settings.sample_rate,
+                    bits_per_sample: settings.bit_depth.bits(),
+                    sample_format: if settings.bit_depth == RenderBitDepth::Float32 {
+                        hound::SampleFormat::Float
+                    } else {
+                        hound::SampleFormat::Int
+                    },
+                };
+
+                let writer = hound::WavWriter::create(&path, spec)
+                    .map_err(|error| RenderError::Wav { path: path.clone(), error })?;
+
+                Ok(AudioFileWriter::Wav { path, writer, bit_depth: settings.bit_depth })
+            }
+            RenderFormat::Flac => {
+                Ok(AudioFileWriter::Flac(Box::new(FlacWriter::new(path, settings)?)))
+            }
+        }
+    }
+
+    fn write(
+        &mut self,
+        block: &[f32],
+        mut ditherer: Option<&mut Ditherer>,
+    ) -> Result<(), RenderError> {
+        match self {
+            AudioFileWriter::Wav { path, writer, bit_depth } => {
+                if *bit_depth == RenderBitDepth::Float32 {
+                    for s in block.iter() {
+                        writer
+                            .write_sample(*s)
+                            .map_err(|error| RenderError::Wav { path: path.clone(), error })?;
+                    }
+                } else {
+                    for s in block.iter() {
+                        let s = to_int(*s, *bit_depth, ditherer.as_deref_mut());
+                        writer
+                            .write_sample(s)
+                            .map_err(|error| RenderError::Wav { path: path.clone(), error })?;
+                    }
+                }
+            }
+            AudioFileWriter::Flac(writer) => {
+                let bit_depth = writer.bit_depth;
+                for s in block.iter() {
+                    writer.push(to_int(*s, bit_depth, ditherer.as_deref_mut()))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), RenderError> {
+        match self {
+            AudioFileWriter::Wav { path, writer, .. } => {
+                writer.finalize().map_err(|error| RenderError::Wav { path, error })
+            }
+            AudioFileWriter::Flac(writer) => writer.finish(),
+        }
+    }
+
+    /// Stop writing and remove the partially written file.
+    fn discard(self) {
+        let path = match self {
+            AudioFileWriter::Wav { path, writer, .. } => {
+                let _ = writer.finalize();
+                path
+            }
+            AudioFileWriter::Flac(writer) => writer.path,
+        };
+
+        if let Err(e) = std::fs::remove_file(&path) {
+            log::warn!("Failed to remove partially rendered file {:?}: {}", &path, e);
+        }
+    }
+}
+
+/// Encodes a FLAC file one block at a time, so that the whole render never
+/// has to be kept in memory.
+struct FlacWriter {
+    path: PathBuf,
+    file: BufWriter<File>,
+    bit_depth: RenderBitDepth,
+    num_channels: usize,
+
+    config: flacenc::config::Encoder,
+    stream_info: StreamInfo,
+    frame_buf: FrameBuf,
+    context: Context,
+    /// The number of frames in every block but the last.
+    block_size: usize,
+    /// The number of the next FLAC frame.
+    frame_number: usize,
+
+    /// The interleaved samples of the block that is being filled.
+    block: Vec<i32>,
+    /// Reused to encode each frame.
+    sink: ByteSink,
+}
+
+impl FlacWriter {
+    fn new(path: PathBuf, settings: &RenderSettings) -> Result<Self, RenderError> {
+        let file =
+            File::create(&path).map_err(|error| RenderError::Io { path: path.clone(), error })?;
+
+        let config = flacenc::config::Encoder::default();
+        let block_size = config.block_sizes[0];
+        let num_channels = usize::from(settings.num_channels);
+        let bits_per_sample = usize::from(settings.bit_depth.bits());
+
+        let mut writer = Self {
+            path,
+            file: BufWriter::new(file),
+            bit_depth: settings.bit_depth,
+            num_channels,
+            stream_info: StreamInfo::new(
+                settings.sample_rate as usize,
+                num_channels,
+                bits_per_sample,
+            ),
+            frame_buf: FrameBuf::with_size(num_channels, block_size),
+            context: Context::new(bits_per_sample, num_channels, block_size),
+            block_size,
+            frame_number: 0,
+            block: Vec::with_capacity(block_size * num_channels),
+            sink: ByteSink::new(),
+            config,
+        };
+
+        // The stream info is written again once the whole stream is known.
+        writer.write_header()?;
+
+        Ok(writer)
+    }
+
+    fn push(&mut self, s: i32) -> Result<(), RenderError> {
+        self.block.push(s);
+
+        if self.block.len() == self.block_size * self.num_channels {
+            self.context.fill_interleaved(&self.block).map_err(|e| self.flac_err(e))?;
+            self.frame_buf.fill_interleaved(&self.block).map_err(|e| self.flac_err(e))?;
+            self.block.clear();
+
+            self.write_frame()?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(), RenderError> {
+        if self.block.is_empty() {
+            self.stream_info.set_md5_digest(&self.context.md5_digest());
+        } else {
+            // The last frame is shorter than the others. The MD5 digest of the
+            // encoder assumes that every frame is full, so it is left unset.
+            self.frame_buf.resize(self.block.len() / self.num_channels);
+            self.frame_buf.fill_interleaved(&self.block).map_err(|e| self.flac_err(e))?;
+            self.block.clear();
+
+            self.write_frame()?;
+        }
+
+        self.file.seek(SeekFrom::Start(0)).map_err(|error| self.io_err(error))?;
+        self.write_header()?;
+        self.file.flush().map_err(|error| self.io_err(error))
+    }
+
+    /// Encode the samples in `frame_buf` and write them to the file.
+    fn write_frame(&mut self) -> Result<(), RenderError> {
+        let frame = flacenc::encode_fixed_size_frame(
+            &self.config,
+            &self.frame_buf,
+            self.frame_number,
+            &self.stream_info,
+        )
+        .map_err(|e| self.flac_err(e))?;
+        self.frame_number += 1;
+
+        self.stream_info.update_frame_info(&frame);
+
+        self.sink.clear();
+        frame.write(&mut self.sink).map_err(|e| self.flac_err(e))?;
+        self.file.write_all(self.sink.as_slice()).map_err(|error| self.io_err(error))
+    }
+
+    /// Write the `fLaC` marker and the stream info, which is the only
+    /// metadata block.
+    fn write_header(&mut self) -> Result<(), RenderError> {
+        self.sink.clear();
+        self.stream_info.write(&mut self.sink).map_err(|e| self.flac_err(e))?;
+        let mut stream_info = self.sink.as_slice().to_vec();
+
+        // The minimum and maximum block sizes must not include the last
+        // frame, which is shorter than the others. Every other frame has the
+        // same size.
+        let block_size = (self.block_size as u16).to_be_bytes();
+        stream_info[0..2].copy_from_slice(&block_size);
+        stream_info[2..4].copy_from_slice(&block_size);
+
+        let len = stream_info.len() as u32;
+        // The top bit marks the last metadata block. The stream info is
+        // block type 0.
+        let block_header = [0x80, (len >> 16) as u8, (len >> 8) as u8, len as u8];
+
+        let mut header = b"fLaC".to_vec();
+        header.extend_from_slice(&block_header);
+        header.extend_from_slice(&stream_info);
+
+        self.file.write_all(&header).map_err(|error| self.io_err(error))
+    }
+
+    fn flac_err(&self, e: impl fmt::Debug) -> RenderError {
+        RenderError::Flac { path: self.path.clone(), error: format!("{:?}", e) }
+    }
+
+    fn io_err(&self, error: std::io::Error) -> RenderError {
+        RenderError::Io { path: self.path.clone(), error }
+    }
+}
+
+/// Convert a sample to an integer at the given bit depth, with optional
+/// dither.
+fn to_int(s: f32, bit_depth: RenderBitDepth, ditherer: Option<&mut Ditherer>) -> i32 {
+    let max = match bit_depth {
+        RenderBitDepth::Int16 => f32::from(i16::MAX),
+        _ => 8_388_607.0,
+    };
+
+    let mut scaled = s.clamp(-1.0, 1.0) * max;
+    if let Some(ditherer) = ditherer {
+        scaled += ditherer.noise();
+    }
+
+    scaled.round().clamp(-max - 1.0, max) as i32
+}
@@ -1,28 +1,203 @@
 use basedrop::{Collector, Shared};
+use crossbeam::channel::{self, Receiver, Sender};
 use meadowlark_core_types::time::SampleRate;
 use pcm_loader::{error::PcmLoadError, PcmLoader, PcmRAM, PcmRAMType, ResampleQuality};
-use std::path::PathBuf;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
 
+use super::disk_stream::{DiskStream, DiskStreamError, DEFAULT_STREAM_THRESHOLD_BYTES};
 use crate::util::TwoXHashMap;
 
-#[derive(Default, Debug, Clone, PartialEq, Hash, Eq)]
+/// The maximum number of threads used to load resources in the background.
+const MAX_WORKER_THREADS: usize = 4;
+
+/// The default amount of memory that loaded resources can take up before the
+/// least recently used resources are evicted.
+pub const DEFAULT_MEMORY_BUDGET_BYTES: usize = 1024 * 1024 * 1024;
+
+/// The default time between each call to `ResourceLoader::collect()` from
+/// `ResourceLoader::collect_if_due()`.
+pub const DEFAULT_COLLECT_INTERVAL: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone)]
 pub struct PcmKey {
     pub path: PathBuf,
 
     pub resample_to_project_sr: bool,
     pub resample_quality: ResampleQuality,
-    /* TODO
+
     /// The amount of doppler stretching to apply.
     ///
+    /// This is the playback-rate ratio the resource is resampled to when it
+    /// is loaded (i.e. `2.0` plays back twice as fast and an octave higher,
+    /// `0.5` plays back twice as slow and an octave lower). Each ratio is
+    /// cached as its own resource.
+    ///
     /// By default this is `1.0` (no doppler stretching).
-    //pub doppler_stretch_ratio: f64,
-     */
+    pub doppler_stretch_ratio: f64,
+}
+
+impl Default for PcmKey {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::new(),
+            resample_to_project_sr: false,
+            resample_quality: ResampleQuality::default(),
+            doppler_stretch_ratio: 1.0,
+        }
+    }
+}
+
+impl PartialEq for PcmKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path
+            && self.resample_to_project_sr == other.resample_to_project_sr
+            && self.resample_quality == other.resample_quality
+            && self.doppler_stretch_ratio.to_bits() == other.doppler_stretch_ratio.to_bits()
+    }
+}
+
+impl Eq for PcmKey {}
+
+impl Hash for PcmKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.path.hash(state);
+        self.resample_to_project_sr.hash(state);
+        self.resample_quality.hash(state);
+        self.doppler_stretch_ratio.to_bits().hash(state);
+    }
+}
+
+/// A handle to a resource that was requested with `ResourceLoader::request_pcm()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PcmLoadHandle(u64);
+
+/// The events sent by `ResourceLoader::poll()`.
+pub enum ResourceLoaderEvent {
+    /// A worker thread has started decoding the resource. A `Loaded` or
+    /// `LoadFailed` event with the same handle follows once it is done.
+    ///
+    /// `pcm-loader` decodes a file in a single call, so there is no progress
+    /// to report within a file. Use `file_size` to decide whether the load
+    /// will take long enough to show a loading indicator.
+    ///
+    /// This is not sent for requests that are served by an already loaded
+    /// resource.
+    LoadStarted { handle: PcmLoadHandle, path: PathBuf, file_size: u64 },
+    /// The resource has finished loading.
+    Loaded { handle: PcmLoadHandle, pcm: Shared<PcmRAM> },
+    /// The resource could not be loaded.
+    LoadFailed { handle: PcmLoadHandle, path: PathBuf, error: String },
+    /// A stream requested with `request_stream()` is ready to be played.
+    StreamOpened { handle: PcmLoadHandle, stream: DiskStream },
+    /// A stream requested with `request_stream()` could not be opened.
+    ///
+    /// If the error is `DiskStreamError::NeedsResample`, then `should_stream()`
+    /// returns `false` for the file from now on, and it should be requested
+    /// with `request_pcm()` instead.
+    StreamFailed { handle: PcmLoadHandle, error: DiskStreamError },
+}
+
+/// Statistics about the resources held by a `ResourceLoader`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ResourceLoaderStats {
+    /// The total size of all of the loaded resources in bytes.
+    pub bytes_loaded: usize,
+    /// The number of loaded resources.
+    pub num_loaded: usize,
+    /// The number of requests that were served by an already loaded resource.
+    pub hits: u64,
+    /// The number of requests that required decoding the file.
+    pub misses: u64,
+    /// The number of requests that were served by a resource that was
+    /// already being loaded for an earlier request.
+    pub joined: u64,
+    /// The number of unused resources that were dropped to stay within the
+    /// memory budget.
+    pub evictions: u64,
+}
+
+struct CachedPcm {
+    pcm: Shared<PcmRAM>,
+    size_bytes: usize,
+    /// The value of `ResourceLoader::access_counter` the last time this
+    /// resource was requested.
+    last_used: u64,
+}
+
+enum LoadJob {
+    Decode { key: PcmKey, project_sr: u32 },
+    OpenStream { handle: PcmLoadHandle, path: PathBuf, start_frame: u64, project_sr: u32 },
+}
+
+/// The messages sent back by the worker threads. Each one carries the project
+/// sample rate of the job it belongs to, since the sample rate can change
+/// while the job is running.
+enum WorkerMsg {
+    Started {
+        key: PcmKey,
+        project_sr: u32,
+        file_size: u64,
+    },
+    Finished {
+        key: PcmKey,
+        project_sr: u32,
+        res: Result<PcmRAM, PcmLoadError>,
+    },
+    StreamOpened {
+        handle: PcmLoadHandle,
+        path: PathBuf,
+        start_frame: u64,
+        project_sr: u32,
+        res: Result<DiskStream, DiskStreamError>,
+    },
 }
 
 pub struct ResourceLoader {
     pcm_loader: PcmLoader,
 
-    loaded: TwoXHashMap<PcmKey, Shared<PcmRAM>>,
+    to_workers_tx: Sender<LoadJob>,
+    from_workers_rx: Receiver<WorkerMsg>,
+
+    /// The handles waiting on each resource that is currently being loaded
+    /// in the background.
+    in_flight: TwoXHashMap<PcmKey, Vec<PcmLoadHandle>>,
+
+    /// Events for requests that could be completed right away.
+    ready_events: Vec<ResourceLoaderEvent>,
+
+    next_handle_id: u64,
+
+    loaded: TwoXHashMap<PcmKey, CachedPcm>,
+
+    /// Resources that are no longer used by anything are kept around (so
+    /// that i.e. recently auditioned samples in the browser can be replayed
+    /// instantly) until the total size of the loaded resources exceeds this
+    /// budget. Then the least recently used ones are dropped first.
+    ///
+    /// Resources that are still in use are never dropped, so the total can
+    /// still go above this budget if the project itself needs more memory.
+    memory_budget_bytes: usize,
+
+    /// Files at least this large are streamed from disk instead of being
+    /// loaded into RAM.
+    stream_threshold_bytes: u64,
+
+    /// The files that cannot be streamed because they are not at the project
+    /// sample rate.
+    unstreamable: Vec<PathBuf>,
+
+    access_counter: u64,
+    stats: ResourceLoaderStats,
+
+    collect_interval: Duration,
+    last_collect: Instant,
 
     /// The resource to send when the resource could not be loaded.
     empty_pcm: Shared<PcmRAM>,
@@ -30,6 +205,9 @@ pub struct ResourceLoader {
     project_sr: SampleRate,
 
     collector: Collector,
+
+    /// The files that failed to load because they do not exist.
+    missing_files: Vec<PathBuf>,
 }
 
 impl ResourceLoader {
@@ -41,57 +219,634 @@ impl ResourceLoader {
             PcmRAM::new(PcmRAMType::F32(vec![Vec::new()]), project_sample_rate.as_u32()),
         );
 
+        let (to_workers_tx, from_ui_rx) = channel::unbounded::<LoadJob>();
+        let (to_ui_tx, from_workers_rx) = channel::unbounded::<WorkerMsg>();
+
+        let num_workers = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            .min(MAX_WORKER_THREADS);
+        for _ in 0..num_workers {
+            let from_ui_rx = from_ui_rx.clone();
+            let to_ui_tx = to_ui_tx.clone();
+            std::thread::spawn(move || run_worker(from_ui_rx, to_ui_tx));
+        }
+
         Self {
             pcm_loader: PcmLoader::new(),
+            to_workers_tx,
+            from_workers_rx,
+            in_flight: Default::default(),
+            ready_events: Vec::new(),
+            next_handle_id: 0,
             loaded: Default::default(),
+            memory_budget_bytes: DEFAULT_MEMORY_BUDGET_BYTES,
+            stream_threshold_bytes: DEFAULT_STREAM_THRESHOLD_BYTES,
+            unstreamable: Vec::new(),
+            access_counter: 0,
+            stats: ResourceLoaderStats::default(),
+            collect_interval: DEFAULT_COLLECT_INTERVAL,
+            last_collect: Instant::now(),
             empty_pcm,
             project_sr: project_sample_rate,
             collector,
+            missing_files: Vec::new(),
         }
     }
 
+    /// Load a resource on the calling thread.
+    ///
+    /// Prefer `request_pcm()` in the UI, since decoding a long file can take
+    /// a while.
     pub fn load_pcm(&mut self, key: &PcmKey) -> (Shared<PcmRAM>, Result<(), PcmLoadError>) {
         match self.try_load(key) {
             Ok(pcm) => (pcm, Ok(())),
             Err(e) => {
                 log::error!("{}", e);
 
+                self.check_missing(&key.path);
+
                 // Send an empty PCM resource instead.
                 (Shared::clone(&self.empty_pcm), Err(e))
             }
         }
     }
 
+    /// Load a resource in the background.
+    ///
+    /// This returns immediately. A `Loaded` or `LoadFailed` event with the
+    /// returned handle will be sent from `poll()` once the resource is ready.
+    pub fn request_pcm(&mut self, key: &PcmKey) -> PcmLoadHandle {
+        let handle = PcmLoadHandle(self.next_handle_id);
+        self.next_handle_id += 1;
+
+        if let Some(pcm) = self.get_loaded(key) {
+            log::debug!("PCM file already loaded");
+            self.ready_events.push(ResourceLoaderEvent::Loaded { handle, pcm });
+            return handle;
+        }
+
+        if let Some(handles) = self.in_flight.get_mut(key) {
+            // The resource is already being loaded.
+            handles.push(handle);
+            self.stats.joined += 1;
+            return handle;
+        }
+
+        self.stats.misses += 1;
+
+        log::trace!("Requesting PCM file: {:?}", &key.path);
+
+        self.in_flight.insert(key.clone(), vec![handle]);
+        self.to_workers_tx
+            .send(LoadJob::Decode { key: key.clone(), project_sr: self.project_sr.as_u32() })
+            .unwrap();
+
+        handle
+    }
+
+    /// Open a stream of the resource from disk in the background, starting at
+    /// `start_frame`.
+    ///
+    /// This returns immediately. A `StreamOpened` or `StreamFailed` event with
+    /// the returned handle will be sent from `poll()` once the audio at
+    /// `start_frame` is ready to be played. Unlike resources loaded into RAM,
+    /// every request opens a new stream.
+    pub fn request_stream(&mut self, key: &PcmKey, start_frame: u64) -> PcmLoadHandle {
+        let handle = PcmLoadHandle(self.next_handle_id);
+        self.next_handle_id += 1;
+
+        log::trace!("Requesting stream: {:?}", &key.path);
+
+        self.to_workers_tx
+            .send(LoadJob::OpenStream {
+                handle,
+                path: key.path.clone(),
+                start_frame,
+                project_sr: self.project_sr.as_u32(),
+            })
+            .unwrap();
+
+        handle
+    }
+
+    /// Collect the events from resources that were requested with
+    /// `request_pcm()` and `request_stream()`.
+    pub fn poll(&mut self) -> Vec<ResourceLoaderEvent> {
+        let mut events = std::mem::take(&mut self.ready_events);
+
+        while let Ok(msg) = self.from_workers_rx.try_recv() {
+            match msg {
+                WorkerMsg::Started { key, project_sr, file_size } => {
+                    if key.resample_to_project_sr && project_sr != self.project_sr.as_u32() {
+                        continue;
+                    }
+
+                    if let Some(handles) = self.in_flight.get(&key) {
+                        for handle in handles.iter() {
+                            events.push(ResourceLoaderEvent::LoadStarted {
+                                handle: *handle,
+                                path: key.path.clone(),
+                                file_size,
+                            });
+                        }
+                    }
+                }
+                WorkerMsg::Finished { key, project_sr, res } => {
+                    if key.resample_to_project_sr && project_sr != self.project_sr.as_u32() {
+                        // The project sample rate changed while the resource
+                        // was being loaded, so load it again at the new rate.
+                        if self.in_flight.contains_key(&key) {
+                            log::debug!("Reloading PCM file at the new sample rate");
+                            self.to_workers_tx
+                                .send(LoadJob::Decode { key, project_sr: self.project_sr.as_u32() })
+                                .unwrap();
+                        }
+                        continue;
+                    }
+
+                    let handles = self.in_flight.remove(&key).unwrap_or_default();
+
+                    match res {
+                        Ok(pcm) => {
+                            log::trace!("Successfully loaded PCM file");
+
+                            let pcm = self.insert_loaded(key, pcm);
+
+                            for handle in handles {
+                                events.push(ResourceLoaderEvent::Loaded {
+                                    handle,
+                                    pcm: Shared::clone(&pcm),
+                                });
+                            }
+                        }
+                        Err(e) => {
+                            log::error!("{}", e);
+
+                            self.check_missing(&key.path);
+
+                            let error = e.to_string();
+                            for handle in handles {
+                                events.push(ResourceLoaderEvent::LoadFailed {
+                                    handle,
+                                    path: key.path.clone(),
+                                    error: error.clone(),
+                                });
+                            }
+                        }
+                    }
+                }
+                WorkerMsg::StreamOpened { handle, path, start_frame, project_sr, res } => {
+                    if project_sr != self.project_sr.as_u32() {
+                        // Streams are not resampled, so a stream opened at the
+                        // old sample rate cannot be used.
+                        self.to_workers_tx
+                            .send(LoadJob::OpenStream {
+                                handle,
+                                path,
+                                start_frame,
+                                project_sr: self.project_sr.as_u32(),
+                            })
+                            .unwrap();
+                        continue;
+                    }
+
+                    match res {
+                        Ok(stream) => {
+                            events.push(ResourceLoaderEvent::StreamOpened { handle, stream })
+                        }
+                        Err(error) => {
+                            if let DiskStreamError::NeedsResample { path, .. } = &error {
+                                log::debug!("{}", &error);
+                                if !self.unstreamable.contains(path) {
+                                    self.unstreamable.push(path.clone());
+                                }
+                            } else {
+                                log::error!("{}", &error);
+                                if let DiskStreamError::Open { path, .. } = &error {
+                                    self.check_missing(path);
+                                }
+                            }
+
+                            events.push(ResourceLoaderEvent::StreamFailed { handle, error });
+                        }
+                    }
+                }
+            }
+        }
+
+        events
+    }
+
+    fn check_missing(&mut self, path: &PathBuf) {
+        if !path.exists() && !self.missing_files.contains(path) {
+            self.missing_files.push(path.clone());
+        }
+    }
+
     fn try_load(&mut self, key: &PcmKey) -> Result<Shared<PcmRAM>, PcmLoadError> {
         log::trace!("Loading PCM file: {:?}", &key.path);
 
-        if let Some(pcm) = self.loaded.get(key) {
+        if let Some(pcm) = self.get_loaded(key) {
             // Resource is already loaded.
             log::debug!("PCM file already loaded");
-            return Ok(Shared::clone(pcm));
+            return Ok(pcm);
         }
 
-        let target_sample_rate =
-            if key.resample_to_project_sr { Some(self.project_sr.as_u32()) } else { None };
+        self.stats.misses += 1;
 
-        let pcm =
-            self.pcm_loader.load(&key.path, target_sample_rate, key.resample_quality, None)?;
+        let pcm = decode(&mut self.pcm_loader, key, self.project_sr.as_u32())?;
 
-        let pcm = Shared::new(&self.collector.handle(), pcm);
-
-        self.loaded.insert(key.to_owned(), Shared::clone(&pcm));
+        let pcm = self.insert_loaded(key.to_owned(), pcm);
 
         log::trace!("Successfully loaded PCM file");
 
         Ok(pcm)
     }
 
-    /// Drop all of the loaded resources that are no longer being used.
+    /// Returns the loaded resource for `key` (if it is loaded), and marks it as
+    /// the most recently used resource.
+    pub fn get_loaded(&mut self, key: &PcmKey) -> Option<Shared<PcmRAM>> {
+        let cached = self.loaded.get_mut(key)?;
+
+        self.access_counter += 1;
+        cached.last_used = self.access_counter;
+        self.stats.hits += 1;
+
+        Some(Shared::clone(&cached.pcm))
+    }
+
+    fn insert_loaded(&mut self, key: PcmKey, pcm: PcmRAM) -> Shared<PcmRAM> {
+        let size_bytes = pcm_size_bytes(&pcm);
+        let pcm = Shared::new(&self.collector.handle(), pcm);
+
+        self.access_counter += 1;
+        if let Some(old) = self.loaded.insert(
+            key,
+            CachedPcm { pcm: Shared::clone(&pcm), size_bytes, last_used: self.access_counter },
+        ) {
+            self.stats.bytes_loaded -= old.size_bytes;
+            self.stats.num_loaded -= 1;
+        }
+        self.stats.bytes_loaded += size_bytes;
+        self.stats.num_loaded += 1;
+
+        self.evict_to_budget();
+
+        pcm
+    }
+
+    /// Drop the least recently used resources that are no longer in use until
+    /// the loaded resources fit within the memory budget.
+    fn evict_to_budget(&mut self) {
+        if self.stats.bytes_loaded <= self.memory_budget_bytes {
+            return;
+        }
+
+        let mut unused: Vec<(u64, PcmKey)> = self
+            .loaded
+            .iter_mut()
+            .filter(|(_, cached)| Shared::get_mut(&mut cached.pcm).is_some())
+            .map(|(key, cached)| (cached.last_used, key.clone()))
+            .collect();
+        unused.sort_unstable_by_key(|(last_used, _)| *last_used);
+
+        for (_, key) in unused.iter() {
+            if self.stats.bytes_loaded <= self.memory_budget_bytes {
+                break;
+            }
+
+            if let Some(cached) = self.loaded.remove(key) {
+                log::trace!("Evicting PCM file: {:?}", &key.path);
+
+                self.stats.bytes_loaded -= cached.size_bytes;
+                self.stats.num_loaded -= 1;
+                self.stats.evictions += 1;
+            }
+        }
+
+        if self.stats.bytes_loaded > self.memory_budget_bytes {
+            log::debug!(
+                "Resources in use take up {} bytes, which is over the memory budget of {} bytes",
+                self.stats.bytes_loaded,
+                self.memory_budget_bytes
+            );
+        }
+    }
+
+    /// Set the amount of memory that loaded resources can take up before the
+    /// least recently used resources that are no longer in use are dropped.
+    pub fn set_memory_budget(&mut self, bytes: usize) {
+        self.memory_budget_bytes = bytes;
+        self.evict_to_budget();
+    }
+
+    pub fn memory_budget(&self) -> usize {
+        self.memory_budget_bytes
+    }
+
+    pub fn stats(&self) -> ResourceLoaderStats {
+        self.stats
+    }
+
+    /// Take the list of files that failed to load because they do not exist.
+    pub fn take_missing_files(&mut self) -> Vec<PathBuf> {
+        std::mem::take(&mut self.missing_files)
+    }
+
+    /// Drop the least recently used resources that are no longer being used
+    /// if the memory budget has been exceeded, and free the memory of any
+    /// dropped resources.
     pub fn collect(&mut self) {
-        // If no other extant Shared pointers to the resource exists, then
-        // remove that entry.
-        self.loaded.retain(|_, pcm| Shared::get_mut(pcm).is_none());
+        self.last_collect = Instant::now();
+
+        self.evict_to_budget();
 
         self.collector.collect();
     }
+
+    /// Call `collect()` if the collect interval has elapsed since the last
+    /// collection.
+    ///
+    /// This is cheap enough to call on every poll of the UI, no matter how many
+    /// resources are loaded.
+    pub fn collect_if_due(&mut self) {
+        if self.last_collect.elapsed() >= self.collect_interval {
+            self.collect();
+        }
+    }
+
+    /// Set the sample rate resources are resampled to.
+    ///
+    /// Any loaded resources that were resampled to the old sample rate are
+    /// dropped from the cache, and will be loaded again the next time they are
+    /// requested. Resources and streams that are still being loaded at the old
+    /// sample rate are loaded again at the new one before they are sent from
+    /// `poll()`.
+    pub fn set_project_sample_rate(&mut self, sample_rate: SampleRate) {
+        if sample_rate.as_u32() == self.project_sr.as_u32() {
+            return;
+        }
+
+        self.project_sr = sample_rate;
+        self.unstreamable.clear();
+
+        let stats = &mut self.stats;
+        self.loaded.retain(|key, cached| {
+            if key.resample_to_project_sr {
+                stats.bytes_loaded -= cached.size_bytes;
+                stats.num_loaded -= 1;
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// Set the size at which files are streamed from disk instead of being
+    /// loaded into RAM.
+    pub fn set_stream_threshold(&mut self, bytes: u64) {
+        self.stream_threshold_bytes = bytes;
+    }
+
+    pub fn stream_threshold(&self) -> u64 {
+        self.stream_threshold_bytes
+    }
+
+    /// Returns `true` if the resource should be streamed from disk with
+    /// `request_stream()` instead of being loaded into RAM.
+    ///
+    /// Resources with doppler stretching are always loaded into RAM, since
+    /// streams are not resampled. The same goes for files that already failed
+    /// to stream because they are not at the project sample rate.
+    pub fn should_stream(&self, key: &PcmKey) -> bool {
+        if key.doppler_stretch_ratio != 1.0 || self.unstreamable.contains(&key.path) {
+            return false;
+        }
+
+        std::fs::metadata(&key.path)
+            .map(|m| m.len() >= self.stream_threshold_bytes)
+            .unwrap_or(false)
+    }
+
+    /// The handle to the collector that frees resources which are no longer
+    /// used by the audio thread.
+    pub fn collector_handle(&self) -> basedrop::Handle {
+        self.collector.handle()
+    }
+
+    /// Set the time between each collection in `collect_if_due()`.
+    pub fn set_collect_interval(&mut self, interval: Duration) {
+        self.collect_interval = interval;
+    }
+}
+
+fn run_worker(from_ui_rx: Receiver<LoadJob>, to_ui_tx: Sender<WorkerMsg>) {
+    let mut pcm_loader = PcmLoader::new();
+
+    // This thread exits once the `ResourceLoader` is dropped.
+    for job in from_ui_rx.iter() {
+        let msg = match job {
+            LoadJob::Decode { key, project_sr } => {
+                let file_size = std::fs::metadata(&key.path).map(|m| m.len()).unwrap_or(0);
+                let _ =
+                    to_ui_tx.send(WorkerMsg::Started { key: key.clone(), project_sr, file_size });
+
+                let res = decode(&mut pcm_loader, &key, project_sr);
+
+                WorkerMsg::Finished { key, project_sr, res }
+            }
+            LoadJob::OpenStream { handle, path, start_frame, project_sr } => {
+                // This blocks until the audio at `start_frame` has been read.
+                let res = DiskStream::open(&path, start_frame, project_sr);
+
+                WorkerMsg::StreamOpened { handle, path, start_frame, project_sr, res }
+            }
+        };
+
+        if to_ui_tx.send(msg).is_err() {
+            break;
+        }
+    }
+}
+
+/// Returns the number of bytes taken up by the samples in `pcm`.
+fn pcm_size_bytes(pcm: &PcmRAM) -> usize {
+    fn size<T>(channels: &[Vec<T>]) -> usize {
+        channels.iter().map(|ch| ch.len() * std::mem::size_of::<T>()).sum()
+    }
+
+    match pcm.buffer() {
+        PcmRAMType::U8(b) => size(b),
+        PcmRAMType::U16(b) => size(b),
+        PcmRAMType::U24(b) => size(b),
+        PcmRAMType::S8(b) => size(b),
+        PcmRAMType::S16(b) => size(b),
+        PcmRAMType::S24(b) => size(b),
+        PcmRAMType::F32(b) => size(b),
+        PcmRAMType::F64(b) => size(b),
+    }
+}
+
+/// Decode the resource described by `key`, resampling it to the project
+/// sample rate and applying doppler stretching if needed.
+fn decode(
+    pcm_loader: &mut PcmLoader,
+    key: &PcmKey,
+    project_sr: u32,
+) -> Result<PcmRAM, PcmLoadError> {
+    let mut ratio = key.doppler_stretch_ratio;
+    if !(ratio.is_finite() && ratio > 0.0) {
+        log::warn!("Ignoring invalid doppler stretch ratio {} for {:?}", ratio, &key.path);
+        ratio = 1.0;
+    }
+
+    if ratio == 1.0 {
+        let target_sample_rate = if key.resample_to_project_sr { Some(project_sr) } else { None };
+        return pcm_loader.load(&key.path, target_sample_rate, key.resample_quality, None);
+    }
+
+    // Resampling to `1 / ratio` of the playback sample rate makes the resource
+    // play back `ratio` times as fast.
+    let playback_sr = if key.resample_to_project_sr {
+        project_sr
+    } else {
+        match probe_sample_rate(&key.path) {
+            Some(sample_rate) => sample_rate,
+            None => {
+                // The header does not say, so the sample rate of the file is
+                // only known once it is decoded.
+                pcm_loader.load(&key.path, None, key.resample_quality, None)?.sample_rate()
+            }
+        }
+    };
+    let target_sample_rate = (f64::from(playback_sr) / ratio).round().max(1.0) as u32;
+
+    pcm_loader.load(&key.path, Some(target_sample_rate), key.resample_quality, None)
+}
+
+/// Read the sample rate of the file at `path` from its header without
+/// decoding it.
+///
+/// This returns `None` if the file could not be probed or if its header does
+/// not contain the sample rate.
+fn probe_sample_rate(path: &Path) -> Option<u32> {
+    let file = File::open(path).ok()?;
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(extension);
+    }
+
+    let probed = symphonia::default::get_probe()
+        .format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default())
+        .ok()?;
+
+    probed.format.default_track()?.codec_params.sample_rate
+}
+
+#[cfg(test)]
+mod tests {
+    use meadowlark_core_types::time::SampleRate;
+    use std::path::Path;
+    use std::time::{Duration, Instant};
+
+    use super::{PcmKey, ResourceLoader, ResourceLoaderEvent};
+
+    fn sample_key(name: &str) -> PcmKey {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/test_files/drums").join(name);
+        PcmKey { path, ..PcmKey::default() }
+    }
+
+    /// Load `key` and return the number of bytes it takes up.
+    fn load_unused(resource_loader: &mut ResourceLoader, key: &PcmKey) -> usize {
+        let bytes_before = resource_loader.stats().bytes_loaded;
+        let (_, res) = resource_loader.load_pcm(key);
+        res.unwrap();
+        resource_loader.stats().bytes_loaded - bytes_before
+    }
+
+    /// The least recently used resources are evicted first once the memory
+    /// budget is exceeded.
+    #[test]
+    fn evict_least_recently_used() {
+        let mut resource_loader = ResourceLoader::new(SampleRate(48_000.0));
+        let (kick, snare) = (sample_key("kick.wav"), sample_key("snare.wav"));
+
+        let kick_bytes = load_unused(&mut resource_loader, &kick);
+        let snare_bytes = load_unused(&mut resource_loader, &snare);
+        assert_eq!(resource_loader.stats().num_loaded, 2);
+
+        // The kick is now used more recently than the snare.
+        assert!(resource_loader.get_loaded(&kick).is_some());
+
+        resource_loader.set_memory_budget(kick_bytes + snare_bytes - 1);
+
+        let stats = resource_loader.stats();
+        assert_eq!((stats.num_loaded, stats.bytes_loaded, stats.evictions), (1, kick_bytes, 1));
+        assert!(resource_loader.get_loaded(&snare).is_none());
+        assert!(resource_loader.get_loaded(&kick).is_some());
+
+        // Nothing is evicted while the resources fit in the budget.
+        resource_loader.set_memory_budget(kick_bytes + snare_bytes);
+        load_unused(&mut resource_loader, &snare);
+        assert_eq!(resource_loader.stats().num_loaded, 2);
+    }
+
+    /// Resources that are still in use are kept, even if that goes over the
+    /// memory budget.
+    #[test]
+    fn keep_resources_in_use() {
+        let mut resource_loader = ResourceLoader::new(SampleRate(48_000.0));
+        let (kick, snare) = (sample_key("kick.wav"), sample_key("snare.wav"));
+
+        let (kick_pcm, res) = resource_loader.load_pcm(&kick);
+        res.unwrap();
+        let kick_bytes = resource_loader.stats().bytes_loaded;
+        load_unused(&mut resource_loader, &snare);
+
+        resource_loader.set_memory_budget(0);
+
+        let stats = resource_loader.stats();
+        assert_eq!((stats.num_loaded, stats.bytes_loaded, stats.evictions), (1, kick_bytes, 1));
+        assert!(resource_loader.get_loaded(&kick).is_some());
+
+        drop(kick_pcm);
+        resource_loader.collect();
+
+        let stats = resource_loader.stats();
+        assert_eq!((stats.num_loaded, stats.bytes_loaded, stats.evictions), (0, 0, 2));
+    }
+
+    /// A request for a resource that is already being loaded waits on the
+    /// same load instead of decoding the file again.
+    #[test]
+    fn join_requests_in_flight() {
+        let mut resource_loader = ResourceLoader::new(SampleRate(48_000.0));
+        let kick = sample_key("kick.wav");
+
+        let first = resource_loader.request_pcm(&kick);
+        let second = resource_loader.request_pcm(&kick);
+
+        let stats = resource_loader.stats();
+        assert_eq!((stats.misses, stats.joined, stats.hits), (1, 1, 0));
+
+        let mut loaded = Vec::new();
+        let start = Instant::now();
+        while loaded.len() < 2 {
+            assert!(start.elapsed() < Duration::from_secs(10), "timed out loading {:?}", kick.path);
+            for event in resource_loader.poll() {
+                if let ResourceLoaderEvent::Loaded { handle, .. } = event {
+                    loaded.push(handle);
+                }
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(loaded, [first, second]);
+
+        resource_loader.request_pcm(&kick);
+        let stats = resource_loader.stats();
+        assert_eq!((stats.misses, stats.joined, stats.hits), (1, 1, 1));
+    }
 }
@@ -13,15 +13,14 @@ use meadowlark_core_types::time::{SampleRate, Seconds};
 use pcm_loader::PcmRAM;
 use rtrb::{Consumer, Producer, RingBuffer};
 
+use super::disk_stream::{DiskStream, PcmSource, UnderrunCounter};
+
 pub static SAMPLE_BROWSER_PLUG_RDN: &str = "app.meadowlark.sample-browser";
 
 static DECLICK_TIME: Seconds = Seconds(30.0 / 1000.0);
 
 const MSG_BUFFER_SIZE: usize = 64;
 
-// TODO: Use disk streaming with `clack` for sample playback instead of loading
-// the whole file upfront in the UI.
-
 pub struct SampleBrowserPlugFactory;
 
 impl PluginFactory for SampleBrowserPlugFactory {
@@ -53,14 +52,32 @@ impl PluginFactory for SampleBrowserPlugFactory {
 pub struct SampleBrowserPlugHandle {
     to_audio_thread_tx: Producer<ProcessMsg>,
     host_request: HostRequestChannelSender,
+    coll_handle: basedrop::Handle,
+    underruns: UnderrunCounter,
 }
 
 impl SampleBrowserPlugHandle {
     pub fn play_sample(&mut self, pcm: Shared<PcmRAM>) {
-        self.send(ProcessMsg::PlayNewSample { pcm });
+        self.send(ProcessMsg::PlayNewSample { source: PcmSource::Ram(pcm) });
+        self.host_request.request(HostRequestFlags::PROCESS);
+    }
+
+    /// Play a file that is streamed from disk.
+    ///
+    /// A stream cannot be replayed with `replay_sample()` while it is still
+    /// playing, so open a new stream to play it again from the start.
+    pub fn play_stream(&mut self, stream: DiskStream) {
+        let source = PcmSource::Stream(Owned::new(&self.coll_handle, stream));
+        self.send(ProcessMsg::PlayNewSample { source });
         self.host_request.request(HostRequestFlags::PROCESS);
     }
 
+    /// Returns the number of times a streamed file could not be read from
+    /// disk in time since this was last called.
+    pub fn take_underruns(&mut self) -> u32 {
+        self.underruns.take()
+    }
+
     pub fn replay_sample(&mut self) {
         self.send(ProcessMsg::ReplaySample);
         self.host_request.request(HostRequestFlags::PROCESS);
@@ -78,7 +95,7 @@ impl SampleBrowserPlugHandle {
 }
 
 enum ProcessMsg {
-    PlayNewSample { pcm: Shared<PcmRAM> },
+    PlayNewSample { source: PcmSource },
     ReplaySample,
     Stop,
 }
@@ -144,6 +161,8 @@ impl PluginMainThread for SampleBrowserPlugMainThread {
         let declick_buf_l = Owned::new(coll_handle, vec![0.0; max_frames as usize]);
         let declick_buf_r = Owned::new(coll_handle, vec![0.0; max_frames as usize]);
 
+        let underruns = UnderrunCounter::default();
+
         Ok(PluginActivatedInfo {
             audio_thread: Box::new(SampleBrowserPlugAudioThread {
                 params,
@@ -156,10 +175,13 @@ impl PluginMainThread for SampleBrowserPlugMainThread {
                 declick_frames,
                 declick_buf_l,
                 declick_buf_r,
+                underruns: underruns.clone(),
             }),
             internal_handle: Some(Box::new(SampleBrowserPlugHandle {
                 to_audio_thread_tx,
                 host_request: self.host_request.clone(),
+                coll_handle: coll_handle.clone(),
+                underruns,
             })),
         })
     }
@@ -231,14 +253,16 @@ pub struct SampleBrowserPlugAudioThread {
     play_state: PlayState,
     declick_state: DeclickState,
 
-    pcm: Option<Shared<PcmRAM>>,
-    old_pcm: Option<Shared<PcmRAM>>,
+    pcm: Option<PcmSource>,
+    old_pcm: Option<PcmSource>,
 
     declick_dec: f32,
     declick_frames: usize,
 
     declick_buf_l: Owned<Vec<f32>>,
     declick_buf_r: Owned<Vec<f32>>,
+
+    underruns: UnderrunCounter,
 }
 
 impl SampleBrowserPlugAudioThread {
@@ -253,10 +277,10 @@ impl SampleBrowserPlugAudioThread {
 
         while let Ok(msg) = self.from_handle_rx.pop() {
             match msg {
-                ProcessMsg::PlayNewSample { pcm } => {
+                ProcessMsg::PlayNewSample { source } => {
                     if let PlayState::Playing { playhead: old_playhead } = self.play_state {
                         self.old_pcm = Some(self.pcm.take().unwrap());
-                        self.pcm = Some(pcm);
+                        self.pcm = Some(source);
 
                         self.declick_state = DeclickState::Running {
                             old_playhead,
@@ -266,20 +290,24 @@ impl SampleBrowserPlugAudioThread {
 
                         self.play_state = PlayState::Playing { playhead: 0 };
                     } else {
-                        self.pcm = Some(pcm);
+                        self.pcm = Some(source);
 
                         self.play_state = PlayState::Playing { playhead: 0 };
                     }
                 }
                 ProcessMsg::ReplaySample => {
                     if let PlayState::Playing { playhead: old_playhead } = self.play_state {
-                        self.old_pcm = Some(Shared::clone(self.pcm.as_ref().unwrap()));
-
-                        self.declick_state = DeclickState::Running {
-                            old_playhead,
-                            declick_gain: 1.0,
-                            declick_frames_left: self.declick_frames,
-                        };
+                        // A stream can only be read from one place at a time,
+                        // so it is restarted without a crossfade.
+                        if let Some(old_pcm) = self.pcm.as_ref().unwrap().try_clone() {
+                            self.old_pcm = Some(old_pcm);
+
+                            self.declick_state = DeclickState::Running {
+                                old_playhead,
+                                declick_gain: 1.0,
+                                declick_frames_left: self.declick_frames,
+                            };
+                        }
 
                         self.play_state = PlayState::Playing { playhead: 0 };
                     } else if self.pcm.is_some() {
@@ -330,10 +358,12 @@ impl PluginAudioThread for SampleBrowserPlugAudioThread {
         let mut apply_gain = false;
 
         if let PlayState::Playing { mut playhead } = self.play_state {
-            let pcm = self.pcm.as_ref().unwrap();
+            let pcm = self.pcm.as_mut().unwrap();
 
-            if playhead < pcm.len_frames() as usize {
-                pcm.fill_stereo_f32(playhead as isize, buf_l_part, buf_r_part);
+            if (playhead as u64) < pcm.len_frames() {
+                if !pcm.fill_stereo_f32(playhead as u64, buf_l_part, buf_r_part) {
+                    self.underruns.increment();
+                }
 
                 playhead += proc_info.frames;
 
@@ -357,19 +387,21 @@ impl PluginAudioThread for SampleBrowserPlugAudioThread {
             mut declick_frames_left,
         } = self.declick_state
         {
-            let old_pcm = self.old_pcm.as_ref().unwrap();
+            let old_pcm = self.old_pcm.as_mut().unwrap();
 
             let mut running = true;
 
             let declick_buf_l_part = &mut self.declick_buf_l[0..proc_info.frames];
             let declick_buf_r_part = &mut self.declick_buf_r[0..proc_info.frames];
 
-            if old_playhead < old_pcm.len_frames() as usize {
-                old_pcm.fill_stereo_f32(
-                    old_playhead as isize,
+            if (old_playhead as u64) < old_pcm.len_frames() {
+                if !old_pcm.fill_stereo_f32(
+                    old_playhead as u64,
                     declick_buf_l_part,
                     declick_buf_r_part,
-                );
+                ) {
+                    self.underruns.increment();
+                }
 
                 old_playhead += proc_info.frames;
 
@@ -1,12 +1,258 @@
 use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::Stream;
+use cpal::{
+    BufferSize, Device, Host, SampleFormat, Stream, StreamConfig, SupportedBufferSize,
+    SupportedStreamConfigRange,
+};
+use crossbeam::channel::{self, Receiver, Sender};
 use dropseed::DSEngineAudioThread;
 use meadowlark_core_types::time::SampleRate;
-use rtrb::{Producer, RingBuffer};
+use rtrb::{Consumer, Producer, RingBuffer};
+use serde::{Deserialize, Serialize};
 
 const HANDLE_TO_STREAM_MSG_SIZE: usize = 8;
+const STREAM_ERROR_MSG_SIZE: usize = 16;
+
+/// The smallest block of frames the engine will be asked to process.
+pub const MIN_FRAMES: u32 = 1;
+
+/// The largest block of frames the engine will be asked to process when the
+/// buffer size is left up to the audio driver.
+pub const DEFAULT_MAX_FRAMES: u32 = 512;
+
+/// The sample rate the null backend runs at if none was chosen.
+pub const DEFAULT_NULL_SAMPLE_RATE: u32 = 48_000;
+
+pub const DEFAULT_GRAPH_IN_CHANNELS: u16 = 2;
+pub const DEFAULT_GRAPH_OUT_CHANNELS: u16 = 2;
+
+/// The sample rates that are offered to the user if the device supports them.
+const COMMON_SAMPLE_RATES: [u32; 7] = [22_050, 44_100, 48_000, 88_200, 96_000, 176_400, 192_000];
+
+/// The smallest number of frames the input ring buffer can hold, in case the
+/// audio driver sends larger buffers than it was asked for.
+const MIN_INPUT_RING_BUFFER_FRAMES: usize = 4096;
+
+/// The buffer sizes that are offered to the user if the device supports them.
+const COMMON_BUFFER_SIZES: [u32; 8] = [32, 64, 128, 256, 512, 1024, 2048, 4096];
+
+static SYSTEM_IO_CONFIG_FILE_NAME: &str = "system_io.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SystemIOBackend {
+    /// Use the audio devices of the system through CPAL.
+    Cpal,
+    /// Do not use any audio device. The engine is driven from a timer thread
+    /// instead, which is useful for headless runs and for machines with no
+    /// sound hardware.
+    Null,
+}
+
+impl Default for SystemIOBackend {
+    fn default() -> Self {
+        SystemIOBackend::Cpal
+    }
+}
+
+/// The user's choice of audio host, device, and stream settings.
+///
+/// Any setting left as `None` uses the default of the system.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SystemIOConfig {
+    pub backend: SystemIOBackend,
+
+    pub host: Option<String>,
+    pub output_device: Option<String>,
+
+    /// Whether to capture audio from an input device into the audio graph.
+    pub input_enabled: bool,
+    pub input_device: Option<String>,
+
+    pub sample_rate: Option<u32>,
+    pub buffer_size: Option<u32>,
+
+    /// The number of input channels on the audio graph.
+    pub graph_in_channels: u16,
+    /// The number of output channels on the audio graph.
+    pub graph_out_channels: u16,
+
+    /// Only used by the null backend. If `true`, all of the output of the
+    /// engine is captured into a buffer that can be read with
+    /// `SystemIOStreamHandle::take_captured_output()`.
+    #[serde(skip)]
+    pub capture_output: bool,
+}
+
+impl Default for SystemIOConfig {
+    fn default() -> Self {
+        Self {
+            backend: SystemIOBackend::default(),
+            host: None,
+            output_device: None,
+            input_enabled: false,
+            input_device: None,
+            sample_rate: None,
+            buffer_size: None,
+            graph_in_channels: DEFAULT_GRAPH_IN_CHANNELS,
+            graph_out_channels: DEFAULT_GRAPH_OUT_CHANNELS,
+            capture_output: false,
+        }
+    }
+}
+
+impl SystemIOConfig {
+    /// The file the config is persisted to.
+    pub fn default_path() -> PathBuf {
+        crate::util::config_dir().join(SYSTEM_IO_CONFIG_FILE_NAME)
+    }
+
+    /// Load the config from `path`, or return the default config if it does
+    /// not exist or could not be read.
+    pub fn load_or_default(path: &Path) -> Self {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(_) => return Self::default(),
+        };
+
+        match serde_json::from_str(&contents) {
+            Ok(config) => config,
+            Err(e) => {
+                log::error!("Failed to parse system IO config {:?}: {}", path, e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// An audio device and the settings it supports.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioDeviceInfo {
+    pub name: String,
+    pub is_default: bool,
+    pub sample_rates: Vec<u32>,
+    pub buffer_sizes: Vec<u32>,
+}
+
+/// Returns the names of the audio hosts (i.e. ALSA, JACK, WASAPI, ASIO,
+/// CoreAudio) available on this system.
+pub fn available_hosts() -> Vec<String> {
+    cpal::available_hosts().iter().map(|id| String::from(id.name())).collect()
+}
+
+/// Returns the output devices of the given host (or of the default host if
+/// `None`).
+pub fn output_devices(host: Option<&str>) -> Result<Vec<AudioDeviceInfo>, Box<dyn Error>> {
+    let host = find_host(host)?;
+
+    let default_name = host.default_output_device().and_then(|d| d.name().ok());
+
+    Ok(host
+        .output_devices()?
+        .filter_map(|device| {
+            let configs =
+                device.supported_output_configs().map(|c| c.collect()).unwrap_or_default();
+            device_info(&device, default_name.as_deref(), configs)
+        })
+        .collect())
+}
+
+/// Returns the input devices of the given host (or of the default host if
+/// `None`).
+pub fn input_devices(host: Option<&str>) -> Result<Vec<AudioDeviceInfo>, Box<dyn Error>> {
+    let host = find_host(host)?;
+
+    let default_name = host.default_input_device().and_then(|d| d.name().ok());
+
+    Ok(host
+        .input_devices()?
+        .filter_map(|device| {
+            let configs = device.supported_input_configs().map(|c| c.collect()).unwrap_or_default();
+            device_info(&device, default_name.as_deref(), configs)
+        })
+        .collect())
+}
+
+fn device_info(
+    device: &Device,
+    default_name: Option<&str>,
+    configs: Vec<SupportedStreamConfigRange>,
+) -> Option<AudioDeviceInfo> {
+    let name = match device.name() {
+        Ok(name) => name,
+        Err(e) => {
+            log::warn!("CPAL: skipping audio device with no name: {}", e);
+            return None;
+        }
+    };
+
+    let mut sample_rates: Vec<u32> = Vec::new();
+    let mut buffer_sizes: Vec<u32> = Vec::new();
+    for range in configs.iter() {
+        for sr in COMMON_SAMPLE_RATES {
+            if range.min_sample_rate().0 <= sr
+                && sr <= range.max_sample_rate().0
+                && !sample_rates.contains(&sr)
+            {
+                sample_rates.push(sr);
+            }
+        }
+
+        for size in COMMON_BUFFER_SIZES {
+            if buffer_size_supported(range.buffer_size(), size) && !buffer_sizes.contains(&size) {
+                buffer_sizes.push(size);
+            }
+        }
+    }
+    sample_rates.sort_unstable();
+    buffer_sizes.sort_unstable();
+
+    Some(AudioDeviceInfo {
+        is_default: Some(name.as_str()) == default_name,
+        name,
+        sample_rates,
+        buffer_sizes,
+    })
+}
+
+/// An error reported by a running system IO stream.
+///
+/// Once this is received the stream should be considered dead, and it should
+/// be replaced with a new one.
+#[derive(Debug)]
+pub struct SystemIOStreamError {
+    /// `true` if the error came from the input stream, `false` if it came
+    /// from the output stream.
+    pub is_input: bool,
+    pub error: cpal::StreamError,
+}
+
+impl Error for SystemIOStreamError {}
+
+impl fmt::Display for SystemIOStreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_input {
+            write!(f, "Audio input stream stopped: {}", self.error)
+        } else {
+            write!(f, "Audio output stream stopped: {}", self.error)
+        }
+    }
+}
 
 #[derive(Debug)]
 enum HandleToStreamMsg {
@@ -14,10 +260,19 @@ enum HandleToStreamMsg {
     DropEngineAudioThread,
 }
 
+enum StreamKind {
+    Cpal { output: Stream, input: Option<Stream> },
+    Thread(ThreadStream),
+}
+
 pub struct SystemIOStreamHandle {
-    cpal_stream: Stream,
+    stream: StreamKind,
     to_stream_tx: Producer<HandleToStreamMsg>,
+    from_stream_err_rx: Receiver<SystemIOStreamError>,
     sample_rate: SampleRate,
+    max_frames: u32,
+    graph_in_channels: u16,
+    graph_out_channels: u16,
 }
 
 impl SystemIOStreamHandle {
@@ -25,6 +280,54 @@ impl SystemIOStreamHandle {
         self.sample_rate
     }
 
+    pub fn min_frames(&self) -> u32 {
+        MIN_FRAMES
+    }
+
+    pub fn max_frames(&self) -> u32 {
+        self.max_frames
+    }
+
+    /// Returns `true` if audio from an input device is being fed into the
+    /// audio graph.
+    pub fn has_input(&self) -> bool {
+        matches!(&self.stream, StreamKind::Cpal { input: Some(_), .. })
+    }
+
+    pub fn backend(&self) -> SystemIOBackend {
+        match &self.stream {
+            StreamKind::Cpal { .. } => SystemIOBackend::Cpal,
+            StreamKind::Thread(_) => SystemIOBackend::Null,
+        }
+    }
+
+    /// Take all of the interleaved output that was captured since the last
+    /// call.
+    ///
+    /// This is always empty unless this is a null stream with
+    /// `SystemIOConfig::capture_output` enabled.
+    pub fn take_captured_output(&mut self) -> Vec<f32> {
+        match &self.stream {
+            StreamKind::Thread(ThreadStream { captured: Some(captured), .. }) => {
+                std::mem::take(&mut *captured.lock().unwrap())
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn graph_in_channels(&self) -> u16 {
+        self.graph_in_channels
+    }
+
+    pub fn graph_out_channels(&self) -> u16 {
+        self.graph_out_channels
+    }
+
+    /// Returns any errors the stream has reported since the last call.
+    pub fn take_errors(&mut self) -> Vec<SystemIOStreamError> {
+        self.from_stream_err_rx.try_iter().collect()
+    }
+
     pub fn engine_activated(&mut self, engine_audio_thread: DSEngineAudioThread) {
         self.to_stream_tx
             .push(HandleToStreamMsg::NewEngineAudioThread(engine_audio_thread))
@@ -36,31 +339,68 @@ impl SystemIOStreamHandle {
     }
 }
 
-/// This is temporary. Eventually we will have a more sophisticated and
-/// configurable system using `rainout`.
-pub fn temp_spawn_cpal_default_output_only() -> Result<SystemIOStreamHandle, Box<dyn Error>> {
+/// Start a stream using the backend chosen in the config.
+pub fn spawn_stream(config: &SystemIOConfig) -> Result<SystemIOStreamHandle, Box<dyn Error>> {
+    match config.backend {
+        SystemIOBackend::Cpal => spawn_cpal_stream(config),
+        SystemIOBackend::Null => Ok(spawn_null_stream(config)),
+    }
+}
+
+/// Start an output stream with the given config, along with an input stream
+/// if input is enabled.
+///
+/// Eventually we will have a more sophisticated system using `rainout`.
+fn spawn_cpal_stream(config: &SystemIOConfig) -> Result<SystemIOStreamHandle, Box<dyn Error>> {
     let (to_stream_tx, mut from_handle_rx) =
         RingBuffer::<HandleToStreamMsg>::new(HANDLE_TO_STREAM_MSG_SIZE);
 
-    let cpal_host = cpal::default_host();
+    // The error callbacks may be called from the audio thread, so use a
+    // bounded channel to avoid allocating there.
+    let (to_ui_err_tx, from_stream_err_rx) =
+        channel::bounded::<SystemIOStreamError>(STREAM_ERROR_MSG_SIZE);
+
+    let cpal_host = find_host(config.host.as_deref())?;
+
+    log::info!("Selected CPAL host: {:?}", cpal_host.id().name());
 
-    let device = cpal_host
-        .default_output_device()
-        .ok_or("CPAL: no default audio out device found".to_string())?;
+    let device = find_output_device(&cpal_host, config.output_device.as_deref())?;
 
-    log::info!("Selected default CPAL output device: {:?}", &device.name());
+    log::info!("Selected CPAL output device: {:?}", &device.name());
 
-    let config = device.default_output_config()?;
+    let stream_config = output_stream_config(&device, config)?;
 
-    let num_out_channels = usize::from(config.channels());
-    let sample_rate: SampleRate = config.sample_rate().0.into();
+    let num_out_channels = usize::from(stream_config.channels);
+    let sample_rate: SampleRate = stream_config.sample_rate.0.into();
+    let max_frames = match stream_config.buffer_size {
+        BufferSize::Fixed(size) => size.max(MIN_FRAMES),
+        BufferSize::Default => DEFAULT_MAX_FRAMES,
+    };
+
+    let (cpal_input_stream, mut input_reader) = if config.input_enabled {
+        match spawn_input_stream(
+            &cpal_host,
+            config.input_device.as_deref(),
+            &stream_config,
+            max_frames,
+            to_ui_err_tx.clone(),
+        ) {
+            Ok((stream, reader)) => (Some(stream), Some(reader)),
+            Err(e) => {
+                log::error!("Failed to start audio input stream, continuing without input: {}", e);
+                (None, None)
+            }
+        }
+    } else {
+        (None, None)
+    };
 
     let mut engine_audio_thread: Option<DSEngineAudioThread> = None;
 
-    log::info!("Starting CPAL stream with config {:?}...", &config);
+    log::info!("Starting CPAL stream with config {:?}...", &stream_config);
 
     let cpal_stream = device.build_output_stream(
-        &config.into(),
+        &stream_config,
         move |audio_buffer: &mut [f32], _: &cpal::OutputCallbackInfo| {
             while let Ok(msg) = from_handle_rx.pop() {
                 match msg {
@@ -73,14 +413,28 @@ pub fn temp_spawn_cpal_default_output_only() -> Result<SystemIOStreamHandle, Box
                 }
             }
 
+            // Always read the input, even when the engine is not running, so
+            // that it stays in sync with the output.
+            if let Some(input_reader) = &mut input_reader {
+                input_reader.read(audio_buffer.len() / num_out_channels);
+            }
+
             if let Some(engine_audio_thread) = &mut engine_audio_thread {
-                engine_audio_thread
-                    .process_cpal_interleaved_output_only(num_out_channels, audio_buffer);
+                if let Some(input_reader) = &input_reader {
+                    engine_audio_thread.process_cpal_interleaved(
+                        input_reader.num_channels,
+                        &input_reader.buffer,
+                        num_out_channels,
+                        audio_buffer,
+                    );
+                } else {
+                    engine_audio_thread
+                        .process_cpal_interleaved_output_only(num_out_channels, audio_buffer);
+                }
             }
         },
-        |e| {
-            // TODO: Better handling of the system IO stream crashing.
-            panic!("{}", e);
+        move |error| {
+            let _ = to_ui_err_tx.try_send(SystemIOStreamError { is_input: false, error });
         },
     )?;
 
@@ -88,5 +442,438 @@ pub fn temp_spawn_cpal_default_output_only() -> Result<SystemIOStreamHandle, Box
 
     log::info!("Successfully started CPAL stream");
 
-    Ok(SystemIOStreamHandle { cpal_stream, to_stream_tx, sample_rate })
+    Ok(SystemIOStreamHandle {
+        stream: StreamKind::Cpal { output: cpal_stream, input: cpal_input_stream },
+        to_stream_tx,
+        from_stream_err_rx,
+        sample_rate,
+        max_frames,
+        graph_in_channels: config.graph_in_channels,
+        graph_out_channels: config.graph_out_channels,
+    })
+}
+
+/// A stream that is driven by its own thread instead of by an audio device.
+struct ThreadStream {
+    run: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    captured: Option<Arc<Mutex<Vec<f32>>>>,
+}
+
+impl Drop for ThreadStream {
+    fn drop(&mut self) {
+        self.run.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Owns the engine audio thread inside of a stream that is driven by its own
+/// thread.
+pub(crate) struct ThreadStreamEngine {
+    from_handle_rx: Consumer<HandleToStreamMsg>,
+    engine_audio_thread: Option<DSEngineAudioThread>,
+}
+
+impl ThreadStreamEngine {
+    /// Process one block of interleaved output.
+    ///
+    /// Returns `false` if the engine is not activated, in which case the
+    /// buffer is filled with silence.
+    pub fn process(&mut self, num_out_channels: usize, audio_buffer: &mut [f32]) -> bool {
+        while let Ok(msg) = self.from_handle_rx.pop() {
+            match msg {
+                HandleToStreamMsg::NewEngineAudioThread(new_engine_audio_thread) => {
+                    self.engine_audio_thread = Some(new_engine_audio_thread);
+                }
+                HandleToStreamMsg::DropEngineAudioThread => {
+                    self.engine_audio_thread = None;
+                }
+            }
+        }
+
+        audio_buffer.iter_mut().for_each(|s| *s = 0.0);
+
+        if let Some(engine_audio_thread) = &mut self.engine_audio_thread {
+            engine_audio_thread
+                .process_cpal_interleaved_output_only(num_out_channels, audio_buffer);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Start a stream that is driven by a thread running `run_stream`.
+///
+/// `run_stream` should return once the given flag is set to `false`, which
+/// happens when the `SystemIOStreamHandle` is dropped.
+pub(crate) fn spawn_thread_stream<F>(
+    sample_rate: u32,
+    max_frames: u32,
+    graph_in_channels: u16,
+    graph_out_channels: u16,
+    captured: Option<Arc<Mutex<Vec<f32>>>>,
+    run_stream: F,
+) -> SystemIOStreamHandle
+where
+    F: FnOnce(ThreadStreamEngine, Arc<AtomicBool>) + Send + 'static,
+{
+    let (to_stream_tx, from_handle_rx) =
+        RingBuffer::<HandleToStreamMsg>::new(HANDLE_TO_STREAM_MSG_SIZE);
+
+    // These streams never fail, so nothing is ever sent on this channel.
+    let (_, from_stream_err_rx) = channel::bounded::<SystemIOStreamError>(STREAM_ERROR_MSG_SIZE);
+
+    let run = Arc::new(AtomicBool::new(true));
+
+    let thread = {
+        let run = Arc::clone(&run);
+        let engine = ThreadStreamEngine { from_handle_rx, engine_audio_thread: None };
+        std::thread::spawn(move || run_stream(engine, run))
+    };
+
+    SystemIOStreamHandle {
+        stream: StreamKind::Thread(ThreadStream { run, thread: Some(thread), captured }),
+        to_stream_tx,
+        from_stream_err_rx,
+        sample_rate: sample_rate.into(),
+        max_frames,
+        graph_in_channels,
+        graph_out_channels,
+    }
+}
+
+/// Start a stream that is not connected to any audio device. A timer thread
+/// processes one block of frames every time a block's worth of time has
+/// passed, just like an audio device would.
+fn spawn_null_stream(config: &SystemIOConfig) -> SystemIOStreamHandle {
+    let sample_rate = config.sample_rate.unwrap_or(DEFAULT_NULL_SAMPLE_RATE);
+    let max_frames = config.buffer_size.unwrap_or(DEFAULT_MAX_FRAMES).max(MIN_FRAMES);
+    let num_out_channels = usize::from(config.graph_out_channels);
+
+    let captured =
+        if config.capture_output { Some(Arc::new(Mutex::new(Vec::new()))) } else { None };
+
+    log::info!(
+        "Starting null audio stream with sample rate {} and block size {}...",
+        sample_rate,
+        max_frames
+    );
+
+    let thread_captured = captured.clone();
+    spawn_thread_stream(
+        sample_rate,
+        max_frames,
+        config.graph_in_channels,
+        config.graph_out_channels,
+        captured,
+        move |mut engine, run| {
+            run_timer_loop(
+                sample_rate,
+                max_frames,
+                num_out_channels,
+                &run,
+                thread_captured.as_deref(),
+                |audio_buffer| {
+                    engine.process(num_out_channels, audio_buffer);
+                },
+            );
+        },
+    )
+}
+
+/// Call `process` with a block of `max_frames` frames every time a block's
+/// worth of time has passed, until `run` is set to `false`.
+fn run_timer_loop<F>(
+    sample_rate: u32,
+    max_frames: u32,
+    num_out_channels: usize,
+    run: &AtomicBool,
+    captured: Option<&Mutex<Vec<f32>>>,
+    mut process: F,
+) where
+    F: FnMut(&mut [f32]),
+{
+    let period = Duration::from_secs_f64(f64::from(max_frames) / f64::from(sample_rate));
+
+    let mut audio_buffer = vec![0.0; max_frames as usize * num_out_channels];
+    let mut next_block = Instant::now();
+
+    while run.load(Ordering::Relaxed) {
+        process(&mut audio_buffer);
+
+        if let Some(captured) = captured {
+            captured.lock().unwrap().extend_from_slice(&audio_buffer);
+        }
+
+        next_block += period;
+        let now = Instant::now();
+        if next_block > now {
+            std::thread::sleep(next_block - now);
+        } else {
+            // Don't try to catch up if the thread fell behind.
+            next_block = now;
+        }
+    }
+}
+
+/// Reads the captured input from the ring buffer in the output callback.
+struct InputReader {
+    from_input_rx: Consumer<f32>,
+    num_channels: usize,
+
+    /// The interleaved input for the current output buffer.
+    buffer: Vec<f32>,
+
+    /// The number of samples kept in the ring buffer so that the output never
+    /// has to wait on the input.
+    latency_samples: usize,
+}
+
+impl InputReader {
+    /// Fill `buffer` with the next `num_frames` frames of input.
+    fn read(&mut self, num_frames: usize) {
+        let num_samples = num_frames * self.num_channels;
+
+        // The input and output devices run on separate clocks, so input slowly
+        // builds up if the input device runs slightly faster. Drop the oldest
+        // input to get back to the target latency.
+        let available = self.from_input_rx.slots();
+        if available > (self.latency_samples * 2) + num_samples {
+            let mut skip = available - self.latency_samples - num_samples;
+            skip -= skip % self.num_channels;
+            if let Ok(chunk) = self.from_input_rx.read_chunk(skip) {
+                chunk.commit_all();
+            }
+        }
+
+        // The buffer was allocated with enough capacity up front, so this
+        // will not allocate unless the driver sends an unusually large buffer.
+        self.buffer.clear();
+        self.buffer.resize(num_samples, 0.0);
+
+        let mut to_read = num_samples.min(self.from_input_rx.slots());
+        to_read -= to_read % self.num_channels;
+        if let Ok(chunk) = self.from_input_rx.read_chunk(to_read) {
+            let (first, second) = chunk.as_slices();
+            self.buffer[0..first.len()].copy_from_slice(first);
+            self.buffer[first.len()..first.len() + second.len()].copy_from_slice(second);
+            chunk.commit_all();
+        }
+
+        // If the input ran out (i.e. the input device runs slightly slower),
+        // then the rest of the buffer is left silent.
+    }
+}
+
+/// Start an input stream that runs at the same sample rate and buffer size as
+/// the output stream.
+fn spawn_input_stream(
+    host: &Host,
+    device_name: Option<&str>,
+    output_config: &StreamConfig,
+    max_frames: u32,
+    to_ui_err_tx: Sender<SystemIOStreamError>,
+) -> Result<(Stream, InputReader), Box<dyn Error>> {
+    let device = match device_name {
+        Some(name) => host
+            .input_devices()?
+            .find(|device| device.name().map(|n| n == name).unwrap_or(false))
+            .ok_or_else(|| format!("CPAL: audio in device {:?} not found", name))?,
+        None => host.default_input_device().ok_or("CPAL: no default audio in device found")?,
+    };
+
+    log::info!("Selected CPAL input device: {:?}", &device.name());
+
+    let sr = output_config.sample_rate;
+    let supported = device
+        .supported_input_configs()?
+        .filter(|range| range.min_sample_rate() <= sr && sr <= range.max_sample_rate())
+        .max_by_key(|range| range.sample_format() == SampleFormat::F32)
+        .ok_or_else(|| format!("CPAL: audio in device does not support sample rate {}", sr.0))?
+        .with_sample_rate(sr);
+
+    let mut input_config = supported.config();
+    if let BufferSize::Fixed(size) = output_config.buffer_size {
+        if buffer_size_supported(supported.buffer_size(), size) {
+            input_config.buffer_size = BufferSize::Fixed(size);
+        }
+    }
+
+    let num_channels = usize::from(input_config.channels);
+
+    // Keep one buffer of input ahead of the output, so the output callback
+    // always has a full buffer of input to read from.
+    let latency_samples = max_frames as usize * num_channels;
+
+    let capacity = (max_frames as usize).max(MIN_INPUT_RING_BUFFER_FRAMES) * 4 * num_channels;
+    let (mut to_output_tx, from_input_rx) = RingBuffer::<f32>::new(capacity);
+    for _ in 0..latency_samples {
+        to_output_tx.push(0.0).unwrap();
+    }
+
+    log::info!("Starting CPAL input stream with config {:?}...", &input_config);
+
+    let stream = device.build_input_stream(
+        &input_config,
+        move |data: &[f32], _: &cpal::InputCallbackInfo| {
+            // Only write whole frames, so the channels never get out of order.
+            let mut n = data.len().min(to_output_tx.slots());
+            n -= n % num_channels;
+            for s in data[0..n].iter() {
+                let _ = to_output_tx.push(*s);
+            }
+        },
+        move |error| {
+            let _ = to_ui_err_tx.try_send(SystemIOStreamError { is_input: true, error });
+        },
+    )?;
+
+    stream.play()?;
+
+    log::info!("Successfully started CPAL input stream with {} frames of latency", max_frames);
+
+    let reader = InputReader {
+        from_input_rx,
+        num_channels,
+        buffer: Vec::with_capacity(capacity),
+        latency_samples,
+    };
+
+    Ok((stream, reader))
+}
+
+fn find_host(name: Option<&str>) -> Result<Host, Box<dyn Error>> {
+    match name {
+        Some(name) => {
+            let id = cpal::available_hosts()
+                .into_iter()
+                .find(|id| id.name() == name)
+                .ok_or_else(|| format!("CPAL: audio host {:?} not found", name))?;
+
+            Ok(cpal::host_from_id(id)?)
+        }
+        None => Ok(cpal::default_host()),
+    }
+}
+
+fn find_output_device(host: &Host, name: Option<&str>) -> Result<Device, Box<dyn Error>> {
+    match name {
+        Some(name) => Ok(host
+            .output_devices()?
+            .find(|device| device.name().map(|n| n == name).unwrap_or(false))
+            .ok_or_else(|| format!("CPAL: audio out device {:?} not found", name))?),
+        None => {
+            Ok(host.default_output_device().ok_or("CPAL: no default audio out device found")?)
+        }
+    }
+}
+
+/// Returns the stream config closest to the user's choice of sample rate and
+/// buffer size.
+fn output_stream_config(
+    device: &Device,
+    config: &SystemIOConfig,
+) -> Result<StreamConfig, Box<dyn Error>> {
+    let mut supported = device.default_output_config()?;
+
+    if let Some(sr) = config.sample_rate {
+        if sr != supported.sample_rate().0 {
+            let default_channels = supported.channels();
+
+            supported = device
+                .supported_output_configs()?
+                .filter(|range| range.min_sample_rate().0 <= sr && sr <= range.max_sample_rate().0)
+                .max_by_key(|range| {
+                    (
+                        range.channels() == default_channels,
+                        range.sample_format() == SampleFormat::F32,
+                    )
+                })
+                .ok_or_else(|| format!("CPAL: audio device does not support sample rate {}", sr))?
+                .with_sample_rate(cpal::SampleRate(sr));
+        }
+    }
+
+    let mut stream_config = supported.config();
+
+    if let Some(size) = config.buffer_size {
+        if buffer_size_supported(supported.buffer_size(), size) {
+            stream_config.buffer_size = BufferSize::Fixed(size);
+        } else {
+            log::warn!(
+                "CPAL: audio device does not support a buffer size of {}, using the default",
+                size
+            );
+        }
+    }
+
+    Ok(stream_config)
+}
+
+fn buffer_size_supported(supported: &SupportedBufferSize, size: u32) -> bool {
+    match supported {
+        SupportedBufferSize::Range { min, max } => *min <= size && size <= *max,
+        SupportedBufferSize::Unknown => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+
+    use super::run_timer_loop;
+
+    /// The timer thread must process blocks of the configured size no faster
+    /// than the sample rate, like an audio device would.
+    #[test]
+    fn null_stream_processes_in_real_time() {
+        let sample_rate = 48_000;
+        let max_frames = 480;
+        let num_out_channels = 2;
+        let num_blocks = 20;
+
+        let run = AtomicBool::new(true);
+        let captured = Mutex::new(Vec::new());
+
+        let mut frames_processed = 0;
+        let start = Instant::now();
+
+        // A stand-in for the engine audio thread, which stops the stream
+        // after a fixed number of blocks.
+        run_timer_loop(
+            sample_rate,
+            max_frames,
+            num_out_channels,
+            &run,
+            Some(&captured),
+            |audio_buffer| {
+                assert_eq!(audio_buffer.len(), max_frames as usize * num_out_channels);
+                audio_buffer.iter_mut().for_each(|s| *s = 1.0);
+
+                frames_processed += max_frames;
+                if frames_processed == num_blocks * max_frames {
+                    run.store(false, Ordering::Relaxed);
+                }
+            },
+        );
+
+        let elapsed = start.elapsed();
+
+        assert_eq!(frames_processed, num_blocks * max_frames);
+        assert_eq!(
+            captured.lock().unwrap().len(),
+            (num_blocks * max_frames) as usize * num_out_channels
+        );
+
+        // Each block is 10 ms long. The first block is processed right away,
+        // and every block after that waits for the previous one to end.
+        let min_elapsed = Duration::from_millis(10 * u64::from(num_blocks - 1));
+        assert!(elapsed >= min_elapsed, "processed {} blocks in {:?}", num_blocks, elapsed);
+        assert!(elapsed < min_elapsed * 10, "processed {} blocks in {:?}", num_blocks, elapsed);
+    }
 }
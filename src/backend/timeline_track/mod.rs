@@ -1,13 +1,19 @@
-use basedrop::Shared;
+use basedrop::{Owned, Shared};
 use dropseed::plugin::HostRequestChannelSender;
 use dropseed::plugin::{
-    buffer::EventBuffer, ext, HostInfo, PluginActivatedInfo, PluginAudioThread, PluginDescriptor,
-    PluginFactory, PluginInstanceID, PluginMainThread, ProcBuffers, ProcInfo, ProcessStatus,
+    buffer::EventBuffer, ext, HostInfo, HostRequestFlags, PluginActivatedInfo, PluginAudioThread,
+    PluginDescriptor, PluginFactory, PluginInstanceID, PluginMainThread, ProcBuffers, ProcInfo,
+    ProcessStatus,
 };
-use meadowlark_core_types::time::SampleRate;
+use meadowlark_core_types::time::{SampleRate, Seconds, SuperFrames};
+use rtrb::{Consumer, Producer, RingBuffer};
+
+use super::disk_stream::{PcmSource, UnderrunCounter};
 
 pub static TIMELINE_TRACK_PLUG_RDN: &str = "app.meadowlark.timeline-track";
 
+const MSG_BUFFER_SIZE: usize = 64;
+
 pub struct TimelineTrackPlugFactory;
 
 impl PluginFactory for TimelineTrackPlugFactory {
@@ -15,7 +21,7 @@ impl PluginFactory for TimelineTrackPlugFactory {
         PluginDescriptor {
             id: TIMELINE_TRACK_PLUG_RDN.into(),
             version: "0.1".into(),
-            name: "Sample Browser".into(),
+            name: "Timeline Track".into(),
             vendor: "Meadowlark".into(),
             description: String::new(),
             url: String::new(),
@@ -36,15 +42,100 @@ impl PluginFactory for TimelineTrackPlugFactory {
     }
 }
 
-pub struct TimelineTrackPlugHandle {}
+/// An audio clip on a timeline track, in frames at the sample rate of the
+/// project.
+pub struct TimelineAudioClip {
+    pub source: PcmSource,
+
+    /// The frame on the timeline where the clip starts.
+    pub timeline_start: u64,
+
+    /// The length of the clip in frames.
+    pub length: u64,
+
+    /// The frame in the audio file that is played at the start of the clip.
+    pub pcm_start: u64,
+
+    pub fade_in_frames: u64,
+    pub fade_out_frames: u64,
+}
+
+impl TimelineAudioClip {
+    pub fn new(
+        source: PcmSource,
+        timeline_start: u64,
+        length: u64,
+        clip_start_offset: SuperFrames,
+        fade_in: Seconds,
+        fade_out: Seconds,
+        sample_rate: SampleRate,
+    ) -> Self {
+        Self {
+            source,
+            timeline_start,
+            length,
+            pcm_start: clip_start_offset.to_nearest_frame_round(sample_rate).0,
+            fade_in_frames: fade_in.to_nearest_frame_round(sample_rate).0,
+            fade_out_frames: fade_out.to_nearest_frame_round(sample_rate).0,
+        }
+    }
+
+    /// The gain of the fades at the given frame relative to the start of the
+    /// clip.
+    fn fade_gain(&self, frame: u64) -> f32 {
+        let mut gain = 1.0;
+
+        if frame < self.fade_in_frames {
+            gain *= frame as f32 / self.fade_in_frames as f32;
+        }
+
+        let frames_left = self.length.saturating_sub(frame);
+        if frames_left < self.fade_out_frames {
+            gain *= frames_left as f32 / self.fade_out_frames as f32;
+        }
+
+        gain
+    }
+}
+
+pub struct TimelineTrackPlugHandle {
+    to_audio_thread_tx: Producer<ProcessMsg>,
+    host_request: HostRequestChannelSender,
+    coll_handle: basedrop::Handle,
+    underruns: UnderrunCounter,
+}
+
+impl TimelineTrackPlugHandle {
+    /// Replace all of the clips on this track.
+    pub fn set_clips(&mut self, clips: Vec<TimelineAudioClip>) {
+        self.send(ProcessMsg::SetClips { clips: Owned::new(&self.coll_handle, clips) });
+        self.host_request.request(HostRequestFlags::PROCESS);
+    }
+
+    /// Returns the number of times a streamed clip could not be read from
+    /// disk in time since this was last called.
+    pub fn take_underruns(&mut self) -> u32 {
+        self.underruns.take()
+    }
+
+    fn send(&mut self, msg: ProcessMsg) {
+        if let Err(e) = self.to_audio_thread_tx.push(msg) {
+            log::error!("Timeline track plugin failed to send message: {}", e);
+        }
+    }
+}
+
+enum ProcessMsg {
+    SetClips { clips: Owned<Vec<TimelineAudioClip>> },
+}
 
 pub struct TimelineTrackPlugMainThread {
-    host_request_channel: HostRequestChannelSender,
+    host_request: HostRequestChannelSender,
 }
 
 impl TimelineTrackPlugMainThread {
-    pub fn new(host_request_channel: HostRequestChannelSender) -> Self {
-        Self { host_request_channel }
+    pub fn new(host_request: HostRequestChannelSender) -> Self {
+        Self { host_request }
     }
 }
 
@@ -56,9 +147,28 @@ impl PluginMainThread for TimelineTrackPlugMainThread {
         max_frames: u32,
         coll_handle: &basedrop::Handle,
     ) -> Result<PluginActivatedInfo, String> {
+        let (to_audio_thread_tx, from_handle_rx) = RingBuffer::<ProcessMsg>::new(MSG_BUFFER_SIZE);
+        let from_handle_rx = Owned::new(coll_handle, from_handle_rx);
+
+        let clip_buf_l = Owned::new(coll_handle, vec![0.0; max_frames as usize]);
+        let clip_buf_r = Owned::new(coll_handle, vec![0.0; max_frames as usize]);
+
+        let underruns = UnderrunCounter::default();
+
         Ok(PluginActivatedInfo {
-            audio_thread: Box::new(TimelineTrackPlugAudioThread {}),
-            internal_handle: Some(Box::new(TimelineTrackPlugHandle {})),
+            audio_thread: Box::new(TimelineTrackPlugAudioThread {
+                from_handle_rx,
+                clips: Owned::new(coll_handle, Vec::new()),
+                clip_buf_l,
+                clip_buf_r,
+                underruns: underruns.clone(),
+            }),
+            internal_handle: Some(Box::new(TimelineTrackPlugHandle {
+                to_audio_thread_tx,
+                host_request: self.host_request.clone(),
+                coll_handle: coll_handle.clone(),
+                underruns,
+            })),
         })
     }
 
@@ -67,7 +177,30 @@ impl PluginMainThread for TimelineTrackPlugMainThread {
     }
 }
 
-pub struct TimelineTrackPlugAudioThread {}
+pub struct TimelineTrackPlugAudioThread {
+    from_handle_rx: Owned<Consumer<ProcessMsg>>,
+
+    clips: Owned<Vec<TimelineAudioClip>>,
+
+    clip_buf_l: Owned<Vec<f32>>,
+    clip_buf_r: Owned<Vec<f32>>,
+
+    underruns: UnderrunCounter,
+}
+
+impl TimelineTrackPlugAudioThread {
+    fn poll(&mut self) {
+        while let Ok(msg) = self.from_handle_rx.pop() {
+            match msg {
+                ProcessMsg::SetClips { clips } => {
+                    // The old clips are dropped by the collector, not on the
+                    // audio thread.
+                    self.clips = clips;
+                }
+            }
+        }
+    }
+}
 
 impl PluginAudioThread for TimelineTrackPlugAudioThread {
     fn start_processing(&mut self) -> Result<(), ()> {
@@ -83,8 +216,60 @@ impl PluginAudioThread for TimelineTrackPlugAudioThread {
         in_events: &EventBuffer,
         _out_events: &mut EventBuffer,
     ) -> ProcessStatus {
+        self.poll();
+
+        let (mut buf_l, mut buf_r) = buffers.audio_out[0].stereo_f32_mut().unwrap();
+
+        let buf_l_part = &mut buf_l[0..proc_info.frames];
+        let buf_r_part = &mut buf_r[0..proc_info.frames];
+
+        buf_l_part.fill(0.0);
+        buf_r_part.fill(0.0);
+
+        // The transport info is the audio thread side of the `TransportHandle`
+        // that the UI uses to control playback.
+        let transport = &proc_info.transport;
+        if !transport.is_playing() {
+            return ProcessStatus::Sleep;
+        }
+
+        let block_start = transport.playhead_frame();
+        let block_end = block_start + proc_info.frames as u64;
+
+        for clip in self.clips.iter_mut() {
+            let clip_end = clip.timeline_start + clip.length;
+            if clip_end <= block_start || clip.timeline_start >= block_end {
+                continue;
+            }
+
+            // The part of this block that the clip covers.
+            let start = clip.timeline_start.max(block_start);
+            let end = clip_end.min(block_end);
+            let buf_offset = (start - block_start) as usize;
+            let frames = (end - start) as usize;
+
+            // The frame in the clip at the start of that part.
+            let clip_frame = start - clip.timeline_start;
+
+            let clip_buf_l = &mut self.clip_buf_l[0..frames];
+            let clip_buf_r = &mut self.clip_buf_r[0..frames];
+
+            if !clip.source.fill_stereo_f32(clip.pcm_start + clip_frame, clip_buf_l, clip_buf_r) {
+                self.underruns.increment();
+            }
+
+            for i in 0..frames {
+                let gain = clip.fade_gain(clip_frame + i as u64);
+
+                buf_l_part[buf_offset + i] += clip_buf_l[i] * gain;
+                buf_r_part[buf_offset + i] += clip_buf_r[i] * gain;
+            }
+        }
+
         ProcessStatus::Continue
     }
 
-    fn param_flush(&mut self, in_events: &EventBuffer, _out_events: &mut EventBuffer) {}
+    fn param_flush(&mut self, in_events: &EventBuffer, _out_events: &mut EventBuffer) {
+        self.poll();
+    }
 }
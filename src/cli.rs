@@ -0,0 +1,468 @@
+//! The command line interface.
+//!
+//! `meadowlark render <PROJECT> <OUTPUT>` renders a saved project to an audio
+//! file without opening a window, so that projects can be rendered from
+//! scripts.
+
+use crossbeam::channel::Receiver;
+use dropseed::plugin::{HostInfo, PluginInstanceID};
+use dropseed::transport::TransportHandle;
+use dropseed::{
+    ActivateEngineSettings, DSEngineEvent, DSEngineHandle, DSEngineRequest, DSSaveState,
+    ModifyGraphRes, PluginActivationStatus,
+};
+use meadowlark_core_types::time::{MusicalTime, SampleRate};
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::backend::disk_stream::PcmSource;
+use crate::backend::metronome_plug::{
+    MetronomePlugFactory, MetronomePlugHandle, METRONOME_PLUG_RDN,
+};
+use crate::backend::render::{
+    self, OfflineRenderHandle, RenderBitDepth, RenderError, RenderFormat, RenderOutcome,
+    RenderSettings,
+};
+use crate::backend::resource_loader::ResourceLoader;
+use crate::backend::sample_browser_plug::SampleBrowserPlugFactory;
+use crate::backend::system_io::{
+    SystemIOStreamHandle, DEFAULT_GRAPH_IN_CHANNELS, DEFAULT_GRAPH_OUT_CHANNELS,
+    DEFAULT_NULL_SAMPLE_RATE,
+};
+use crate::backend::timeline_track::{TimelineTrackPlugFactory, TIMELINE_TRACK_PLUG_RDN};
+use crate::ui::state::{
+    find_missing_files, load_project, ProjectLoadError, RenderSettingsState, TimelineTracks,
+    UiState,
+};
+
+const RENDER_USAGE: &str = "\
+Usage: meadowlark render <PROJECT> <OUTPUT> [OPTIONS]
+
+Render a project to a WAV or FLAC file without opening a window.
+
+Options:
+    --sample-rate <HZ>      The sample rate to render at (default: 48000)
+    --bit-depth <BITS>      16, 24, or 32 (32-bit float, WAV only) (default: 24)
+    --tail <SECONDS>        Keep rendering after the end of the project (default: 2)
+    --no-dither             Don't dither when converting to an integer bit depth";
+
+/// How often to check on the engine and the render while waiting.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Run the command line interface, if the arguments ask for it.
+///
+/// Returns the exit code of the process, or `None` if the UI should be started
+/// instead.
+pub fn run(args: &[String]) -> Option<i32> {
+    match args.first().map(|a| a.as_str()) {
+        Some("render") => Some(match render_command(&args[1..]) {
+            Ok(()) => 0,
+            Err(CliError::Usage(msg)) => {
+                eprintln!("error: {}\n\n{}", msg, RENDER_USAGE);
+                2
+            }
+            Err(e) => {
+                eprintln!("error: {}", e);
+                1
+            }
+        }),
+        Some("help") | Some("--help") | Some("-h") => {
+            println!("{}", RENDER_USAGE);
+            Some(0)
+        }
+        _ => None,
+    }
+}
+
+#[derive(Debug)]
+pub enum CliError {
+    Usage(String),
+    Load(ProjectLoadError),
+    MissingSamples(Vec<PathBuf>),
+    LoadSamples(Vec<(PathBuf, String)>),
+    PluginsFailed(Vec<(String, String)>),
+    EngineDeactivated,
+    Render(RenderError),
+    Cancelled,
+}
+
+impl Error for CliError {}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Usage(msg) => write!(f, "{}", msg),
+            CliError::Load(e) => write!(f, "{}", e),
+            CliError::MissingSamples(paths) => {
+                write!(f, "The project is missing {} sample(s):", paths.len())?;
+                for path in paths.iter() {
+                    write!(f, "\n    {}", path.display())?;
+                }
+                Ok(())
+            }
+            CliError::LoadSamples(samples) => {
+                write!(f, "{} sample(s) in the project failed to load:", samples.len())?;
+                for (path, error) in samples.iter() {
+                    write!(f, "\n    {}: {}", path.display(), error)?;
+                }
+                Ok(())
+            }
+            CliError::PluginsFailed(plugins) => {
+                write!(f, "{} plugin(s) in the project failed to load:", plugins.len())?;
+                for (rdn, error) in plugins.iter() {
+                    write!(f, "\n    {}: {}", rdn, error)?;
+                }
+                Ok(())
+            }
+            CliError::EngineDeactivated => {
+                write!(f, "The engine stopped before the project was rendered")
+            }
+            CliError::Render(e) => write!(f, "{}", e),
+            CliError::Cancelled => write!(f, "The render was cancelled"),
+        }
+    }
+}
+
+struct RenderArgs {
+    project: PathBuf,
+    output: PathBuf,
+    sample_rate: u32,
+    bit_depth: RenderBitDepth,
+    tail_seconds: f64,
+    dither: bool,
+}
+
+fn parse_render_args(args: &[String]) -> Result<RenderArgs, CliError> {
+    let defaults = RenderSettingsState::default();
+
+    let mut paths = Vec::new();
+    let mut sample_rate = DEFAULT_NULL_SAMPLE_RATE;
+    let mut bit_depth = defaults.bit_depth;
+    let mut tail_seconds = defaults.tail_seconds;
+    let mut dither = defaults.dither;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next().ok_or_else(|| CliError::Usage(format!("{} needs a value", name)))
+        };
+
+        match arg.as_str() {
+            "--sample-rate" => {
+                let v = value("--sample-rate")?;
+                sample_rate = v
+                    .parse()
+                    .ok()
+                    .filter(|sr| *sr > 0)
+                    .ok_or_else(|| CliError::Usage(format!("Invalid sample rate {:?}", v)))?;
+            }
+            "--bit-depth" => {
+                bit_depth = match value("--bit-depth")?.as_str() {
+                    "16" => RenderBitDepth::Int16,
+                    "24" => RenderBitDepth::Int24,
+                    "32" => RenderBitDepth::Float32,
+                    v => return Err(CliError::Usage(format!("Invalid bit depth {:?}", v))),
+                };
+            }
+            "--tail" => {
+                let v = value("--tail")?;
+                tail_seconds = v
+                    .parse()
+                    .ok()
+                    .filter(|t: &f64| *t >= 0.0)
+                    .ok_or_else(|| CliError::Usage(format!("Invalid tail length {:?}", v)))?;
+            }
+            "--no-dither" => dither = false,
+            _ if arg.starts_with("--") => {
+                return Err(CliError::Usage(format!("Unknown option {:?}", arg)));
+            }
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    if paths.len() != 2 {
+        return Err(CliError::Usage(String::from("Expected a project file and an output file")));
+    }
+    let output = paths.pop().unwrap();
+    let project = paths.pop().unwrap();
+
+    Ok(RenderArgs { project, output, sample_rate, bit_depth, tail_seconds, dither })
+}
+
+/// Load a project, check that everything it uses is available, and render it
+/// to a file.
+fn render_command(args: &[String]) -> Result<(), CliError> {
+    let args = parse_render_args(args)?;
+
+    let format = RenderFormat::from_path(&args.output).ok_or_else(|| {
+        CliError::Usage(format!("Cannot render to {:?}: use a .wav or .flac file", &args.output))
+    })?;
+
+    let loaded = load_project(&args.project).map_err(CliError::Load)?;
+
+    let missing = find_missing_files(&loaded.state);
+    if !missing.is_empty() {
+        return Err(CliError::MissingSamples(missing.into_iter().map(|m| m.path).collect()));
+    }
+
+    let settings = RenderSettings {
+        path: args.output,
+        format,
+        bit_depth: args.bit_depth,
+        sample_rate: args.sample_rate,
+        num_channels: DEFAULT_GRAPH_OUT_CHANNELS,
+        dither: args.dither,
+        num_frames: loaded
+            .state
+            .timeline_grid
+            .tempo_map
+            .musical_to_frames(loaded.state.timeline_grid.project_length.get(), args.sample_rate),
+        tail_frames: (args.tail_seconds * f64::from(args.sample_rate)).round() as u64,
+        stems: Vec::new(),
+    };
+
+    // The render stream runs the engine on its own thread, the same way as the
+    // null backend, so no audio device is needed.
+    let (stream_handle, render_handle) =
+        render::spawn_offline_render_stream(settings, DEFAULT_GRAPH_IN_CHANNELS)
+            .map_err(CliError::Render)?;
+
+    let (mut ds_handle, engine_rx) = DSEngineHandle::new(
+        HostInfo::new(String::from("Meadowlark"), String::from("0.1.0"), None, None),
+        vec![
+            Box::new(SampleBrowserPlugFactory),
+            Box::new(MetronomePlugFactory),
+            Box::new(TimelineTrackPlugFactory),
+        ],
+    );
+
+    // The plugins in the project can only be found once the plugin
+    // directories have been scanned.
+    ds_handle.send(DSEngineRequest::RescanPluginDirectories);
+
+    ds_handle.send(DSEngineRequest::ActivateEngine(Box::new(ActivateEngineSettings {
+        sample_rate: stream_handle.sample_rate(),
+        min_frames: stream_handle.min_frames(),
+        max_frames: stream_handle.max_frames(),
+        num_audio_in_channels: stream_handle.graph_in_channels(),
+        num_audio_out_channels: stream_handle.graph_out_channels(),
+        ..ActivateEngineSettings::default()
+    })));
+
+    let mut headless = HeadlessProject {
+        resource_loader: ResourceLoader::new(stream_handle.sample_rate()),
+        state: loaded.state,
+        timeline_tracks: TimelineTracks::default(),
+        graph_out_node_id: None,
+        sample_rate: stream_handle.sample_rate(),
+    };
+
+    let res = run_headless_render(
+        &mut ds_handle,
+        &engine_rx,
+        stream_handle,
+        &render_handle,
+        loaded.engine_save_state,
+        &mut headless,
+    );
+
+    ds_handle.send(DSEngineRequest::DeactivateEngine);
+
+    res
+}
+
+/// The project being rendered, and the parts of the audio graph that play it.
+struct HeadlessProject {
+    state: UiState,
+    resource_loader: ResourceLoader,
+    timeline_tracks: TimelineTracks,
+    graph_out_node_id: Option<PluginInstanceID>,
+    sample_rate: SampleRate,
+}
+
+impl HeadlessProject {
+    /// Add the timeline tracks that are missing from the audio graph, the same
+    /// way the UI does.
+    fn update_graph(&mut self, ds_handle: &mut DSEngineHandle) {
+        if let Some(graph_out_node_id) = &self.graph_out_node_id {
+            self.timeline_tracks.update_graph(
+                ds_handle,
+                graph_out_node_id,
+                self.state.channels.len(),
+            );
+        }
+    }
+
+    /// Send the clips on the timeline to the timeline tracks.
+    ///
+    /// Every sample is loaded before the render starts, so this blocks until
+    /// they have all been decoded.
+    fn sync_clips(&mut self) -> Result<(), CliError> {
+        let Self { state, resource_loader, timeline_tracks, sample_rate, .. } = self;
+
+        let mut failed = Vec::new();
+        timeline_tracks.sync_clips(state, *sample_rate, |key, _| {
+            match resource_loader.load_pcm(key) {
+                (pcm, Ok(())) => Some(PcmSource::Ram(pcm)),
+                (_, Err(e)) => {
+                    failed.push((key.path.clone(), e.to_string()));
+                    None
+                }
+            }
+        });
+
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(CliError::LoadSamples(failed))
+        }
+    }
+
+    /// Keep track of the new timeline tracks, and silence the metronome,
+    /// which is never heard in renders.
+    fn on_audio_graph_modified(&mut self, mut res: ModifyGraphRes) {
+        for new_plugin in res.new_plugins.drain(..) {
+            let rdn = new_plugin.plugin_id.rdn();
+
+            if rdn.as_str() == TIMELINE_TRACK_PLUG_RDN {
+                self.timeline_tracks
+                    .on_track_added(new_plugin.plugin_id.clone(), new_plugin.status);
+            } else if rdn.as_str() == METRONOME_PLUG_RDN {
+                if let PluginActivationStatus::Activated { mut new_handle, .. } = new_plugin.status
+                {
+                    if let Some(handle) = new_handle
+                        .internal
+                        .as_mut()
+                        .and_then(|h| h.downcast_mut::<MetronomePlugHandle>())
+                    {
+                        handle.set_enabled(false);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn run_headless_render(
+    ds_handle: &mut DSEngineHandle,
+    engine_rx: &Receiver<DSEngineEvent>,
+    mut stream_handle: SystemIOStreamHandle,
+    render_handle: &OfflineRenderHandle,
+    mut engine_save_state: Option<DSSaveState>,
+    project: &mut HeadlessProject,
+) -> Result<(), CliError> {
+    let mut transport_handle = None;
+    let mut started = false;
+    let mut last_reported_percent = None;
+
+    loop {
+        for msg in engine_rx.try_iter() {
+            match msg {
+                DSEngineEvent::EngineActivated(event) => {
+                    stream_handle.engine_activated(event.audio_thread);
+                    transport_handle = Some(event.transport_handle);
+                    project.graph_out_node_id = Some(event.graph_out_node_id.clone());
+                    project.sample_rate = event.sample_rate;
+
+                    if let Some(save_state) = engine_save_state.take() {
+                        ds_handle.send(DSEngineRequest::RestoreFromSaveState(save_state));
+                    } else {
+                        // The render starts once the timeline tracks have been
+                        // added.
+                        project.update_graph(ds_handle);
+                    }
+                }
+                DSEngineEvent::AudioGraphCleared => {
+                    project.timeline_tracks.clear();
+                }
+                DSEngineEvent::AudioGraphModified(res) if !started => {
+                    let failed = failed_plugins(&res);
+                    if !failed.is_empty() {
+                        render_handle.cancel();
+                        wait_for_outcome(render_handle);
+                        return Err(CliError::PluginsFailed(failed));
+                    }
+
+                    project.on_audio_graph_modified(res);
+                    project.update_graph(ds_handle);
+
+                    if project.timeline_tracks.is_ready() {
+                        if let Err(e) = project.sync_clips() {
+                            render_handle.cancel();
+                            wait_for_outcome(render_handle);
+                            return Err(e);
+                        }
+
+                        start_render(&mut transport_handle, render_handle);
+                        started = true;
+                    }
+                }
+                DSEngineEvent::EngineDeactivated(_) => {
+                    render_handle.cancel();
+                    wait_for_outcome(render_handle);
+                    return Err(CliError::EngineDeactivated);
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(outcome) = render_handle.poll() {
+            return match outcome {
+                RenderOutcome::Finished { path } => {
+                    eprintln!("Rendered {}", path.display());
+                    Ok(())
+                }
+                RenderOutcome::Cancelled => Err(CliError::Cancelled),
+                RenderOutcome::Failed(e) => Err(CliError::Render(e)),
+            };
+        }
+
+        if started {
+            let percent = (render_handle.progress() * 10.0) as u32 * 10;
+            if last_reported_percent != Some(percent) {
+                eprintln!("Rendering... {}%", percent);
+                last_reported_percent = Some(percent);
+            }
+        }
+
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Play the project from the start and start writing the output.
+fn start_render(
+    transport_handle: &mut Option<TransportHandle>,
+    render_handle: &OfflineRenderHandle,
+) {
+    if let Some(transport_handle) = transport_handle {
+        transport_handle.seek_to(MusicalTime::from_beats(0));
+        transport_handle.set_playing(true);
+    }
+    render_handle.start();
+}
+
+/// Returns the reverse-domain-name and error of every plugin in the restored
+/// audio graph that failed to load or activate.
+fn failed_plugins(res: &ModifyGraphRes) -> Vec<(String, String)> {
+    res.new_plugins
+        .iter()
+        .filter_map(|new_plugin| match &new_plugin.status {
+            PluginActivationStatus::LoadError(e) => {
+                Some((new_plugin.plugin_id.rdn().as_str().to_string(), e.to_string()))
+            }
+            PluginActivationStatus::ActivationError(e) => {
+                Some((new_plugin.plugin_id.rdn().as_str().to_string(), e.to_string()))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Wait for the render thread to clean up after being cancelled.
+fn wait_for_outcome(render_handle: &OfflineRenderHandle) {
+    while render_handle.poll().is_none() {
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
@@ -0,0 +1,8 @@
+// TODO: Remove these
+#![allow(unused_variables)]
+#![allow(dead_code)]
+
+pub mod backend;
+pub mod cli;
+pub mod ui;
+pub mod util;
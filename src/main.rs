@@ -1,17 +1,16 @@
-// TODO: Remove these
-#![allow(unused_variables)]
-#![allow(dead_code)]
-
 use log::LevelFilter;
 use std::error::Error;
 
-mod backend;
-mod ui;
-mod util;
+use meadowlark::{cli, ui};
 
 fn main() -> Result<(), Box<dyn Error>> {
     setup_logging()?;
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(exit_code) = cli::run(&args) {
+        std::process::exit(exit_code);
+    }
+
     ui::run_ui()
 }
 
@@ -71,6 +71,71 @@ pub fn run_ui() -> Result<(), Box<dyn Error>> {
                     |cx| Label::new(cx, "LOAD"),
                 )
                 .width(Pixels(100.0));
+
+                Button::new(
+                    cx,
+                    |cx| {
+                        cx.emit(UiEvent::CollectAndSaveProject);
+                    },
+                    |cx| Label::new(cx, "COLLECT"),
+                )
+                .width(Pixels(100.0));
+
+                Button::new(
+                    cx,
+                    |cx| {
+                        cx.emit(UiEvent::ExportProjectArchive);
+                    },
+                    |cx| Label::new(cx, "ARCHIVE"),
+                )
+                .width(Pixels(100.0));
+
+                Button::new(
+                    cx,
+                    |cx| {
+                        cx.emit(UiEvent::RelinkMissingFiles);
+                    },
+                    |cx| Label::new(cx, "RELINK"),
+                )
+                .width(Pixels(100.0));
+
+                Button::new(
+                    cx,
+                    |cx| {
+                        cx.emit(UiEvent::RenderProject);
+                    },
+                    |cx| Label::new(cx, "RENDER"),
+                )
+                .width(Pixels(100.0));
+
+                Button::new(
+                    cx,
+                    |cx| {
+                        cx.emit(UiEvent::ExportStems);
+                    },
+                    |cx| Label::new(cx, "STEMS"),
+                )
+                .width(Pixels(100.0));
+
+                Button::new(
+                    cx,
+                    |cx| {
+                        cx.emit(UiEvent::CancelRender);
+                    },
+                    |cx| Label::new(cx, "CANCEL"),
+                )
+                .width(Pixels(100.0));
+
+                Label::new(
+                    cx,
+                    UiData::render_progress.map(|p| match p {
+                        Some(p) => format!("Rendering {:.0}%", p * 100.0),
+                        None => String::new(),
+                    }),
+                )
+                .width(Pixels(120.0))
+                .class("small");
+
                 Label::new(cx, "File").width(Pixels(50.0)).child_space(Stretch(1.0)).class("small");
                 Label::new(cx, "Edit").width(Pixels(50.0)).child_space(Stretch(1.0)).class("small");
                 Label::new(cx, "View").width(Pixels(50.0)).child_space(Stretch(1.0)).class("small");
@@ -79,7 +144,6 @@ pub fn run_ui() -> Result<(), Box<dyn Error>> {
             .class("menu_bar");
             top_bar(cx);
             HStack::new(cx, |cx| {
-
                 browser2::Browser::new().view(cx);
 
                 channels(cx);
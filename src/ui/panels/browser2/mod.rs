@@ -182,6 +182,35 @@ impl FileViewMenu {
             })
             .height(Auto);
         });
+
+        // The pitch that samples are auditioned at.
+        HStack::new(cx, |cx| {
+            Label::new(cx, "Pitch :");
+            Button::new(
+                cx,
+                |cx| cx.emit(BrowserEvent::TransposeAudition(-1)),
+                |cx| Label::new(cx, "-"),
+            );
+            Label::new(
+                cx,
+                UiData::state.then(UiState::browser.then(BrowserState::audition_semitones)).map(
+                    |semitones| {
+                        if *semitones > 0 {
+                            format!("+{} st", semitones)
+                        } else {
+                            format!("{} st", semitones)
+                        }
+                    },
+                ),
+            );
+            Button::new(
+                cx,
+                |cx| cx.emit(BrowserEvent::TransposeAudition(1)),
+                |cx| Label::new(cx, "+"),
+            );
+        })
+        .height(Auto)
+        .col_between(Pixels(4.0));
     }
 }
 
@@ -24,3 +24,6 @@ pub use timeline::*;
 
 pub mod piano_roll;
 pub use piano_roll::*;
+
+pub mod settings;
+pub use settings::*;
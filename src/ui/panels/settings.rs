@@ -0,0 +1,260 @@
+use vizia::prelude::*;
+
+use crate::backend::system_io::SystemIOBackend;
+use crate::ui::state::{
+    AudioSettingsState, AutosaveSettings, MemorySettings, UiData, UiEvent, BYTES_PER_MIB,
+};
+
+/// The app settings, which are not part of the project.
+pub fn settings_dialog(cx: &mut Context) {
+    VStack::new(cx, |cx| {
+        audio_settings(cx);
+        autosave_settings(cx);
+        memory_settings(cx);
+    })
+    .class("settings_dialog");
+}
+
+fn audio_settings(cx: &mut Context) {
+    let audio = UiData::audio_settings;
+
+    Label::new(cx, "AUDIO").class("settings_heading");
+
+    // Each button steps through the available choices, starting with the
+    // default of the system.
+    HStack::new(cx, |cx| {
+        Label::new(cx, "Backend");
+        Button::new(
+            cx,
+            |cx| {
+                if let Some(data) = cx.data::<UiData>() {
+                    let backend = data.audio_settings.next_backend();
+                    cx.emit(UiEvent::SetAudioBackend(backend));
+                }
+            },
+            |cx| {
+                Label::new(
+                    cx,
+                    audio.then(AudioSettingsState::backend).map(|backend| {
+                        String::from(match backend {
+                            SystemIOBackend::Cpal => "System",
+                            SystemIOBackend::Null => "None",
+                        })
+                    }),
+                )
+            },
+        );
+    })
+    .class("settings_row");
+
+    HStack::new(cx, |cx| {
+        Label::new(cx, "Host");
+        Button::new(
+            cx,
+            |cx| {
+                if let Some(data) = cx.data::<UiData>() {
+                    let host = data.audio_settings.next_host();
+                    cx.emit(UiEvent::SetAudioHost(host));
+                }
+            },
+            |cx| Label::new(cx, audio.then(AudioSettingsState::host).map(choice_name)),
+        );
+    })
+    .class("settings_row");
+
+    HStack::new(cx, |cx| {
+        Label::new(cx, "Output");
+        Button::new(
+            cx,
+            |cx| {
+                if let Some(data) = cx.data::<UiData>() {
+                    let device = data.audio_settings.next_output_device();
+                    cx.emit(UiEvent::SetAudioOutputDevice(device));
+                }
+            },
+            |cx| Label::new(cx, audio.then(AudioSettingsState::output_device).map(choice_name)),
+        );
+    })
+    .class("settings_row");
+
+    HStack::new(cx, |cx| {
+        Label::new(cx, "Input");
+        Button::new(
+            cx,
+            |cx| {
+                let enabled = cx.data::<UiData>().map_or(false, |d| d.audio_settings.input_enabled);
+                cx.emit(UiEvent::SetAudioInputEnabled(!enabled));
+            },
+            |cx| {
+                Label::new(
+                    cx,
+                    audio
+                        .then(AudioSettingsState::input_enabled)
+                        .map(|enabled| String::from(if *enabled { "ON" } else { "OFF" })),
+                )
+            },
+        )
+        .toggle_class("selected", audio.then(AudioSettingsState::input_enabled));
+        Button::new(
+            cx,
+            |cx| {
+                if let Some(data) = cx.data::<UiData>() {
+                    let device = data.audio_settings.next_input_device();
+                    cx.emit(UiEvent::SetAudioInputDevice(device));
+                }
+            },
+            |cx| Label::new(cx, audio.then(AudioSettingsState::input_device).map(choice_name)),
+        );
+    })
+    .class("settings_row");
+
+    HStack::new(cx, |cx| {
+        Label::new(cx, "Sample Rate");
+        Button::new(
+            cx,
+            |cx| {
+                if let Some(data) = cx.data::<UiData>() {
+                    let sample_rate = data.audio_settings.next_sample_rate();
+                    cx.emit(UiEvent::SetAudioSampleRate(sample_rate));
+                }
+            },
+            |cx| {
+                Label::new(
+                    cx,
+                    audio.then(AudioSettingsState::sample_rate).map(
+                        |sample_rate| match sample_rate {
+                            Some(sample_rate) => format!("{} Hz", sample_rate),
+                            None => String::from("Default"),
+                        },
+                    ),
+                )
+            },
+        );
+    })
+    .class("settings_row");
+
+    HStack::new(cx, |cx| {
+        Label::new(cx, "Buffer Size");
+        Button::new(
+            cx,
+            |cx| {
+                if let Some(data) = cx.data::<UiData>() {
+                    let buffer_size = data.audio_settings.next_buffer_size();
+                    cx.emit(UiEvent::SetAudioBufferSize(buffer_size));
+                }
+            },
+            |cx| {
+                Label::new(
+                    cx,
+                    audio.then(AudioSettingsState::buffer_size).map(
+                        |buffer_size| match buffer_size {
+                            Some(buffer_size) => format!("{} frames", buffer_size),
+                            None => String::from("Default"),
+                        },
+                    ),
+                )
+            },
+        );
+    })
+    .class("settings_row");
+
+    HStack::new(cx, |cx| {
+        Button::new(
+            cx,
+            |cx| cx.emit(UiEvent::RefreshAudioDevices),
+            |cx| Label::new(cx, "Refresh Devices"),
+        );
+        Button::new(cx, |cx| cx.emit(UiEvent::ReconnectAudio), |cx| Label::new(cx, "Reconnect"));
+    })
+    .class("settings_row");
+}
+
+fn choice_name(choice: &Option<String>) -> String {
+    choice.clone().unwrap_or_else(|| String::from("Default"))
+}
+
+fn autosave_settings(cx: &mut Context) {
+    let autosave = UiData::autosave_settings;
+
+    Label::new(cx, "AUTOSAVE").class("settings_heading");
+
+    HStack::new(cx, |cx| {
+        Label::new(cx, "Enabled");
+        Button::new(
+            cx,
+            |cx| {
+                let enabled = cx.data::<UiData>().map_or(true, |d| d.autosave_settings.enabled);
+                cx.emit(UiEvent::SetAutosaveEnabled(!enabled));
+            },
+            |cx| {
+                Label::new(
+                    cx,
+                    autosave
+                        .then(AutosaveSettings::enabled)
+                        .map(|enabled| String::from(if *enabled { "ON" } else { "OFF" })),
+                )
+            },
+        )
+        .toggle_class("selected", autosave.then(AutosaveSettings::enabled));
+    })
+    .class("settings_row");
+
+    HStack::new(cx, |cx| {
+        Label::new(cx, "Interval");
+        Button::new(
+            cx,
+            |cx| cx.emit(UiEvent::CycleAutosaveInterval),
+            |cx| {
+                Label::new(
+                    cx,
+                    autosave.then(AutosaveSettings::interval_secs).map(|secs| {
+                        if secs % 60 == 0 {
+                            format!("{} min", secs / 60)
+                        } else {
+                            format!("{} s", secs)
+                        }
+                    }),
+                )
+            },
+        );
+    })
+    .class("settings_row");
+}
+
+fn memory_settings(cx: &mut Context) {
+    Label::new(cx, "MEMORY").class("settings_heading");
+
+    HStack::new(cx, |cx| {
+        Label::new(cx, "Sample Budget");
+        Button::new(
+            cx,
+            |cx| cx.emit(UiEvent::CycleMemoryBudget),
+            |cx| {
+                Label::new(
+                    cx,
+                    UiData::memory_settings
+                        .then(MemorySettings::memory_budget_mib)
+                        .map(|mib| format!("{} MiB", mib)),
+                )
+            },
+        );
+    })
+    .class("settings_row");
+
+    // Samples that are still in use are never dropped, so this can go over
+    // the budget.
+    HStack::new(cx, |cx| {
+        Label::new(cx, "Loaded");
+        Label::new(
+            cx,
+            UiData::resource_stats.map(|stats| {
+                format!(
+                    "{} MiB in {} samples",
+                    stats.bytes_loaded / BYTES_PER_MIB,
+                    stats.num_loaded
+                )
+            }),
+        );
+    })
+    .class("settings_row");
+}
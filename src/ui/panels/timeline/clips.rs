@@ -0,0 +1,313 @@
+use super::grid::{BEAT_WIDTH_PX, TIMELINE_DEFAULT_OFFSET, TIMELINE_GAP_BETWEEN_LANES};
+use super::lanes::DEFAULT_LANE_HEIGHT_PX;
+use crate::ui::state::{ClipEvent, ClipState, TimelineGridState, UiData};
+use meadowlark_core_types::time::MusicalTime;
+use vizia::{
+    prelude::*,
+    vg::{Align, Baseline, Paint, Path},
+};
+
+/// The width of the area at either edge of a clip that resizes the clip when
+/// dragged.
+const RESIZE_HANDLE_WIDTH_PX: f32 = 6.0;
+
+/// Dragged clips snap to this fraction of a beat. Hold ALT to drag without
+/// snapping.
+const SNAP_DIVISION: f64 = 4.0;
+
+/// The shortest a clip can be resized to, in beats.
+const MIN_CLIP_LENGTH_BEATS: f64 = 1.0 / SNAP_DIVISION;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DragMode {
+    /// Move the whole clip. `grab_offset` is the distance in beats between
+    /// the start of the clip and the point where it was grabbed.
+    Move {
+        grab_offset: f64,
+    },
+    ResizeStart,
+    ResizeEnd,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ClipDrag {
+    /// The index of the clip in `UiState::clips`.
+    index: usize,
+    mode: DragMode,
+}
+
+/// The position of the clips and lanes in the view, in logical pixels.
+struct ClipLayout {
+    left_start: f64,
+    beat_width: f64,
+    /// The top and height of every lane.
+    lanes: Vec<(f32, f32)>,
+}
+
+impl ClipLayout {
+    fn new(timeline_grid: &TimelineGridState) -> Self {
+        let zoom_y = timeline_grid.vertical_zoom_level as f32;
+
+        let mut top = 0.0;
+        let lanes = timeline_grid
+            .lane_states
+            .lanes
+            .iter()
+            .map(|lane| {
+                let height = DEFAULT_LANE_HEIGHT_PX
+                    * lane.height.unwrap_or(timeline_grid.lane_height) as f32
+                    * zoom_y;
+                let lane = (top, height);
+                top += height + TIMELINE_GAP_BETWEEN_LANES * zoom_y;
+                lane
+            })
+            .collect();
+
+        Self {
+            left_start: timeline_grid.left_start.get().as_beats_f64(),
+            beat_width: f64::from(BEAT_WIDTH_PX) * timeline_grid.horizontal_zoom_level,
+            lanes,
+        }
+    }
+
+    fn x(&self, beats: f64) -> f32 {
+        TIMELINE_DEFAULT_OFFSET + ((beats - self.left_start) * self.beat_width) as f32
+    }
+
+    fn beats(&self, x: f32) -> f64 {
+        self.left_start + f64::from(x - TIMELINE_DEFAULT_OFFSET) / self.beat_width
+    }
+
+    /// Returns the lane under `y`, or the nearest lane if `y` is above or
+    /// below all of them.
+    fn lane_at(&self, y: f32) -> Option<u32> {
+        let last = self.lanes.len().checked_sub(1)?;
+        let index = self.lanes.iter().position(|(top, height)| y < top + height).unwrap_or(last);
+        Some(index as u32)
+    }
+
+    /// Returns the x, y, width and height of the clip, or `None` if it is not
+    /// on a lane in the view.
+    fn clip_rect(&self, clip: &ClipState) -> Option<(f32, f32, f32, f32)> {
+        let on_lane = clip.timeline_start.on_lane()?;
+        let (top, height) = *self.lanes.get(on_lane.lane_index() as usize)?;
+
+        let start = on_lane.timeline_start().as_beats_f64();
+        let x = self.x(start);
+        let w = self.x(start + clip.length.get().as_beats_f64()) - x;
+
+        Some((x, top, w, height))
+    }
+
+    /// Returns the topmost clip under the given point, along with how it would
+    /// be dragged from there.
+    fn hit_test(&self, clips: &[ClipState], x: f32, y: f32) -> Option<ClipDrag> {
+        clips.iter().enumerate().rev().find_map(|(index, clip)| {
+            let (clip_x, clip_y, clip_w, clip_h) = self.clip_rect(clip)?;
+            if x < clip_x || x > clip_x + clip_w || y < clip_y || y > clip_y + clip_h {
+                return None;
+            }
+
+            // Keep part of very short clips grabbable for moving.
+            let handle_width = RESIZE_HANDLE_WIDTH_PX.min(clip_w / 3.0);
+            let mode = if x < clip_x + handle_width {
+                DragMode::ResizeStart
+            } else if x > clip_x + clip_w - handle_width {
+                DragMode::ResizeEnd
+            } else {
+                let start = clip.timeline_start.on_lane()?.timeline_start().as_beats_f64();
+                DragMode::Move { grab_offset: self.beats(x) - start }
+            };
+
+            Some(ClipDrag { index, mode })
+        })
+    }
+}
+
+fn snap(beats: f64, enabled: bool) -> f64 {
+    let beats = if enabled { (beats * SNAP_DIVISION).round() / SNAP_DIVISION } else { beats };
+    beats.max(0.0)
+}
+
+/// Draws the clips on their lanes and lets the user move and resize them.
+pub struct TimelineClips {
+    drag: Option<ClipDrag>,
+    /// Whether the cursor is over the edge of a clip.
+    over_edge: bool,
+}
+
+impl TimelineClips {
+    pub fn new(cx: &mut Context) -> Handle<Self> {
+        Self { drag: None, over_edge: false }.build(cx, |_| {})
+    }
+
+    /// The position of the cursor relative to this view in logical pixels.
+    fn cursor(cx: &EventContext) -> (f32, f32) {
+        let current = cx.current();
+        let dpi = cx.scale_factor();
+        (
+            (cx.mouse.cursorx - cx.cache.get_posx(current)) / dpi,
+            (cx.mouse.cursory - cx.cache.get_posy(current)) / dpi,
+        )
+    }
+
+    /// Returns the event that applies the current drag at the given cursor
+    /// position.
+    fn drag_event(&self, cx: &EventContext, x: f32, y: f32) -> Option<ClipEvent> {
+        let drag = self.drag?;
+        let ui_data = cx.data::<UiData>()?;
+        let layout = ClipLayout::new(&ui_data.state.timeline_grid);
+        let clip = ui_data.state.clips.get(drag.index)?;
+        let start = clip.timeline_start.on_lane()?.timeline_start().as_beats_f64();
+        let end = clip.timeline_end()?.as_beats_f64();
+
+        let snapping = !cx.modifiers.contains(Modifiers::ALT);
+        let beats = layout.beats(x);
+
+        Some(match drag.mode {
+            DragMode::Move { grab_offset } => ClipEvent::MoveClip {
+                index: drag.index,
+                lane_index: layout.lane_at(y)?,
+                timeline_start: MusicalTime::from_beats_f64(snap(beats - grab_offset, snapping)),
+            },
+            DragMode::ResizeStart => ClipEvent::ResizeClipStart {
+                index: drag.index,
+                timeline_start: MusicalTime::from_beats_f64(
+                    snap(beats, snapping).min(end - MIN_CLIP_LENGTH_BEATS),
+                ),
+            },
+            DragMode::ResizeEnd => ClipEvent::ResizeClipEnd {
+                index: drag.index,
+                timeline_end: MusicalTime::from_beats_f64(
+                    snap(beats, snapping).max(start + MIN_CLIP_LENGTH_BEATS),
+                ),
+            },
+        })
+    }
+}
+
+impl View for TimelineClips {
+    fn event(&mut self, cx: &mut EventContext, event: &mut Event) {
+        event.map(|window_event, meta| match window_event {
+            WindowEvent::MouseDown(button) if *button == MouseButton::Left => {
+                let (x, y) = Self::cursor(cx);
+                self.drag = cx.data::<UiData>().and_then(|ui_data| {
+                    ClipLayout::new(&ui_data.state.timeline_grid).hit_test(
+                        &ui_data.state.clips,
+                        x,
+                        y,
+                    )
+                });
+
+                if self.drag.is_some() {
+                    cx.capture();
+                    cx.lock_cursor_icon();
+                    meta.consume();
+                }
+            }
+
+            WindowEvent::MouseMove(_, _) => {
+                let (x, y) = Self::cursor(cx);
+
+                if self.drag.is_some() {
+                    if let Some(clip_event) = self.drag_event(cx, x, y) {
+                        cx.emit(clip_event);
+                        cx.needs_redraw();
+                    }
+                    return;
+                }
+
+                let over_edge = cx
+                    .data::<UiData>()
+                    .and_then(|ui_data| {
+                        ClipLayout::new(&ui_data.state.timeline_grid).hit_test(
+                            &ui_data.state.clips,
+                            x,
+                            y,
+                        )
+                    })
+                    .map_or(false, |drag| {
+                        matches!(drag.mode, DragMode::ResizeStart | DragMode::ResizeEnd)
+                    });
+
+                if over_edge != self.over_edge {
+                    self.over_edge = over_edge;
+                    cx.emit(WindowEvent::SetCursor(if over_edge {
+                        CursorIcon::EwResize
+                    } else {
+                        CursorIcon::Default
+                    }));
+                }
+            }
+
+            WindowEvent::MouseUp(button) if *button == MouseButton::Left => {
+                if self.drag.take().is_some() {
+                    cx.release();
+                    cx.unlock_cursor_icon();
+                    meta.consume();
+                }
+            }
+
+            _ => {}
+        });
+    }
+
+    fn draw(&self, cx: &mut DrawContext, canvas: &mut Canvas) {
+        let bounds = cx.bounds();
+
+        if let Some(ui_data) = cx.data::<UiData>() {
+            let timeline_grid = &ui_data.state.timeline_grid;
+            let layout = ClipLayout::new(timeline_grid);
+
+            canvas.save();
+            canvas.scissor(bounds.x, bounds.y, bounds.w, bounds.h);
+
+            for clip in ui_data.state.clips.iter() {
+                let (x, y, w, h) = match layout.clip_rect(clip) {
+                    Some(rect) => rect,
+                    None => continue,
+                };
+
+                let lane = clip.timeline_start.on_lane().and_then(|on_lane| {
+                    timeline_grid.lane_states.lanes.get(on_lane.lane_index() as usize)
+                });
+                let color = match lane {
+                    Some(lane) if lane.disabled => vizia::vg::Color::rgb(68, 68, 68),
+                    Some(lane) => match &lane.color {
+                        Some(color) => {
+                            let color: Color = color.clone().into();
+                            vizia::vg::Color::rgb(color.r(), color.g(), color.b())
+                        }
+                        None => vizia::vg::Color::rgb(136, 136, 136),
+                    },
+                    None => vizia::vg::Color::rgb(136, 136, 136),
+                };
+
+                let x = bounds.x + cx.logical_to_physical(x);
+                let y = bounds.y + cx.logical_to_physical(y);
+                let w = cx.logical_to_physical(w);
+                let h = cx.logical_to_physical(h);
+
+                let mut path = Path::new();
+                path.rounded_rect(x, y, w, h, cx.logical_to_physical(2.0));
+                canvas.fill_path(&mut path, Paint::color(color));
+
+                // Clip name
+                canvas.save();
+                canvas.intersect_scissor(x, y, w, h);
+                let mut text_paint = Paint::color(vizia::vg::Color::rgb(30, 30, 30));
+                text_paint.set_text_align(Align::Left);
+                text_paint.set_text_baseline(Baseline::Top);
+                let _ = canvas.fill_text(
+                    x + cx.logical_to_physical(4.0),
+                    y + cx.logical_to_physical(2.0),
+                    &clip.name,
+                    text_paint,
+                );
+                canvas.restore();
+            }
+
+            canvas.restore();
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use super::lanes::DEFAULT_LANE_HEIGHT_PX;
-use crate::ui::state::UiData;
+use crate::ui::state::{TransportEvent, UiData};
+use meadowlark_core_types::time::MusicalTime;
 use vizia::{
     prelude::*,
     vg::{Align, Baseline, Paint, Path},
@@ -7,6 +8,14 @@ use vizia::{
 
 pub const TIMELINE_DEFAULT_OFFSET: f32 = 10.0;
 pub const TIMELINE_GAP_BETWEEN_LANES: f32 = 1.0;
+/// The width of a beat at the default horizontal zoom level.
+pub const BEAT_WIDTH_PX: f32 = 100.0;
+
+/// The color of the playhead line.
+const PLAYHEAD_COLOR: (u8, u8, u8) = (235, 235, 235);
+
+/// The height of the marker track at the bottom of the header.
+const MARKER_TRACK_HEIGHT_PX: f32 = 14.0;
 
 pub struct TimelineGrid;
 
@@ -26,8 +35,7 @@ impl View for TimelineGrid {
             let start = timeline_grid.left_start.get().as_beats_f64();
             let end = timeline_grid.left_start.get().as_beats_f64()
                 + timeline_grid.project_length.get().as_beats_f64();
-            // TODO: Horizontal zoom
-            // let zoom_x = timeline_grid.horizontal_zoom_level;
+            let zoom_x = timeline_grid.horizontal_zoom_level;
             let zoom_y = timeline_grid.vertical_zoom_level;
 
             canvas.save();
@@ -61,29 +69,116 @@ impl View for TimelineGrid {
             }
 
             // Vertical lines
-            let beat_width = 100.0;
-            let mut lane_x = cx.logical_to_physical(TIMELINE_DEFAULT_OFFSET);
-            for index in (start as usize)..=(end as usize) {
-                let mut path = Path::new();
-                path.move_to(bounds.x + lane_x, clip_region.y);
-                path.line_to(bounds.x + lane_x, clip_region.y + clip_region.h);
-                canvas.stroke_path(&mut path, Paint::color(vizia::vg::Color::rgb(10, 10, 10)));
-                lane_x += cx.logical_to_physical(beat_width);
+            let beat_width = BEAT_WIDTH_PX * zoom_x as f32;
+            let beats_to_x = |beats: f64| {
+                bounds.x
+                    + cx.logical_to_physical(
+                        TIMELINE_DEFAULT_OFFSET + ((beats - start) as f32) * beat_width,
+                    )
+            };
+            for bar in timeline_grid.tempo_map.bars(start, end) {
+                for beat in 0..bar.beats {
+                    let x = beats_to_x(bar.start + f64::from(beat) * bar.beat_length);
+
+                    // Bar lines are darker than beat lines.
+                    let color = if beat == 0 {
+                        vizia::vg::Color::rgb(10, 10, 10)
+                    } else {
+                        vizia::vg::Color::rgb(34, 34, 34)
+                    };
+
+                    let mut path = Path::new();
+                    path.move_to(x, clip_region.y);
+                    path.line_to(x, clip_region.y + clip_region.h);
+                    canvas.stroke_path(&mut path, Paint::color(color));
+                }
             }
+
+            // Playhead
+            let playhead_x = beats_to_x(ui_data.state.transport.playhead.get().as_beats_f64());
+            let mut path = Path::new();
+            path.move_to(playhead_x, clip_region.y);
+            path.line_to(playhead_x, clip_region.y + clip_region.h);
+            let (r, g, b) = PLAYHEAD_COLOR;
+            canvas.stroke_path(&mut path, Paint::color(vizia::vg::Color::rgb(r, g, b)));
+
             canvas.restore();
         }
     }
 }
 
-pub struct TimelineGridHeader;
+/// The ruler above the timeline.
+///
+/// Click to move the playhead there, or hold SHIFT and drag to set the loop
+/// range. The markers and regions are shown along the bottom.
+pub struct TimelineGridHeader {
+    /// The position in beats where the current loop range drag started.
+    loop_drag_start: Option<f64>,
+}
 
 impl TimelineGridHeader {
     pub fn new(cx: &mut Context) -> Handle<Self> {
-        Self {}.build(cx, |_| {}).focusable(false).hoverable(false)
+        Self { loop_drag_start: None }.build(cx, |_| {}).focusable(false)
+    }
+
+    /// The position in beats under the cursor.
+    fn cursor_beats(cx: &EventContext) -> Option<f64> {
+        let timeline_grid = &cx.data::<UiData>()?.state.timeline_grid;
+        let x = (cx.mouse.cursorx - cx.cache.get_posx(cx.current())) / cx.scale_factor();
+        let beat_width = f64::from(BEAT_WIDTH_PX) * timeline_grid.horizontal_zoom_level;
+
+        Some(
+            (timeline_grid.left_start.get().as_beats_f64()
+                + f64::from(x - TIMELINE_DEFAULT_OFFSET) / beat_width)
+                .max(0.0),
+        )
     }
 }
 
 impl View for TimelineGridHeader {
+    fn event(&mut self, cx: &mut EventContext, event: &mut Event) {
+        event.map(|window_event, meta| match window_event {
+            WindowEvent::MouseDown(button) if *button == MouseButton::Left => {
+                let beats = match Self::cursor_beats(cx) {
+                    Some(beats) => beats,
+                    None => return,
+                };
+
+                if cx.modifiers.contains(Modifiers::SHIFT) {
+                    self.loop_drag_start = Some(beats.round());
+                    cx.capture();
+                } else {
+                    // Seek to the nearest sixteenth note.
+                    let beats = (beats * 4.0).round() / 4.0;
+                    cx.emit(TransportEvent::Seek(MusicalTime::from_beats_f64(beats)));
+                }
+                meta.consume();
+            }
+
+            WindowEvent::MouseMove(_, _) => {
+                if let (Some(drag_start), Some(beats)) =
+                    (self.loop_drag_start, Self::cursor_beats(cx))
+                {
+                    let beats = beats.round();
+                    cx.emit(TransportEvent::SetLoopRange(
+                        MusicalTime::from_beats_f64(drag_start.min(beats)),
+                        MusicalTime::from_beats_f64(drag_start.max(beats)),
+                    ));
+                    cx.emit(TransportEvent::SetLoopEnabled(true));
+                }
+            }
+
+            WindowEvent::MouseUp(button) if *button == MouseButton::Left => {
+                if self.loop_drag_start.take().is_some() {
+                    cx.release();
+                    meta.consume();
+                }
+            }
+
+            _ => {}
+        });
+    }
+
     fn draw(&self, cx: &mut DrawContext, canvas: &mut Canvas) {
         let bounds = cx.bounds();
 
@@ -106,18 +201,49 @@ impl View for TimelineGridHeader {
             let start = timeline_grid.left_start.get().as_beats_f64();
             let end = timeline_grid.left_start.get().as_beats_f64()
                 + timeline_grid.project_length.get().as_beats_f64();
+            let zoom_x = timeline_grid.horizontal_zoom_level;
+            let transport = &ui_data.state.transport;
 
             canvas.save();
             canvas.scissor(bounds.x, bounds.y, bounds.w, bounds.h);
 
+            let beat_width = BEAT_WIDTH_PX * zoom_x as f32;
+            let beats_to_x = |beats: f64| {
+                bounds.x
+                    + cx.logical_to_physical(
+                        TIMELINE_DEFAULT_OFFSET + ((beats - start) as f32) * beat_width,
+                    )
+            };
+
+            // Loop range
+            let loop_start_x = beats_to_x(transport.loop_start.get().as_beats_f64());
+            let loop_end_x = beats_to_x(transport.loop_end.get().as_beats_f64());
+            let mut path = Path::new();
+            path.rect(loop_start_x, bounds.y, loop_end_x - loop_start_x, bounds.h);
+            canvas.fill_path(
+                &mut path,
+                Paint::color(if transport.loop_enabled {
+                    vizia::vg::Color::rgba(237, 225, 113, 60)
+                } else {
+                    vizia::vg::Color::rgba(82, 82, 82, 60)
+                }),
+            );
+
+            let tempo_map = &timeline_grid.tempo_map;
+
+            // The ruler sits above the marker track.
+            let marker_track_h = cx.logical_to_physical(MARKER_TRACK_HEIGHT_PX);
+            let ruler_h = bounds.h - marker_track_h;
+            let ruler_bottom = bounds.y + ruler_h;
+
             // Vertical lines
-            let beat_width = 100.0;
-            let mut lane_x = cx.logical_to_physical(TIMELINE_DEFAULT_OFFSET);
-            for index in (start as usize)..=(end as usize) {
+            for bar in tempo_map.bars(start, end) {
+                let bar_x = beats_to_x(bar.start);
+
                 // Line per bar
                 let mut path = Path::new();
-                path.move_to(bounds.x + lane_x, bounds.y + bounds.h);
-                path.line_to(bounds.x + lane_x, bounds.y + bounds.h - cx.logical_to_physical(10.0));
+                path.move_to(bar_x, ruler_bottom);
+                path.line_to(bar_x, ruler_bottom - cx.logical_to_physical(10.0));
                 canvas.stroke_path(&mut path, Paint::color(vizia::vg::Color::rgb(82, 82, 82)));
 
                 // Number per bar
@@ -125,34 +251,98 @@ impl View for TimelineGridHeader {
                 // text_paint.set_font(&[font_id.clone()]);
                 text_paint.set_text_align(Align::Center);
                 text_paint.set_text_baseline(Baseline::Top);
+                let _ = canvas.fill_text(bar_x, bounds.y, &format!("{}", bar.number), text_paint);
+
+                // Line per beat
+                for beat in 1..bar.beats {
+                    let beat_x = beats_to_x(bar.start + f64::from(beat) * bar.beat_length);
+
+                    // The middle of the bar gets a longer line.
+                    let is_middle = bar.beats % 2 == 0 && beat == bar.beats / 2;
+                    let length = cx.logical_to_physical(if is_middle { 8.0 } else { 5.0 });
+
+                    let mut path = Path::new();
+                    path.move_to(beat_x, ruler_bottom);
+                    path.line_to(beat_x, ruler_bottom - length);
+                    canvas.stroke_path(&mut path, Paint::color(vizia::vg::Color::rgb(82, 82, 82)));
+                }
+            }
+
+            // Time signature and tempo changes
+            let change_paint = |baseline| {
+                let mut paint = Paint::color(vizia::vg::Color::rgb(237, 225, 113));
+                paint.set_text_align(Align::Left);
+                paint.set_text_baseline(baseline);
+                paint
+            };
+            let label_offset = cx.logical_to_physical(8.0);
+            for change in tempo_map.time_signature_changes() {
+                let x = beats_to_x(change.position.get().as_beats_f64());
                 let _ = canvas.fill_text(
-                    bounds.x + lane_x,
+                    x + label_offset,
                     bounds.y,
-                    &format!("{}", index + 1),
-                    text_paint,
+                    &format!("{}/{}", change.numerator, change.denominator),
+                    change_paint(Baseline::Top),
+                );
+            }
+            for change in tempo_map.tempo_changes() {
+                let x = beats_to_x(change.position.get().as_beats_f64());
+                let _ = canvas.fill_text(
+                    x + label_offset,
+                    bounds.y + ruler_h / 2.0,
+                    &format!("{:.1}", change.bpm),
+                    change_paint(Baseline::Middle),
                 );
+            }
 
-                // Line per beat
-                if index != end as usize {
-                    // Line per bar
-                    for index in 1..4 {
-                        let lane_bar_x =
-                            lane_x + cx.logical_to_physical(index as f32 * beat_width / 4.0);
-
-                        let mut path = Path::new();
-                        let length = cx.logical_to_physical(if index == 2 { 8.0 } else { 5.0 });
-
-                        path.move_to(bounds.x + lane_bar_x, bounds.y + bounds.h);
-                        path.line_to(bounds.x + lane_bar_x, bounds.y + bounds.h - length);
-                        canvas.stroke_path(
-                            &mut path,
-                            Paint::color(vizia::vg::Color::rgb(82, 82, 82)),
-                        );
-                    }
-                }
+            // Marker track
+            let mut path = Path::new();
+            path.rect(bounds.x, ruler_bottom, bounds.w, marker_track_h);
+            canvas.fill_path(&mut path, Paint::color(vizia::vg::Color::rgb(24, 24, 24)));
+
+            let name_paint = || {
+                let mut paint = Paint::color(vizia::vg::Color::rgb(235, 235, 235));
+                paint.set_text_align(Align::Left);
+                paint.set_text_baseline(Baseline::Middle);
+                paint
+            };
+            let name_offset = cx.logical_to_physical(3.0);
+            let name_y = ruler_bottom + marker_track_h / 2.0;
+
+            let markers = &timeline_grid.markers;
+            for region in markers.regions() {
+                let x = beats_to_x(region.start.get().as_beats_f64());
+                let w = beats_to_x(region.end.get().as_beats_f64()) - x;
+
+                let mut path = Path::new();
+                path.rect(x, ruler_bottom, w, marker_track_h);
+                canvas
+                    .fill_path(&mut path, Paint::color(vizia::vg::Color::rgba(113, 170, 237, 90)));
 
-                lane_x += cx.logical_to_physical(beat_width);
+                canvas.save();
+                canvas.intersect_scissor(x, ruler_bottom, w, marker_track_h);
+                let _ = canvas.fill_text(x + name_offset, name_y, &region.name, name_paint());
+                canvas.restore();
             }
+            for marker in markers.markers() {
+                let x = beats_to_x(marker.position.get().as_beats_f64());
+
+                let mut path = Path::new();
+                path.move_to(x, ruler_bottom);
+                path.line_to(x, bounds.y + bounds.h);
+                canvas.stroke_path(&mut path, Paint::color(vizia::vg::Color::rgb(234, 113, 108)));
+
+                let _ = canvas.fill_text(x + name_offset, name_y, &marker.name, name_paint());
+            }
+
+            // Playhead
+            let playhead_x = beats_to_x(transport.playhead.get().as_beats_f64());
+            let mut path = Path::new();
+            path.move_to(playhead_x, bounds.y);
+            path.line_to(playhead_x, bounds.y + bounds.h);
+            let (r, g, b) = PLAYHEAD_COLOR;
+            canvas.stroke_path(&mut path, Paint::color(vizia::vg::Color::rgb(r, g, b)));
+
             canvas.restore();
         }
     }
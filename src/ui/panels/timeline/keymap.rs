@@ -111,6 +111,48 @@ pub fn timeline_keymap(cx: &mut Context) {
                 cx.emit(UiEvent::SelectAllLanes);
             }),
         ),
+        // M => Inserts a marker at the playhead.
+        (
+            KeyChord::new(Modifiers::empty(), Code::KeyM),
+            KeymapEntry::new(UiEvent::InsertMarkerAtPlayhead, |cx| {
+                cx.emit(UiEvent::InsertMarkerAtPlayhead);
+            }),
+        ),
+        // SHIFT + M => Inserts a region over the loop range.
+        (
+            KeyChord::new(Modifiers::SHIFT, Code::KeyM),
+            KeymapEntry::new(UiEvent::InsertRegionFromLoopRange, |cx| {
+                cx.emit(UiEvent::InsertRegionFromLoopRange);
+            }),
+        ),
+        // ArrowRight => Moves the playhead to the next marker or region.
+        (
+            KeyChord::new(Modifiers::empty(), Code::ArrowRight),
+            KeymapEntry::new(UiEvent::GoToNextLocator, |cx| {
+                cx.emit(UiEvent::GoToNextLocator);
+            }),
+        ),
+        // ArrowLeft => Moves the playhead to the previous marker or region.
+        (
+            KeyChord::new(Modifiers::empty(), Code::ArrowLeft),
+            KeymapEntry::new(UiEvent::GoToPreviousLocator, |cx| {
+                cx.emit(UiEvent::GoToPreviousLocator);
+            }),
+        ),
+        // L => Loops the region under the playhead.
+        (
+            KeyChord::new(Modifiers::empty(), Code::KeyL),
+            KeymapEntry::new(UiEvent::LoopRegionAtPlayhead, |cx| {
+                cx.emit(UiEvent::LoopRegionAtPlayhead);
+            }),
+        ),
+        // CTRL + SHIFT + E => Renders every region to its own file.
+        (
+            KeyChord::new(Modifiers::CTRL | Modifiers::SHIFT, Code::KeyE),
+            KeymapEntry::new(UiEvent::ExportRegions, |cx| {
+                cx.emit(UiEvent::ExportRegions);
+            }),
+        ),
     ])
     .build(cx);
 }
@@ -1,3 +1,4 @@
+use super::clips::TimelineClips;
 use crate::ui::{
     state::{LaneState, LaneStates, TimelineGridState},
     UiData, UiEvent, UiState,
@@ -68,5 +69,5 @@ pub fn lane_header(cx: &mut Context) {
 }
 
 pub fn lane_content(cx: &mut Context) {
-    // TODO: Implement
+    TimelineClips::new(cx).class("lane_content");
 }
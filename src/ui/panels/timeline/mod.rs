@@ -1,3 +1,4 @@
+mod clips;
 mod grid;
 mod keymap;
 mod lanes;
@@ -1,8 +1,11 @@
 use vizia::prelude::*;
 
 use crate::ui::icons::IconCode;
-use crate::ui::state::PanelEvent;
-use crate::ui::{Icon, Meter, MeterHandle};
+use crate::ui::state::{
+    CountIn, GrooveEvent, GrooveState, MetronomeEvent, MetronomeState, PanelEvent, PanelState,
+    TransportEvent, TransportState, UiData, UiState,
+};
+use crate::ui::{settings_dialog, Icon, Meter, MeterHandle};
 
 #[derive(Lens)]
 pub struct Data {
@@ -14,27 +17,119 @@ impl Model for Data {}
 
 pub fn top_bar(cx: &mut Context) {
     HStack::new(cx, |cx| {
-        Button::new(cx, |_| {}, |cx| Icon::new(cx, IconCode::Menu, 24.0, 16.0))
-            .class("top_bar_menu");
+        Button::new(
+            cx,
+            |cx| cx.emit(PanelEvent::ToggleSettings),
+            |cx| Icon::new(cx, IconCode::Menu, 24.0, 16.0),
+        )
+        .class("top_bar_menu")
+        .toggle_class(
+            "selected",
+            UiData::state.then(UiState::panels.then(PanelState::show_settings)),
+        );
 
         // This is all just dummy content and it doesn't do anything
         HStack::new(cx, |cx| {
             VStack::new(cx, |cx| {
                 HStack::new(cx, |cx| {
-                    Label::new(cx, "130.00");
-                    Label::new(cx, "TAP");
+                    // The tempo at the playhead
+                    Label::new(
+                        cx,
+                        UiData::state.map(|state| {
+                            let tempo = state
+                                .timeline_grid
+                                .tempo_map
+                                .tempo_at(state.transport.playhead.get());
+                            format!("{:.2}", tempo)
+                        }),
+                    );
+                    Button::new(
+                        cx,
+                        |cx| cx.emit(TransportEvent::TapTempo),
+                        |cx| Label::new(cx, "TAP"),
+                    );
                 });
                 HStack::new(cx, |cx| {
-                    Label::new(cx, "4/4");
-                    Label::new(cx, "GRV");
+                    // The time signature at the playhead
+                    Label::new(
+                        cx,
+                        UiData::state.map(|state| {
+                            let (numerator, denominator) = state
+                                .timeline_grid
+                                .tempo_map
+                                .time_signature_at(state.transport.playhead.get());
+                            format!("{}/{}", numerator, denominator)
+                        }),
+                    );
+                    Button::new(
+                        cx,
+                        |cx| cx.emit(PanelEvent::ToggleGroove),
+                        |cx| Label::new(cx, "GRV"),
+                    )
+                    .toggle_class(
+                        "selected",
+                        UiData::state.then(UiState::panels.then(PanelState::show_groove)),
+                    );
                 });
             })
             .class("top_play_left");
 
             HStack::new(cx, |cx| {
-                Button::new(cx, |_| {}, |cx| Icon::new(cx, IconCode::Play, 24.0, 23.0));
-                Button::new(cx, |_| {}, |cx| Icon::new(cx, IconCode::Stop, 24.0, 23.0));
-                Button::new(cx, |_| {}, |cx| Icon::new(cx, IconCode::Record, 24.0, 23.0));
+                VStack::new(cx, |cx| {
+                    Label::new(
+                        cx,
+                        UiData::state.map(|state| {
+                            state
+                                .timeline_grid
+                                .tempo_map
+                                .format_position(state.transport.playhead.get())
+                        }),
+                    );
+                    Label::new(
+                        cx,
+                        UiData::state.map(|state| {
+                            let seconds = state
+                                .timeline_grid
+                                .tempo_map
+                                .musical_to_seconds(state.transport.playhead.get())
+                                .0;
+                            format!("{}:{:06.3}", (seconds / 60.0).floor(), seconds % 60.0)
+                        }),
+                    );
+                })
+                .class("top_play_position");
+                Button::new(
+                    cx,
+                    |cx| cx.emit(TransportEvent::TogglePlay),
+                    |cx| Icon::new(cx, IconCode::Play, 24.0, 23.0),
+                )
+                .toggle_class(
+                    "selected",
+                    UiData::state.then(UiState::transport.then(TransportState::is_playing)),
+                );
+                Button::new(
+                    cx,
+                    |cx| cx.emit(TransportEvent::Stop),
+                    |cx| Icon::new(cx, IconCode::Stop, 24.0, 23.0),
+                );
+                Button::new(
+                    cx,
+                    |cx| cx.emit(TransportEvent::ToggleRecord),
+                    |cx| Icon::new(cx, IconCode::Record, 24.0, 23.0),
+                )
+                .toggle_class(
+                    "selected",
+                    UiData::state.then(UiState::transport.then(TransportState::is_recording)),
+                );
+                Button::new(
+                    cx,
+                    |cx| cx.emit(TransportEvent::ToggleLoop),
+                    |cx| Icon::new(cx, IconCode::Loop, 24.0, 23.0),
+                )
+                .toggle_class(
+                    "selected",
+                    UiData::state.then(UiState::transport.then(TransportState::loop_enabled)),
+                );
             })
             .class("top_play_center")
             .top(Stretch(1.0))
@@ -45,6 +140,35 @@ pub fn top_bar(cx: &mut Context) {
                 Label::new(cx, "OVERWRITE");
             })
             .class("top_play_right");
+
+            VStack::new(cx, |cx| {
+                Button::new(
+                    cx,
+                    |cx| cx.emit(MetronomeEvent::ToggleEnabled),
+                    |cx| Label::new(cx, "CLICK"),
+                )
+                .toggle_class(
+                    "selected",
+                    UiData::state.then(UiState::metronome.then(MetronomeState::enabled)),
+                );
+                Button::new(
+                    cx,
+                    |cx| cx.emit(MetronomeEvent::CycleCountIn),
+                    |cx| {
+                        Label::new(
+                            cx,
+                            UiData::state
+                                .then(UiState::metronome.then(MetronomeState::count_in))
+                                .map(|count_in| match count_in {
+                                    CountIn::Off => String::from("NO COUNT"),
+                                    CountIn::OneBar => String::from("COUNT 1"),
+                                    CountIn::TwoBars => String::from("COUNT 2"),
+                                }),
+                        )
+                    },
+                );
+            })
+            .class("top_play_metronome");
         })
         .class("top_bar_play");
 
@@ -97,6 +221,91 @@ pub fn top_bar(cx: &mut Context) {
             .class("top_bar_graph_container");
         })
         .class("top_bar_right_container");
+
+        Binding::new(
+            cx,
+            UiData::state.then(UiState::panels.then(PanelState::show_groove)),
+            |cx, show_groove| {
+                if show_groove.get(cx) {
+                    groove_dialog(cx);
+                }
+            },
+        );
+
+        Binding::new(
+            cx,
+            UiData::state.then(UiState::panels.then(PanelState::show_settings)),
+            |cx, show_settings| {
+                if show_settings.get(cx) {
+                    settings_dialog(cx);
+                }
+            },
+        );
     })
     .class("top_bar");
 }
+
+/// The swing, grid resolution and accents of the project's groove.
+fn groove_dialog(cx: &mut Context) {
+    let groove = UiData::state.then(UiState::groove);
+
+    VStack::new(cx, |cx| {
+        HStack::new(cx, |cx| {
+            Label::new(cx, "SWING");
+            Button::new(
+                cx,
+                |cx| {
+                    let swing = cx.data::<UiData>().map_or(0.0, |d| d.state.groove.swing);
+                    cx.emit(GrooveEvent::SetSwing(swing - 0.05));
+                },
+                |cx| Label::new(cx, "-"),
+            );
+            Label::new(
+                cx,
+                groove.then(GrooveState::swing).map(|swing| format!("{:.0}%", swing * 100.0)),
+            );
+            Button::new(
+                cx,
+                |cx| {
+                    let swing = cx.data::<UiData>().map_or(0.0, |d| d.state.groove.swing);
+                    cx.emit(GrooveEvent::SetSwing(swing + 0.05));
+                },
+                |cx| Label::new(cx, "+"),
+            );
+        })
+        .class("groove_row");
+
+        HStack::new(cx, |cx| {
+            Label::new(cx, "GRID");
+            Button::new(
+                cx,
+                |cx| cx.emit(GrooveEvent::CycleResolution),
+                |cx| {
+                    Label::new(
+                        cx,
+                        groove
+                            .then(GrooveState::resolution)
+                            .map(|resolution| String::from(resolution.label())),
+                    )
+                },
+            );
+        })
+        .class("groove_row");
+
+        // One button per step. Click a step to change its accent.
+        Binding::new(cx, groove.then(GrooveState::accents), |cx, accents| {
+            HStack::new(cx, |cx| {
+                for (step, accent) in accents.get(cx).into_iter().enumerate() {
+                    Button::new(
+                        cx,
+                        move |cx| cx.emit(GrooveEvent::CycleAccent(step)),
+                        move |cx| Label::new(cx, &format!("{:.0}", accent * 100.0)),
+                    )
+                    .class("groove_step");
+                }
+            })
+            .class("groove_accents");
+        });
+    })
+    .class("groove_dialog");
+}
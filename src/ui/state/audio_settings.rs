@@ -0,0 +1,121 @@
+use vizia::prelude::*;
+
+use crate::backend::system_io::{self, AudioDeviceInfo, SystemIOBackend, SystemIOConfig};
+
+/// The choices shown in the audio settings.
+///
+/// Any selection set to `None` uses the default of the system.
+#[derive(Debug, Lens, Clone, Default)]
+pub struct AudioSettingsState {
+    pub backend: SystemIOBackend,
+
+    pub hosts: Vec<String>,
+    pub output_devices: Vec<String>,
+    pub input_devices: Vec<String>,
+
+    /// The sample rates supported by the selected output device.
+    pub sample_rates: Vec<u32>,
+    /// The buffer sizes supported by the selected output device.
+    pub buffer_sizes: Vec<u32>,
+
+    pub host: Option<String>,
+    pub output_device: Option<String>,
+    pub input_enabled: bool,
+    pub input_device: Option<String>,
+    pub sample_rate: Option<u32>,
+    pub buffer_size: Option<u32>,
+}
+
+impl Data for SystemIOBackend {
+    fn same(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+impl AudioSettingsState {
+    pub fn new(config: &SystemIOConfig) -> Self {
+        let mut state = Self::default();
+        state.refresh(config);
+        state
+    }
+
+    /// Query the available hosts and devices again.
+    pub fn refresh(&mut self, config: &SystemIOConfig) {
+        self.hosts = system_io::available_hosts();
+
+        let devices = match system_io::output_devices(config.host.as_deref()) {
+            Ok(devices) => devices,
+            Err(e) => {
+                log::error!("Failed to list audio output devices: {}", e);
+                Vec::new()
+            }
+        };
+
+        let selected_device: Option<&AudioDeviceInfo> = match &config.output_device {
+            Some(name) => devices.iter().find(|d| &d.name == name),
+            None => devices.iter().find(|d| d.is_default),
+        };
+        self.sample_rates = selected_device.map(|d| d.sample_rates.clone()).unwrap_or_default();
+        self.buffer_sizes = selected_device.map(|d| d.buffer_sizes.clone()).unwrap_or_default();
+
+        self.output_devices = devices.into_iter().map(|d| d.name).collect();
+
+        self.input_devices = match system_io::input_devices(config.host.as_deref()) {
+            Ok(devices) => devices.into_iter().map(|d| d.name).collect(),
+            Err(e) => {
+                log::error!("Failed to list audio input devices: {}", e);
+                Vec::new()
+            }
+        };
+
+        self.backend = config.backend;
+        self.host = config.host.clone();
+        self.output_device = config.output_device.clone();
+        self.input_enabled = config.input_enabled;
+        self.input_device = config.input_device.clone();
+        self.sample_rate = config.sample_rate;
+        self.buffer_size = config.buffer_size;
+    }
+
+    pub fn next_backend(&self) -> SystemIOBackend {
+        match self.backend {
+            SystemIOBackend::Cpal => SystemIOBackend::Null,
+            SystemIOBackend::Null => SystemIOBackend::Cpal,
+        }
+    }
+
+    pub fn next_host(&self) -> Option<String> {
+        next_choice(&self.hosts, &self.host)
+    }
+
+    pub fn next_output_device(&self) -> Option<String> {
+        next_choice(&self.output_devices, &self.output_device)
+    }
+
+    pub fn next_input_device(&self) -> Option<String> {
+        next_choice(&self.input_devices, &self.input_device)
+    }
+
+    pub fn next_sample_rate(&self) -> Option<u32> {
+        next_choice(&self.sample_rates, &self.sample_rate)
+    }
+
+    pub fn next_buffer_size(&self) -> Option<u32> {
+        next_choice(&self.buffer_sizes, &self.buffer_size)
+    }
+}
+
+/// The choice after `selected`, going from the system default (`None`) through
+/// each of `choices` and then back to the default.
+fn next_choice<T: Clone + PartialEq>(choices: &[T], selected: &Option<T>) -> Option<T> {
+    let next = match selected {
+        None => 0,
+        Some(selected) => match choices.iter().position(|c| c == selected) {
+            Some(i) => i + 1,
+            // The selected choice is not available anymore.
+            None => 0,
+        },
+    };
+
+    choices.get(next).cloned()
+}
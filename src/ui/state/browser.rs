@@ -11,6 +11,20 @@ pub struct BrowserState {
     pub browser_tree: BrowserTree,
     pub selected: NodeType,
     pub search_expression: String,
+
+    /// How many semitones samples are transposed by when they are auditioned.
+    pub audition_semitones: i32,
+}
+
+/// The furthest samples can be transposed when they are auditioned.
+pub static MAX_AUDITION_SEMITONES: i32 = 24;
+
+impl BrowserState {
+    /// How many times as fast as normal samples are played back when they
+    /// are auditioned.
+    pub fn audition_rate(&self) -> f64 {
+        2.0f64.powf(f64::from(self.audition_semitones) / 12.0)
+    }
 }
 
 #[derive(Debug)]
@@ -31,6 +45,8 @@ pub enum BrowserEvent {
     PlaySelected,
     StopSelected,
     SetSearchExpression(String),
+    /// Transpose auditioned samples by the given number of semitones.
+    TransposeAudition(i32),
 }
 
 pub mod browser_state {
@@ -264,6 +280,7 @@ impl Default for BrowserState {
             browser_tree: BrowserTree::empty(),
             selected: NodeType::None,
             search_expression: String::from("..."),
+            audition_semitones: 0,
         }
     }
 }
@@ -277,6 +294,11 @@ impl Model for BrowserState {
             }
 
             // Set the new root from where the browser build the file view
+            BrowserEvent::TransposeAudition(semitones) => {
+                self.audition_semitones = (self.audition_semitones + semitones)
+                    .clamp(-MAX_AUDITION_SEMITONES, MAX_AUDITION_SEMITONES);
+            }
+
             BrowserEvent::SetRoot(path) => {
                 self.browser_tree.update(path).expect("Failed to update Root"); //todo better error handling here
             }
@@ -2,14 +2,15 @@ use std::path::PathBuf;
 
 use super::clip::{AudioClipState, AutomationClipState, PianoRollClipState};
 use super::hrack_effect::HRackEffectState;
+use serde::{Deserialize, Serialize};
 use vizia::prelude::*;
 
-#[derive(Debug, Lens, Clone, Data)]
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
 pub enum ChannelBaseColor {
     /// This is an index into a bunch of preset colors that are defined
     /// by the current theme.
     Preset(u16),
-    Color(Color),
+    Color(#[serde(with = "serde_color")] Color),
 }
 
 impl From<ChannelBaseColor> for Color {
@@ -27,8 +28,35 @@ impl From<Color> for ChannelBaseColor {
     }
 }
 
+/// (De)serializes a `vizia::Color` as its packed RGBA value (i.e.
+/// `{"data":3570783743}` for `#D4D5D5`).
+mod serde_color {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use vizia::prelude::Color;
+
+    #[derive(Serialize, Deserialize)]
+    struct PackedColor {
+        data: u32,
+    }
+
+    pub fn serialize<S: Serializer>(color: &Color, serializer: S) -> Result<S::Ok, S::Error> {
+        let data = (u32::from(color.r()) << 24)
+            | (u32::from(color.g()) << 16)
+            | (u32::from(color.b()) << 8)
+            | u32::from(color.a());
+
+        PackedColor { data }.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+        let PackedColor { data } = PackedColor::deserialize(deserializer)?;
+
+        Ok(Color::rgba((data >> 24) as u8, (data >> 16) as u8, (data >> 8) as u8, data as u8))
+    }
+}
+
 /// A "channel" refers to a mixer channel.
-#[derive(Debug, Lens, Clone, Data)]
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
 pub struct ChannelState {
     /// The channel name
     pub name: String,
@@ -44,6 +72,7 @@ pub struct ChannelState {
     pub subchannels: Vec<usize>,
 
     /// Flag indicating whether the channel is currently selected in UI
+    #[serde(skip)]
     pub selected: bool,
 
     /// The audio clips assigned to this channel.
@@ -106,6 +135,38 @@ impl Default for ChannelState {
     }
 }
 
+/// The gain of a channel's fader when it is all the way down (above silence).
+pub const CHANNEL_MIN_GAIN_DB: f64 = -90.0;
+
+impl ChannelState {
+    /// The gain of the channel's fader as an amplitude.
+    ///
+    /// A normalized value of `1.0` is 0dB, and `0.0` is silence.
+    pub fn out_gain_amplitude(&self) -> f32 {
+        if self.out_gain_normalized <= 0.0 {
+            return 0.0;
+        }
+
+        let db = (1.0 - self.out_gain_normalized.min(1.0)) * CHANNEL_MIN_GAIN_DB;
+        10.0f64.powf(db / 20.0) as f32
+    }
+
+    /// The gain of each output channel after the fader, pan, and mute of this
+    /// channel have been applied.
+    ///
+    /// The pan is only applied to stereo outputs.
+    pub fn out_channel_gains(&self, num_channels: u16) -> Vec<f32> {
+        let gain = if self.muted { 0.0 } else { self.out_gain_amplitude() };
+
+        if num_channels == 2 {
+            let pan = self.out_pan_normalized.clamp(0.0, 1.0) as f32;
+            vec![gain * (2.0 * (1.0 - pan)).min(1.0), gain * (2.0 * pan).min(1.0)]
+        } else {
+            vec![gain; usize::from(num_channels)]
+        }
+    }
+}
+
 #[derive(PartialEq, Clone)]
 pub enum ChannelEvent {
     SelectChannel(usize),
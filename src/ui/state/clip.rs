@@ -1,7 +1,14 @@
 use super::core_types::{WMusicalTime, WSeconds, WSuperFrames};
+use super::tempo_map::TempoMap;
+use meadowlark_core_types::time::{MusicalTime, SampleRate, Seconds, SuperFrames};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 use vizia::prelude::*;
 
-#[derive(Debug, Lens, Clone, Data)]
+use crate::backend::disk_stream::PcmSource;
+use crate::backend::timeline_track::TimelineAudioClip;
+
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
 pub struct ClipState {
     pub name: String,
     pub timeline_start: ClipStart,
@@ -12,38 +19,133 @@ pub struct ClipState {
     pub type_: ClipType,
 }
 
-#[derive(Debug, Lens, Clone, Data)]
+impl ClipState {
+    /// The position of the end of the clip on the timeline, or `None` if it is
+    /// not on the timeline.
+    pub fn timeline_end(&self) -> Option<MusicalTime> {
+        self.timeline_start.on_lane().map(|on_lane| {
+            MusicalTime::from_beats_f64(
+                on_lane.timeline_start().as_beats_f64() + self.length.get().as_beats_f64(),
+            )
+        })
+    }
+
+    /// Move the start of the clip while keeping its end in place.
+    ///
+    /// Audio clips cannot start before the start of their audio file, so the
+    /// start is clamped to it.
+    pub fn resize_start(&mut self, timeline_start: MusicalTime, tempo_map: &TempoMap) {
+        let (lane_index, old_start) = match self.timeline_start.on_lane() {
+            Some(on_lane) => (on_lane.lane_index(), on_lane.timeline_start().as_beats_f64()),
+            None => return,
+        };
+        let end = old_start + self.length.get().as_beats_f64();
+
+        let mut new_start = timeline_start.as_beats_f64();
+        if new_start >= end {
+            return;
+        }
+
+        if let ClipType::Audio(audio_clip) = &mut self.type_ {
+            let offset = audio_clip.clip_start_offset.get();
+            let old_start_secs =
+                tempo_map.musical_to_seconds(MusicalTime::from_beats_f64(old_start));
+
+            let earliest_start = tempo_map
+                .seconds_to_musical(Seconds(old_start_secs.0 - offset.to_seconds().0))
+                .as_beats_f64();
+            new_start = new_start.max(earliest_start);
+
+            let delta_secs = tempo_map.musical_to_seconds(MusicalTime::from_beats_f64(new_start)).0
+                - old_start_secs.0;
+            let new_offset = if delta_secs >= 0.0 {
+                offset.0 + Seconds(delta_secs).to_super_frames().0
+            } else {
+                offset.0.saturating_sub(Seconds(-delta_secs).to_super_frames().0)
+            };
+            audio_clip.clip_start_offset = SuperFrames(new_offset).into();
+        }
+
+        self.timeline_start =
+            ClipStart::OnLane(OnLane::new(lane_index, MusicalTime::from_beats_f64(new_start)));
+        self.length = MusicalTime::from_beats_f64(end - new_start).into();
+    }
+
+    /// Move the end of the clip while keeping its start in place.
+    pub fn resize_end(&mut self, timeline_end: MusicalTime) {
+        if let Some(on_lane) = self.timeline_start.on_lane() {
+            let length = timeline_end.as_beats_f64() - on_lane.timeline_start().as_beats_f64();
+            if length > 0.0 {
+                self.length = MusicalTime::from_beats_f64(length).into();
+            }
+        }
+    }
+}
+
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
 pub enum ClipType {
     Audio(AudioClipState),
     PianoRoll(PianoRollClipState),
     Automation(AutomationClipState),
 }
 
-#[derive(Debug, Lens, Clone, Data)]
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
 pub struct AudioClipState {
+    /// The path to the audio file this clip plays.
+    ///
+    /// This is always an absolute path while the project is loaded. In the
+    /// project file, samples inside the project folder are stored relative to
+    /// that folder.
+    pub pcm_path: PathBuf,
+
+    /// The hash of the contents of the audio file. This is used to find the
+    /// file again if it was moved or renamed.
+    pub pcm_hash: Option<u64>,
+
     pub fade_in_secs: WSeconds,
 
     pub fade_out_secs: WSeconds,
 
     /// The amount of time between the start of the raw waveform data
     /// and the start of the clip.
-    ///
-    /// TODO
     pub clip_start_offset: WSuperFrames,
     // TODO: pointer to waveform data
 }
 
-#[derive(Debug, Lens, Clone, Data)]
+impl AudioClipState {
+    /// The clip as it is played by the timeline track plugin, starting at
+    /// `timeline_start` and lasting `length` frames on the timeline.
+    pub fn to_timeline_clip(
+        &self,
+        source: PcmSource,
+        timeline_start: u64,
+        length: u64,
+        sample_rate: SampleRate,
+    ) -> TimelineAudioClip {
+        TimelineAudioClip::new(
+            source,
+            timeline_start,
+            length,
+            self.clip_start_offset.get(),
+            self.fade_in_secs.get(),
+            self.fade_out_secs.get(),
+            sample_rate,
+        )
+    }
+}
+
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
 pub struct PianoRollClipState {
-    // TODO
+    // TODO: Notes. They should be played back through `GrooveState::apply()`,
+    // and be quantized to `QuantizeTarget::Groove` on request.
 }
 
-#[derive(Debug, Lens, Clone, Data)]
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
 pub struct AutomationClipState {
     // TODO
 }
 
-#[derive(Debug, Lens, Clone, Data)]
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
 pub enum ClipStart {
     OnLane(OnLane),
     /// This means that the clip is not currently on the timeline,
@@ -51,8 +153,48 @@ pub enum ClipStart {
     NotInTimeline,
 }
 
-#[derive(Debug, Lens, Clone, Data)]
+impl ClipStart {
+    /// Returns where the clip is placed on the timeline, or `None` if it only
+    /// lives in the clips panel.
+    pub fn on_lane(&self) -> Option<&OnLane> {
+        match self {
+            ClipStart::OnLane(on_lane) => Some(on_lane),
+            ClipStart::NotInTimeline => None,
+        }
+    }
+}
+
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
 pub struct OnLane {
     lane_index: u32,
     timeline_start: WMusicalTime,
 }
+
+impl OnLane {
+    pub fn new(lane_index: u32, timeline_start: MusicalTime) -> Self {
+        Self { lane_index, timeline_start: timeline_start.into() }
+    }
+
+    /// The index of the lane the clip is on.
+    pub fn lane_index(&self) -> u32 {
+        self.lane_index
+    }
+
+    /// The position of the start of the clip on the timeline.
+    pub fn timeline_start(&self) -> MusicalTime {
+        self.timeline_start.get()
+    }
+}
+
+pub enum ClipEvent {
+    /// Move the clip at `index` in `UiState::clips` to the given lane and
+    /// position on the timeline, keeping its length.
+    MoveClip { index: usize, lane_index: u32, timeline_start: MusicalTime },
+    /// Move the start of the clip at `index` while keeping its end in place.
+    ///
+    /// Audio clips keep their audio in place on the timeline, so this also
+    /// moves the start of the clip within the audio file.
+    ResizeClipStart { index: usize, timeline_start: MusicalTime },
+    /// Move the end of the clip at `index` while keeping its start in place.
+    ResizeClipEnd { index: usize, timeline_end: MusicalTime },
+}
@@ -1,5 +1,7 @@
 use std::path::PathBuf;
 
+use crate::backend::system_io::SystemIOBackend;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum UiEvent {
     // ----- General -----
@@ -8,6 +10,30 @@ pub enum UiEvent {
     // Project
     SaveProject,
     LoadProject,
+    CollectAndSaveProject,
+    ExportProjectArchive,
+    RelinkMissingFiles,
+    RenderProject,
+    ExportStems,
+    CancelRender,
+
+    // Audio settings
+    RefreshAudioDevices,
+    ReconnectAudio,
+    SetAudioBackend(SystemIOBackend),
+    SetAudioHost(Option<String>),
+    SetAudioOutputDevice(Option<String>),
+    SetAudioInputEnabled(bool),
+    SetAudioInputDevice(Option<String>),
+    SetAudioSampleRate(Option<u32>),
+    SetAudioBufferSize(Option<u32>),
+
+    // Autosave settings
+    SetAutosaveEnabled(bool),
+    CycleAutosaveInterval,
+
+    // Memory settings
+    CycleMemoryBudget,
 
     // ----- Channel Rack -----
     SelectChannel(usize),
@@ -43,6 +69,14 @@ pub enum UiEvent {
     DeactivateSelectedLanes,
     ToggleSelectedLaneActivation,
 
+    // Markers
+    InsertMarkerAtPlayhead,
+    InsertRegionFromLoopRange,
+    GoToNextLocator,
+    GoToPreviousLocator,
+    LoopRegionAtPlayhead,
+    ExportRegions,
+
     // ----- Browser -----
     SetBrowserWidth(f32),
     BrowserFileClicked(PathBuf),
@@ -0,0 +1,242 @@
+use serde::{Deserialize, Serialize};
+use vizia::prelude::*;
+
+/// The number of steps in the accent pattern of a new groove.
+pub const DEFAULT_GROOVE_STEPS: usize = 16;
+
+/// The accent levels that a step switches between in the groove dialog.
+pub const GROOVE_ACCENT_LEVELS: [f32; 4] = [1.0, 0.8, 0.6, 0.4];
+
+/// The length of a step of the groove.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Data, Serialize, Deserialize)]
+pub enum GrooveResolution {
+    Eighth,
+    Sixteenth,
+    ThirtySecond,
+}
+
+impl GrooveResolution {
+    /// The length of a step in quarter notes.
+    pub fn step_length(&self) -> f64 {
+        match self {
+            GrooveResolution::Eighth => 0.5,
+            GrooveResolution::Sixteenth => 0.25,
+            GrooveResolution::ThirtySecond => 0.125,
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        match self {
+            GrooveResolution::Eighth => GrooveResolution::Sixteenth,
+            GrooveResolution::Sixteenth => GrooveResolution::ThirtySecond,
+            GrooveResolution::ThirtySecond => GrooveResolution::Eighth,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            GrooveResolution::Eighth => "1/8",
+            GrooveResolution::Sixteenth => "1/16",
+            GrooveResolution::ThirtySecond => "1/32",
+        }
+    }
+}
+
+/// The swing and accents that are applied to the notes of the piano roll
+/// clips in the project.
+#[derive(Debug, Lens, Clone, Serialize, Deserialize)]
+pub struct GrooveState {
+    /// How far every other step is pushed towards the step after it, in the
+    /// range `[0.0, 1.0]`. `0.0` is straight, and `1.0` is a triplet feel.
+    pub swing: f32,
+
+    pub resolution: GrooveResolution,
+
+    /// The velocity of the notes on each step, relative to the velocity of
+    /// the note itself. The pattern repeats from the start of the timeline.
+    pub accents: Vec<f32>,
+}
+
+impl Default for GrooveState {
+    fn default() -> Self {
+        Self {
+            swing: 0.0,
+            resolution: GrooveResolution::Sixteenth,
+            accents: vec![1.0; DEFAULT_GROOVE_STEPS],
+        }
+    }
+}
+
+impl GrooveState {
+    /// The position of a step in quarter notes, including swing.
+    pub fn step_position(&self, step: i64) -> f64 {
+        let step_length = self.resolution.step_length();
+        let mut position = step as f64 * step_length;
+
+        // A full swing moves the offbeat step two thirds into the pair of
+        // steps.
+        if step.rem_euclid(2) == 1 {
+            position += f64::from(self.swing) * step_length / 3.0;
+        }
+
+        position
+    }
+
+    /// The accent of a step.
+    pub fn accent(&self, step: i64) -> f32 {
+        if self.accents.is_empty() {
+            return 1.0;
+        }
+        self.accents[step.rem_euclid(self.accents.len() as i64) as usize]
+    }
+
+    /// The step nearest to the given position, ignoring swing.
+    fn nearest_step(&self, beats: f64) -> i64 {
+        (beats / self.resolution.step_length()).round() as i64
+    }
+
+    /// Apply the groove to a note that starts at `beats`, returning the new
+    /// start of the note and its new velocity.
+    ///
+    /// The note is moved by the same amount as the step it is nearest to, so
+    /// notes that are slightly off the grid keep their feel.
+    pub fn apply(&self, beats: f64, velocity: f32) -> (f64, f32) {
+        let step = self.nearest_step(beats);
+        let straight = step as f64 * self.resolution.step_length();
+
+        let beats = (beats + self.step_position(step) - straight).max(0.0);
+        let velocity = (velocity * self.accent(step)).clamp(0.0, 1.0);
+
+        (beats, velocity)
+    }
+
+    /// Move a position to the nearest step of the groove, including swing.
+    pub fn quantize(&self, beats: f64) -> f64 {
+        let step = self.nearest_step(beats);
+        (step - 1..=step + 1)
+            .map(|step| self.step_position(step).max(0.0))
+            .min_by(|a, b| (a - beats).abs().total_cmp(&(b - beats).abs()))
+            .unwrap()
+    }
+}
+
+/// What notes are moved to when they are quantized.
+#[derive(Debug, Clone, Copy, PartialEq, Data, Serialize, Deserialize)]
+pub enum QuantizeTarget {
+    /// Straight steps of the given length in quarter notes.
+    Grid(f64),
+    /// The steps of the project's groove, including swing.
+    Groove,
+}
+
+impl QuantizeTarget {
+    pub fn quantize(&self, beats: f64, groove: &GrooveState) -> f64 {
+        match self {
+            QuantizeTarget::Grid(step_length) => {
+                ((beats / step_length).round() * step_length).max(0.0)
+            }
+            QuantizeTarget::Groove => groove.quantize(beats),
+        }
+    }
+}
+
+pub enum GrooveEvent {
+    SetSwing(f32),
+    SetResolution(GrooveResolution),
+    CycleResolution,
+    SetAccent {
+        step: usize,
+        accent: f32,
+    },
+    /// Switch a step to the next level in `GROOVE_ACCENT_LEVELS`.
+    CycleAccent(usize),
+}
+
+impl Model for GrooveState {
+    fn event(&mut self, _: &mut EventContext, event: &mut Event) {
+        event.map(|groove_event, _| match groove_event {
+            GrooveEvent::SetSwing(swing) => {
+                self.swing = swing.clamp(0.0, 1.0);
+            }
+            GrooveEvent::SetResolution(resolution) => {
+                self.resolution = *resolution;
+            }
+            GrooveEvent::CycleResolution => {
+                self.resolution = self.resolution.next();
+            }
+            GrooveEvent::SetAccent { step, accent } => {
+                if let Some(a) = self.accents.get_mut(*step) {
+                    *a = accent.clamp(0.0, 1.0);
+                }
+            }
+            GrooveEvent::CycleAccent(step) => {
+                if let Some(a) = self.accents.get_mut(*step) {
+                    let level = GROOVE_ACCENT_LEVELS
+                        .iter()
+                        .position(|level| (level - *a).abs() < f32::EPSILON)
+                        .map_or(0, |i| (i + 1) % GROOVE_ACCENT_LEVELS.len());
+                    *a = GROOVE_ACCENT_LEVELS[level];
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GrooveResolution, GrooveState, QuantizeTarget};
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    /// Sixteenth notes with a full swing, and every offbeat at half velocity.
+    fn swung() -> GrooveState {
+        GrooveState { swing: 1.0, resolution: GrooveResolution::Sixteenth, accents: vec![1.0, 0.5] }
+    }
+
+    #[test]
+    fn step_position_and_accent() {
+        let groove = swung();
+
+        assert_close(groove.step_position(0), 0.0);
+        assert_close(groove.step_position(1), 0.25 + 0.25 / 3.0);
+        assert_close(groove.step_position(2), 0.5);
+        assert_close(groove.step_position(-1), -0.25 + 0.25 / 3.0);
+
+        assert_eq!(groove.accent(2), 1.0);
+        assert_eq!(groove.accent(3), 0.5);
+        assert_eq!(groove.accent(-1), 0.5);
+
+        let straight = GrooveState::default();
+        assert_close(straight.step_position(3), 0.75);
+        assert_eq!(straight.accent(3), 1.0);
+    }
+
+    /// Notes keep their distance to the step they are nearest to.
+    #[test]
+    fn apply() {
+        let groove = swung();
+
+        let (beats, velocity) = groove.apply(0.26, 0.8);
+        assert_close(beats, 0.26 + 0.25 / 3.0);
+        assert_eq!(velocity, 0.4);
+
+        let (beats, velocity) = groove.apply(0.49, 0.8);
+        assert_close(beats, 0.49);
+        assert_eq!(velocity, 0.8);
+    }
+
+    #[test]
+    fn quantize() {
+        let groove = swung();
+
+        assert_close(QuantizeTarget::Groove.quantize(0.3, &groove), 0.25 + 0.25 / 3.0);
+        assert_close(QuantizeTarget::Groove.quantize(0.2, &groove), 0.25 + 0.25 / 3.0);
+        assert_close(QuantizeTarget::Groove.quantize(0.45, &groove), 0.5);
+        assert_close(QuantizeTarget::Groove.quantize(0.05, &groove), 0.0);
+
+        assert_close(QuantizeTarget::Grid(0.25).quantize(0.3, &groove), 0.25);
+        assert_close(QuantizeTarget::Grid(0.25).quantize(-0.2, &groove), 0.0);
+    }
+}
@@ -1,19 +1,20 @@
+use serde::{Deserialize, Serialize};
 use vizia::prelude::*;
 
 /// An effect on the horizontal effect rack.
-#[derive(Debug, Lens, Clone, Data)]
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
 pub enum HRackEffectState {
     Internal(InternalEffectState),
     External(ExternalEffectState),
 }
 
-#[derive(Debug, Clone, PartialEq, Data)]
+#[derive(Debug, Clone, PartialEq, Data, Serialize, Deserialize)]
 pub enum InternalEffectState {
     // TODO
     Todo,
 }
 
-#[derive(Debug, Lens, Clone, Data)]
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
 pub struct ExternalEffectState {
     pub name: String,
 
@@ -78,7 +79,36 @@ pub struct ExternalEffectState {
     pub all_parameters: Vec<ParameterState>,
 }
 
-#[derive(Debug, Clone, Data)]
+impl ExternalEffectState {
+    /// Create the state for a newly added plugin with the given
+    /// reverse-domain-name.
+    ///
+    /// The rest of the fields are filled in once the plugin has activated.
+    pub fn new(rdn: &str) -> Self {
+        Self {
+            name: rdn.to_string(),
+            rdn: rdn.to_string(),
+            version: String::new(),
+            product_url: None,
+            manual_url: None,
+            support_url: None,
+            collapsed: false,
+            status: ActivatedStatus::Deactivated,
+            has_gui: false,
+            gui_is_open: false,
+            bypassed: false,
+            delay: 0,
+            preset_name: None,
+            preset_changed: false,
+            last_tweaked_parameter: None,
+            quick_access_parameters: Vec::new(),
+            all_parameters_shown: false,
+            all_parameters: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Data, Serialize, Deserialize)]
 pub enum ActivatedStatus {
     /// The plugin is successfully activated an running.
     Activated,
@@ -93,7 +123,7 @@ pub enum ActivatedStatus {
     DeactivatedDueToError { error_msg: String },
 }
 
-#[derive(Debug, Lens, Clone, Data)]
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
 pub enum AllParametersState {
     /// The parameters are currently hidden. This should be used by default since
     /// having them enabled creates some overhead in the backend.
@@ -102,7 +132,7 @@ pub enum AllParametersState {
     Shown(Vec<ParameterState>),
 }
 
-#[derive(Debug, Lens, Clone, Data)]
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
 pub struct ParameterState {
     pub name: String,
 
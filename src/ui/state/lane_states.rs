@@ -1,9 +1,10 @@
 use super::{ChannelBaseColor, UiEvent};
+use serde::{Deserialize, Serialize};
 use std::ops::RangeBounds;
 use vizia::prelude::*;
 
 /// The state of every lane in the timeline.
-#[derive(Debug, Lens, Clone)]
+#[derive(Debug, Lens, Clone, Serialize, Deserialize)]
 pub struct LaneStates {
     /// The state of every lane in the timeline.
     pub lanes: Vec<LaneState>,
@@ -267,7 +268,7 @@ impl Model for LaneStates {
     }
 }
 
-#[derive(Debug, Lens, Clone)]
+#[derive(Debug, Lens, Clone, Serialize, Deserialize)]
 pub struct LaneState {
     /// The name of this lane.
     ///
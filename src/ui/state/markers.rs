@@ -0,0 +1,205 @@
+use meadowlark_core_types::time::MusicalTime;
+use serde::{Deserialize, Serialize};
+use vizia::prelude::*;
+
+use super::core_types::WMusicalTime;
+
+/// A named position on the timeline.
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
+pub struct Marker {
+    pub name: String,
+    pub position: WMusicalTime,
+}
+
+/// A named range of the timeline, i.e. a section of a song.
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
+pub struct Region {
+    pub name: String,
+    pub start: WMusicalTime,
+    pub end: WMusicalTime,
+}
+
+impl Region {
+    fn contains(&self, beats: f64) -> bool {
+        beats >= self.start.get().as_beats_f64() && beats < self.end.get().as_beats_f64()
+    }
+}
+
+/// The markers and regions of the project.
+#[derive(Debug, Lens, Clone, Default, Serialize, Deserialize)]
+#[serde(from = "MarkerTrackData")]
+pub struct MarkerTrack {
+    /// Sorted by position.
+    markers: Vec<Marker>,
+
+    /// Sorted by start. Regions may overlap.
+    regions: Vec<Region>,
+}
+
+/// A marker track as it is stored in a project file, which may not be sorted.
+#[derive(Deserialize)]
+struct MarkerTrackData {
+    markers: Vec<Marker>,
+    regions: Vec<Region>,
+}
+
+impl From<MarkerTrackData> for MarkerTrack {
+    /// Empty regions are dropped, the same way they are when they are
+    /// inserted.
+    fn from(data: MarkerTrackData) -> Self {
+        let MarkerTrackData { mut markers, mut regions } = data;
+
+        regions.retain(|r| r.start.get().as_beats_f64() < r.end.get().as_beats_f64());
+
+        markers.sort_by(|a, b| {
+            a.position.get().as_beats_f64().total_cmp(&b.position.get().as_beats_f64())
+        });
+        regions
+            .sort_by(|a, b| a.start.get().as_beats_f64().total_cmp(&b.start.get().as_beats_f64()));
+
+        Self { markers, regions }
+    }
+}
+
+impl MarkerTrack {
+    pub fn markers(&self) -> &[Marker] {
+        &self.markers
+    }
+
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    /// Insert a marker. A marker that is already at `position` is renamed
+    /// instead.
+    pub fn insert_marker(&mut self, name: String, position: MusicalTime) {
+        let beats = position.as_beats_f64();
+        let i = self.markers.partition_point(|m| m.position.get().as_beats_f64() < beats);
+
+        match self.markers.get_mut(i) {
+            Some(marker) if marker.position.get() == position => marker.name = name,
+            _ => self.markers.insert(i, Marker { name, position: position.into() }),
+        }
+    }
+
+    pub fn remove_marker(&mut self, index: usize) {
+        if index < self.markers.len() {
+            self.markers.remove(index);
+        }
+    }
+
+    pub fn rename_marker(&mut self, index: usize, name: String) {
+        if let Some(marker) = self.markers.get_mut(index) {
+            marker.name = name;
+        }
+    }
+
+    /// Insert a region. The region is ignored if it is empty.
+    pub fn insert_region(&mut self, name: String, start: MusicalTime, end: MusicalTime) {
+        let start_beats = start.as_beats_f64();
+        if start_beats >= end.as_beats_f64() {
+            return;
+        }
+
+        let i = self.regions.partition_point(|r| r.start.get().as_beats_f64() <= start_beats);
+        self.regions.insert(i, Region { name, start: start.into(), end: end.into() });
+    }
+
+    pub fn remove_region(&mut self, index: usize) {
+        if index < self.regions.len() {
+            self.regions.remove(index);
+        }
+    }
+
+    pub fn rename_region(&mut self, index: usize, name: String) {
+        if let Some(region) = self.regions.get_mut(index) {
+            region.name = name;
+        }
+    }
+
+    /// The positions of all markers and the starts of all regions, which are
+    /// the places the playhead can jump to.
+    fn locators(&self) -> impl Iterator<Item = MusicalTime> + '_ {
+        self.markers
+            .iter()
+            .map(|m| m.position.get())
+            .chain(self.regions.iter().map(|r| r.start.get()))
+    }
+
+    /// The first locator after `position`.
+    pub fn next_locator(&self, position: MusicalTime) -> Option<MusicalTime> {
+        let beats = position.as_beats_f64();
+        self.locators()
+            .filter(|l| l.as_beats_f64() > beats)
+            .min_by(|a, b| a.as_beats_f64().total_cmp(&b.as_beats_f64()))
+    }
+
+    /// The last locator before `position`.
+    pub fn previous_locator(&self, position: MusicalTime) -> Option<MusicalTime> {
+        let beats = position.as_beats_f64();
+        self.locators()
+            .filter(|l| l.as_beats_f64() < beats)
+            .max_by(|a, b| a.as_beats_f64().total_cmp(&b.as_beats_f64()))
+    }
+
+    /// The region under `position`. If regions overlap, the one that started
+    /// last is returned.
+    pub fn region_at(&self, position: MusicalTime) -> Option<&Region> {
+        let beats = position.as_beats_f64();
+        self.regions.iter().rev().find(|r| r.contains(beats))
+    }
+
+    /// A name for a new marker that is not used yet.
+    pub fn new_marker_name(&self) -> String {
+        unused_name("Marker", self.markers.iter().map(|m| m.name.as_str()))
+    }
+
+    /// A name for a new region that is not used yet.
+    pub fn new_region_name(&self) -> String {
+        unused_name("Region", self.regions.iter().map(|r| r.name.as_str()))
+    }
+}
+
+fn unused_name<'a>(base: &str, used: impl Iterator<Item = &'a str> + Clone) -> String {
+    (1..)
+        .map(|n| format!("{} {}", base, n))
+        .find(|name| !used.clone().any(|used| used == name))
+        .unwrap()
+}
+
+pub enum MarkerEvent {
+    InsertMarker { name: String, position: MusicalTime },
+    RemoveMarker(usize),
+    RenameMarker(usize, String),
+    InsertRegion { name: String, start: MusicalTime, end: MusicalTime },
+    RemoveRegion(usize),
+    RenameRegion(usize, String),
+}
+
+impl Model for MarkerTrack {
+    fn event(&mut self, cx: &mut EventContext, event: &mut Event) {
+        event.map(|marker_event, _| {
+            match marker_event {
+                MarkerEvent::InsertMarker { name, position } => {
+                    self.insert_marker(name.clone(), *position);
+                }
+                MarkerEvent::RemoveMarker(index) => {
+                    self.remove_marker(*index);
+                }
+                MarkerEvent::RenameMarker(index, name) => {
+                    self.rename_marker(*index, name.clone());
+                }
+                MarkerEvent::InsertRegion { name, start, end } => {
+                    self.insert_region(name.clone(), *start, *end);
+                }
+                MarkerEvent::RemoveRegion(index) => {
+                    self.remove_region(*index);
+                }
+                MarkerEvent::RenameRegion(index, name) => {
+                    self.rename_region(*index, name.clone());
+                }
+            }
+            cx.needs_redraw();
+        });
+    }
+}
@@ -0,0 +1,78 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use vizia::prelude::*;
+
+use crate::backend::resource_loader::{ResourceLoaderStats, DEFAULT_MEMORY_BUDGET_BYTES};
+
+static MEMORY_SETTINGS_FILE_NAME: &str = "memory.json";
+
+pub const BYTES_PER_MIB: usize = 1024 * 1024;
+
+/// The memory budgets that are offered in the settings, in MiB.
+pub const MEMORY_BUDGETS_MIB: [usize; 5] = [256, 512, 1024, 2048, 4096];
+
+#[derive(Debug, Lens, Clone, PartialEq, Data, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MemorySettings {
+    /// The amount of memory in MiB that loaded samples can take up before the
+    /// least recently used samples that are no longer in use are dropped.
+    pub memory_budget_mib: usize,
+}
+
+impl Default for MemorySettings {
+    fn default() -> Self {
+        Self { memory_budget_mib: DEFAULT_MEMORY_BUDGET_BYTES / BYTES_PER_MIB }
+    }
+}
+
+impl MemorySettings {
+    /// The file the settings are persisted to.
+    pub fn default_path() -> PathBuf {
+        crate::util::config_dir().join(MEMORY_SETTINGS_FILE_NAME)
+    }
+
+    /// Load the settings from `path`, or return the default settings if they
+    /// do not exist or could not be read.
+    pub fn load_or_default(path: &Path) -> Self {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(_) => return Self::default(),
+        };
+
+        match serde_json::from_str(&contents) {
+            Ok(settings) => settings,
+            Err(e) => {
+                log::error!("Failed to parse memory settings {:?}: {}", path, e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn memory_budget_bytes(&self) -> usize {
+        self.memory_budget_mib.saturating_mul(BYTES_PER_MIB)
+    }
+
+    /// The next budget in `MEMORY_BUDGETS_MIB` after the current one.
+    pub fn next_memory_budget_mib(&self) -> usize {
+        MEMORY_BUDGETS_MIB
+            .iter()
+            .copied()
+            .find(|mib| *mib > self.memory_budget_mib)
+            .unwrap_or(MEMORY_BUDGETS_MIB[0])
+    }
+}
+
+impl Data for ResourceLoaderStats {
+    fn same(&self, other: &Self) -> bool {
+        self == other
+    }
+}
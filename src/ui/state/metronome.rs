@@ -0,0 +1,242 @@
+use meadowlark_core_types::time::MusicalTime;
+use pcm_loader::ResampleQuality;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use vizia::prelude::*;
+
+use crate::backend::metronome_plug::{Click, MetronomePlugHandle};
+use crate::backend::resource_loader::PcmKey;
+
+use super::{NotificationLogType, TempoMap, UiData};
+
+/// The number of beats past the end of the project that the metronome keeps
+/// clicking for.
+const CLICK_HORIZON_BEATS: f64 = 256.0;
+
+/// How many bars the metronome counts in before recording starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Data, Serialize, Deserialize)]
+pub enum CountIn {
+    Off,
+    OneBar,
+    TwoBars,
+}
+
+impl CountIn {
+    pub fn bars(&self) -> u32 {
+        match self {
+            CountIn::Off => 0,
+            CountIn::OneBar => 1,
+            CountIn::TwoBars => 2,
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        match self {
+            CountIn::Off => CountIn::OneBar,
+            CountIn::OneBar => CountIn::TwoBars,
+            CountIn::TwoBars => CountIn::Off,
+        }
+    }
+}
+
+#[derive(Debug, Lens, Clone, Serialize, Deserialize)]
+pub struct MetronomeState {
+    /// Whether the metronome clicks along while the transport is playing.
+    ///
+    /// This does not affect the count-in.
+    pub enabled: bool,
+
+    pub gain_db: f32,
+
+    pub count_in: CountIn,
+
+    /// The sample to play on the first beat of every bar, or `None` to use
+    /// the built-in click.
+    pub downbeat_sample: Option<PathBuf>,
+
+    /// The sample to play on the other beats, or `None` to use the built-in
+    /// click.
+    pub beat_sample: Option<PathBuf>,
+}
+
+impl Default for MetronomeState {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            gain_db: -6.0,
+            count_in: CountIn::Off,
+            downbeat_sample: None,
+            beat_sample: None,
+        }
+    }
+}
+
+/// Returns the frame of every beat from `start` up to `end`, following the
+/// tempo map.
+pub fn metronome_clicks(
+    tempo_map: &TempoMap,
+    start: MusicalTime,
+    end: MusicalTime,
+    sample_rate: u32,
+) -> Vec<Click> {
+    let mut clicks = Vec::new();
+    for bar in tempo_map.bars(start.as_beats_f64(), end.as_beats_f64()) {
+        for beat in 0..bar.beats {
+            let beats = bar.start + f64::from(beat) * bar.beat_length;
+            clicks.push(Click {
+                frame: tempo_map.musical_to_frames(MusicalTime::from_beats_f64(beats), sample_rate),
+                downbeat: beat == 0,
+            });
+        }
+    }
+    clicks
+}
+
+/// Returns the clicks of a count-in that leads up to `position`, along with
+/// the length of the count-in in frames.
+///
+/// The count-in uses the tempo and time signature at `position`.
+pub fn count_in_clicks(
+    tempo_map: &TempoMap,
+    position: MusicalTime,
+    bars: u32,
+    sample_rate: u32,
+) -> (Vec<Click>, u64) {
+    let (numerator, denominator) = tempo_map.time_signature_at(position);
+    let beat_seconds = 60.0 / tempo_map.tempo_at(position) * 4.0 / f64::from(denominator);
+    let beat_frames = beat_seconds * f64::from(sample_rate);
+
+    let clicks = (0..bars * numerator)
+        .map(|beat| Click {
+            frame: (f64::from(beat) * beat_frames).round() as u64,
+            downbeat: beat % numerator == 0,
+        })
+        .collect();
+    let len_frames = (f64::from(bars * numerator) * beat_frames).round() as u64;
+
+    (clicks, len_frames)
+}
+
+pub enum MetronomeEvent {
+    SetEnabled(bool),
+    ToggleEnabled,
+    SetGainDb(f32),
+    SetCountIn(CountIn),
+    /// Switch between no count-in, one bar, and two bars.
+    CycleCountIn,
+    /// Set the sample played on the first beat of every bar. `None` uses the
+    /// built-in click.
+    SetDownbeatSample(Option<PathBuf>),
+    /// Set the sample played on the other beats. `None` uses the built-in
+    /// click.
+    SetBeatSample(Option<PathBuf>),
+}
+
+impl UiData {
+    pub(super) fn on_metronome_event(&mut self, metronome_event: &MetronomeEvent) {
+        let metronome = &mut self.state.metronome;
+        match metronome_event {
+            MetronomeEvent::SetEnabled(enabled) => metronome.enabled = *enabled,
+            MetronomeEvent::ToggleEnabled => metronome.enabled = !metronome.enabled,
+            MetronomeEvent::SetGainDb(gain_db) => metronome.gain_db = *gain_db,
+            MetronomeEvent::SetCountIn(count_in) => metronome.count_in = *count_in,
+            MetronomeEvent::CycleCountIn => metronome.count_in = metronome.count_in.next(),
+            MetronomeEvent::SetDownbeatSample(path) => metronome.downbeat_sample = path.clone(),
+            MetronomeEvent::SetBeatSample(path) => metronome.beat_sample = path.clone(),
+        }
+
+        match metronome_event {
+            MetronomeEvent::SetDownbeatSample(_) | MetronomeEvent::SetBeatSample(_) => {
+                self.sync_metronome();
+            }
+            _ => self.sync_metronome_params(),
+        }
+    }
+
+    pub(super) fn metronome_plug_handle(&mut self) -> Option<&mut MetronomePlugHandle> {
+        self.engine_handles
+            .as_mut()
+            .and_then(|(h, _)| h.metronome_plug_handle.as_mut())
+            .and_then(|h| h.internal.as_mut())
+            .and_then(|h| h.downcast_mut::<MetronomePlugHandle>())
+    }
+
+    /// Send the enabled state and gain to the metronome plugin.
+    fn sync_metronome_params(&mut self) {
+        // The metronome is never heard in renders.
+        let enabled = self.state.metronome.enabled && self.render.is_none();
+        let gain_db = self.state.metronome.gain_db;
+
+        if let Some(handle) = self.metronome_plug_handle() {
+            handle.set_enabled(enabled);
+            handle.set_gain_db(gain_db);
+        }
+    }
+
+    /// Send the clicks of the current tempo map to the metronome plugin.
+    pub(super) fn sync_metronome_clicks(&mut self) {
+        let sample_rate =
+            match self.engine_handles.as_ref().and_then(|(h, _)| h.activated_info.as_ref()) {
+                Some(activated_info) => activated_info.sample_rate.as_u32(),
+                None => return,
+            };
+
+        let state = &self.state;
+        let end = state
+            .timeline_grid
+            .project_length
+            .get()
+            .as_beats_f64()
+            .max(state.transport.loop_end.get().as_beats_f64())
+            .max(state.transport.playhead.get().as_beats_f64())
+            + CLICK_HORIZON_BEATS;
+        let clicks = metronome_clicks(
+            &state.timeline_grid.tempo_map,
+            MusicalTime::from_beats(0),
+            MusicalTime::from_beats_f64(end),
+            sample_rate,
+        );
+
+        if let Some(handle) = self.metronome_plug_handle() {
+            handle.set_clicks(clicks);
+        }
+    }
+
+    /// Bring the metronome plugin up to date with the project, i.e. once it
+    /// has been added to the audio graph.
+    pub(super) fn sync_metronome(&mut self) {
+        if self.metronome_plug_handle().is_none() {
+            return;
+        }
+
+        // Click samples are short, so they are loaded on this thread.
+        let mut load = |path: &Option<PathBuf>| {
+            let path = path.as_ref()?;
+            let key = PcmKey {
+                path: path.clone(),
+                resample_to_project_sr: true,
+                resample_quality: ResampleQuality::Linear,
+                doppler_stretch_ratio: 1.0,
+            };
+            match self.resource_loader.load_pcm(&key) {
+                (pcm, Ok(())) => Some(pcm),
+                (_, Err(e)) => {
+                    self.notification_log.push(NotificationLogType::Error(format!(
+                        "Failed to load metronome sample {:?}: {}",
+                        path, e
+                    )));
+                    None
+                }
+            }
+        };
+        let downbeat = load(&self.state.metronome.downbeat_sample);
+        let beat = load(&self.state.metronome.beat_sample);
+
+        if let Some(handle) = self.metronome_plug_handle() {
+            handle.set_sounds(downbeat, beat);
+        }
+
+        self.sync_metronome_params();
+        self.sync_metronome_clicks();
+    }
+}
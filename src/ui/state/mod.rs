@@ -3,7 +3,7 @@ use dropseed::plugin::PluginSaveState;
 use dropseed::plugin::{HostInfo, ParamID, PluginInstanceID};
 use dropseed::{
     transport::TransportHandle, ActivateEngineSettings, ActivatePluginError, DSEngineEvent,
-    DSEngineHandle, DSEngineRequest, EdgeReq, EdgeReqPortID, EngineActivatedInfo,
+    DSEngineHandle, DSEngineRequest, DSSaveState, EdgeReq, EdgeReqPortID, EngineActivatedInfo,
     EngineDeactivatedInfo, ModifyGraphRequest, ModifyGraphRes, ParamModifiedInfo,
     PluginActivationStatus, PluginEvent, PluginHandle, PluginIDReq, PluginScannerEvent, PortType,
     RescanPluginDirectoriesRes,
@@ -12,48 +12,102 @@ use dropseed::{
 use fnv::FnvHashMap;
 use meadowlark_core_types::time::{MusicalTime, SampleRate};
 use pcm_loader::ResampleQuality;
+use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
+use std::collections::VecDeque;
 use std::error::Error;
-use std::{fmt::Debug, path::PathBuf};
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use vizia::prelude::*;
 
-use crate::backend::resource_loader::{PcmKey, ResourceLoader};
+use crate::backend::disk_stream::DiskStreamError;
+use crate::backend::metronome_plug::{MetronomePlugFactory, METRONOME_PLUG_RDN};
+use crate::backend::resource_loader::{
+    PcmKey, PcmLoadHandle, ResourceLoader, ResourceLoaderEvent, ResourceLoaderStats,
+};
 use crate::backend::sample_browser_plug::{
     SampleBrowserPlugFactory, SampleBrowserPlugHandle, SAMPLE_BROWSER_PLUG_RDN,
 };
-use crate::backend::system_io::{self, SystemIOStreamHandle};
+use crate::backend::system_io::{
+    self, SystemIOBackend, SystemIOConfig, SystemIOStreamError, SystemIOStreamHandle,
+};
+use crate::backend::timeline_track::{TimelineTrackPlugFactory, TIMELINE_TRACK_PLUG_RDN};
 
+mod audio_settings;
 mod browser;
 mod channel;
 mod clip;
 mod core_types;
 mod event;
+mod groove;
 mod hrack_effect;
 mod lane_states;
+mod markers;
+mod memory_settings;
+mod metronome;
 mod panel;
+mod project;
+mod render;
+mod tempo_map;
 mod timeline_grid;
+mod timeline_tracks;
+mod transport;
 
+pub use audio_settings::*;
 pub use browser::*;
 pub use channel::*;
 pub use clip::*;
 pub use core_types::*;
 pub use event::*;
+pub use groove::*;
 pub use hrack_effect::*;
 pub use lane_states::*;
+pub use markers::*;
+pub use memory_settings::*;
+pub use metronome::*;
 pub use panel::*;
+pub use project::*;
+pub use render::*;
+pub use tempo_map::*;
 pub use timeline_grid::*;
+pub use timeline_tracks::*;
+pub use transport::*;
+
+/// The time to wait before trying to start the system IO stream again after
+/// it failed to start.
+const SYSTEM_IO_RETRY_INTERVAL: Duration = Duration::from_secs(3);
+
+/// The minimum time between notifications about disk streaming underruns.
+const UNDERRUN_REPORT_INTERVAL: Duration = Duration::from_secs(5);
 
-// TODO: Have these be configurable.
-const MIN_FRAMES: u32 = 1;
-const MAX_FRAMES: u32 = 512;
-const GRAPH_IN_CHANNELS: u16 = 2;
-const GRAPH_OUT_CHANNELS: u16 = 2;
+/// Files at least this large show a notice while the browser is loading them.
+const LOADING_NOTICE_FILE_BYTES: u64 = 8 * 1024 * 1024;
+
+/// How long to wait for the engine to send back the save state for a recovery
+/// snapshot before giving up on that snapshot.
+const AUTOSAVE_SAVE_STATE_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub struct EngineHandles {
     ds_handle: DSEngineHandle,
 
     activated_info: Option<ActivatedEngineInfo>,
     sample_browser_plug_handle: Option<PluginHandle>,
+    metronome_plug_handle: Option<PluginHandle>,
+
+    /// The plugins that play the clips on the timeline.
+    timeline_tracks: TimelineTracks,
+
+    /// The handles to all of the plugins in the effect racks.
+    effect_plug_handles: FnvHashMap<PluginInstanceID, PluginHandle>,
+
+    /// Where each plugin in the audio graph lives in the effect racks, as
+    /// `(channel index, effect index)`.
+    effect_plug_locations: FnvHashMap<PluginInstanceID, (usize, usize)>,
+
+    /// The save state to restore the audio graph from once the engine has
+    /// been activated.
+    restore_on_activate: Option<DSSaveState>,
 }
 
 pub struct ActivatedEngineInfo {
@@ -102,6 +156,17 @@ pub struct ActiveEngineInfo {
     pub num_audio_out_channels: u16,
 }
 
+/// A request to write the project to disk.
+struct SaveRequest {
+    path: PathBuf,
+
+    /// Whether to copy all of the samples into the project folder first.
+    collect_samples: bool,
+
+    /// If set, also write the project and its samples to this archive.
+    archive_path: Option<PathBuf>,
+}
+
 #[derive(Lens)]
 pub struct UiData {
     pub state: UiState,
@@ -121,7 +186,102 @@ pub struct UiData {
     pub resource_loader: ResourceLoader,
 
     #[lens(ignore)]
-    last_clicked_browser_file: Option<PathBuf>,
+    last_clicked_browser_key: Option<PcmKey>,
+
+    /// The sample that will be played in the browser once it has finished
+    /// loading.
+    #[lens(ignore)]
+    pending_browser_load: Option<PcmLoadHandle>,
+
+    /// The audio of the clips on the timeline that is being loaded or
+    /// streamed.
+    #[lens(ignore)]
+    timeline_sources: TimelineSources,
+
+    /// The file the current project was last saved to or loaded from.
+    #[lens(ignore)]
+    project_path: Option<PathBuf>,
+
+    /// The save to perform once the engine has sent back its latest save
+    /// state.
+    #[lens(ignore)]
+    pending_save: Option<SaveRequest>,
+
+    /// The samples used by the project that could not be found.
+    #[lens(ignore)]
+    missing_files: Vec<MissingFile>,
+
+    /// The samples that are being copied into the project folder before the
+    /// project is saved.
+    #[lens(ignore)]
+    collect_job: Option<CollectJob>,
+
+    /// The search for the samples in `missing_files`.
+    #[lens(ignore)]
+    relink_job: Option<RelinkJob>,
+
+    #[lens(ignore)]
+    autosaver: Autosaver,
+
+    /// How often recovery snapshots are written and where to. This is
+    /// persisted between sessions.
+    pub autosave_settings: AutosaveSettings,
+
+    /// When the engine was asked for the save state that the next recovery
+    /// snapshot will be written with, or `None` if no snapshot is waiting on
+    /// the engine.
+    #[lens(ignore)]
+    pending_autosave: Option<Instant>,
+
+    /// How much memory the loaded samples can take up. This is persisted
+    /// between sessions.
+    pub memory_settings: MemorySettings,
+
+    /// The memory taken up by the loaded samples, as shown in the settings.
+    pub resource_stats: ResourceLoaderStats,
+
+    /// The choices shown in the audio settings.
+    pub audio_settings: AudioSettingsState,
+
+    /// The audio host, device, and stream settings chosen by the user. This
+    /// is persisted between sessions.
+    #[lens(ignore)]
+    system_io_config: SystemIOConfig,
+
+    /// True if the system IO stream should be restarted with the new config
+    /// once the engine has been deactivated.
+    #[lens(ignore)]
+    restart_system_io_on_deactivate: bool,
+
+    /// When to try starting the system IO stream again after it failed to
+    /// start.
+    #[lens(ignore)]
+    system_io_retry_at: Option<Instant>,
+
+    /// The options for rendering the project to an audio file.
+    pub render_settings: RenderSettingsState,
+
+    /// The progress of the current render in the range `[0.0, 1.0]`, or `None`
+    /// if nothing is being rendered.
+    pub render_progress: Option<f32>,
+
+    #[lens(ignore)]
+    render: Option<RenderJob>,
+
+    /// The regions that are left to render once the current render has
+    /// finished.
+    #[lens(ignore)]
+    pending_region_renders: VecDeque<PendingRender>,
+
+    #[lens(ignore)]
+    tap_tempo: TapTempo,
+
+    /// The number of disk streaming underruns that have not been reported
+    /// yet, and when they were last reported.
+    #[lens(ignore)]
+    unreported_underruns: u32,
+    #[lens(ignore)]
+    last_underrun_report: Option<Instant>,
 
     #[lens(ignore)]
     system_io_stream_handle: Option<SystemIOStreamHandle>,
@@ -133,12 +293,31 @@ pub struct UiData {
 impl UiData {
     // Create some dummy state for now
     pub fn new() -> Result<Self, Box<dyn Error>> {
-        // This is temporary. Eventually we will have a more sophisticated and
-        // configurable system using `rainout`.
-        let system_io_stream_handle = system_io::temp_spawn_cpal_default_output_only()?;
+        let system_io_config = SystemIOConfig::load_or_default(&SystemIOConfig::default_path());
+        let autosave_settings =
+            AutosaveSettings::load_or_default(&AutosaveSettings::default_path());
+        let memory_settings = MemorySettings::load_or_default(&MemorySettings::default_path());
+
+        let system_io_stream_handle = match system_io::spawn_stream(&system_io_config) {
+            Ok(handle) => handle,
+            Err(e) => {
+                // The chosen device may have been unplugged since the last session.
+                log::error!("Failed to start audio stream, falling back to the default: {}", e);
+
+                system_io::spawn_stream(&SystemIOConfig::default()).or_else(|e| {
+                    // Keep the app usable on machines with no sound hardware.
+                    log::error!("Failed to start audio stream, falling back to no audio: {}", e);
+                    system_io::spawn_stream(&SystemIOConfig {
+                        backend: SystemIOBackend::Null,
+                        ..SystemIOConfig::default()
+                    })
+                })?
+            }
+        };
         let sample_rate = system_io_stream_handle.sample_rate();
 
-        let resource_loader = ResourceLoader::new(sample_rate);
+        let mut resource_loader = ResourceLoader::new(sample_rate);
+        resource_loader.set_memory_budget(memory_settings.memory_budget_bytes());
 
         let mut app_data = UiData {
             state: UiState {
@@ -222,9 +401,14 @@ impl UiData {
                             selected: false,
                         },
                     ]),
-                    project_length: MusicalTime::from_beats(16).into(),
+                    project_length: MusicalTime::from_beats(DEFAULT_PROJECT_LENGTH_BEATS).into(),
                     used_lanes: 0,
+                    tempo_map: TempoMap::default(),
+                    markers: MarkerTrack::default(),
                 },
+                transport: TransportState::default(),
+                metronome: MetronomeState::default(),
+                groove: GrooveState::default(),
                 browser: BrowserState::default(),
                 panels: PanelState {
                     channel_rack_orientation: ChannelRackOrientation::Horizontal,
@@ -232,65 +416,277 @@ impl UiData {
                     hide_piano_roll: false,
                     browser_width: 200.0,
                     hide_browser: false,
+                    show_groove: false,
+                    show_settings: false,
                 },
                 dragging_channel: None,
             },
             resource_loader,
             notification_log: Vec::new(),
             engine_running: false,
+            memory_settings,
+            resource_stats: ResourceLoaderStats::default(),
+            audio_settings: AudioSettingsState::new(&system_io_config),
+            system_io_config,
+            restart_system_io_on_deactivate: false,
+            system_io_retry_at: None,
+            render_settings: RenderSettingsState::default(),
+            render_progress: None,
+            render: None,
+            pending_region_renders: VecDeque::new(),
+            tap_tempo: TapTempo::default(),
+            unreported_underruns: 0,
+            last_underrun_report: None,
             system_io_stream_handle: Some(system_io_stream_handle),
-            last_clicked_browser_file: None,
+            last_clicked_browser_key: None,
+            pending_browser_load: None,
+            timeline_sources: TimelineSources::default(),
+            project_path: None,
+            pending_save: None,
+            missing_files: Vec::new(),
+            collect_job: None,
+            relink_job: None,
+            autosaver: Autosaver::new(autosave_settings.clone()),
+            autosave_settings,
+            pending_autosave: None,
             engine_handles: None,
         };
 
         app_data.activate_engine();
 
+        app_data.offer_recovery();
+
         Ok(app_data)
     }
 
     pub fn activate_engine(&mut self) {
         if let Some(system_io_stream_handle) = &mut self.system_io_stream_handle {
-            let (mut engine_handle, engine_rx) = DSEngineHandle::new(
-                HostInfo::new(
-                    String::from("RustyDAW integration test"),
-                    String::from("0.1.0"),
-                    None,
-                    None,
-                ),
-                vec![Box::new(SampleBrowserPlugFactory)],
+            if self.engine_handles.is_none() {
+                let (mut engine_handle, engine_rx) = DSEngineHandle::new(
+                    HostInfo::new(
+                        String::from("RustyDAW integration test"),
+                        String::from("0.1.0"),
+                        None,
+                        None,
+                    ),
+                    vec![
+                        Box::new(SampleBrowserPlugFactory),
+                        Box::new(MetronomePlugFactory),
+                        Box::new(TimelineTrackPlugFactory),
+                    ],
+                );
+
+                log::debug!("{:?}", &engine_handle.internal_plugins_res);
+
+                engine_handle.send(DSEngineRequest::RescanPluginDirectories);
+
+                self.engine_handles = Some((
+                    EngineHandles {
+                        ds_handle: engine_handle,
+                        activated_info: None,
+                        sample_browser_plug_handle: None,
+                        metronome_plug_handle: None,
+                        timeline_tracks: TimelineTracks::default(),
+                        effect_plug_handles: FnvHashMap::default(),
+                        effect_plug_locations: FnvHashMap::default(),
+                        restore_on_activate: None,
+                    },
+                    engine_rx,
+                ));
+            }
+
+            let (engine_handles, _) = self.engine_handles.as_mut().unwrap();
+
+            engine_handles.ds_handle.send(DSEngineRequest::ActivateEngine(Box::new(
+                ActivateEngineSettings {
+                    sample_rate: system_io_stream_handle.sample_rate(),
+                    min_frames: system_io_stream_handle.min_frames(),
+                    max_frames: system_io_stream_handle.max_frames(),
+                    num_audio_in_channels: system_io_stream_handle.graph_in_channels(),
+                    num_audio_out_channels: system_io_stream_handle.graph_out_channels(),
+                    ..ActivateEngineSettings::default()
+                },
+            )));
+        } else {
+            log::warn!("Cannot activate engine until a system IO stream is started");
+        }
+    }
+
+    /// Use a new audio host, device, or stream settings.
+    ///
+    /// The new config is persisted, and the system IO stream is restarted
+    /// with it.
+    fn set_system_io_config(&mut self, config: SystemIOConfig) {
+        if config == self.system_io_config {
+            return;
+        }
+
+        self.system_io_config = config;
+
+        if let Err(e) = self.system_io_config.save(&SystemIOConfig::default_path()) {
+            log::error!("Failed to save system IO config: {}", e);
+            self.notification_log
+                .push(NotificationLogType::Error(format!("Failed to save audio settings: {}", e)));
+        }
+
+        self.audio_settings.refresh(&self.system_io_config);
+
+        self.reconnect_system_io();
+    }
+
+    /// Tear down the system IO stream and build a new one using the current
+    /// config, then reactivate the engine in it.
+    ///
+    /// The project state and audio graph are kept intact.
+    fn reconnect_system_io(&mut self) {
+        if self.restart_system_io_on_deactivate || self.render.is_some() {
+            // Already waiting on the engine to deactivate, or the render stream
+            // is running. The live stream is restarted with the current config
+            // once the render has ended.
+            return;
+        }
+
+        self.system_io_retry_at = None;
+
+        // The engine must be deactivated before the stream it runs in can be
+        // replaced. The stream is restarted once the engine has sent back the
+        // save state of the audio graph and has been deactivated.
+        if let Some((engine_handles, _)) = &mut self.engine_handles {
+            if engine_handles.activated_info.is_some() {
+                self.restart_system_io_on_deactivate = true;
+                engine_handles.ds_handle.send(DSEngineRequest::RequestLatestSaveState);
+                engine_handles.ds_handle.send(DSEngineRequest::DeactivateEngine);
+                return;
+            }
+        }
+
+        self.restart_system_io();
+    }
+
+    /// This is called when the system IO stream stopped because of an error
+    /// (i.e. the audio device was unplugged).
+    fn on_system_io_errors(&mut self, errors: Vec<SystemIOStreamError>) {
+        for e in errors.iter() {
+            log::error!("{}", e);
+            self.notification_log.push(NotificationLogType::Error(e.to_string()));
+        }
+
+        self.reconnect_system_io();
+    }
+
+    /// Collect the underruns of the plugins that stream files from disk, and
+    /// let the user know if the disk could not keep up.
+    fn poll_underruns(&mut self) {
+        if let Some((engine_handles, _)) = &mut self.engine_handles {
+            if let Some(handle) = engine_handles
+                .sample_browser_plug_handle
+                .as_mut()
+                .and_then(|h| h.internal.as_mut())
+                .and_then(|h| h.downcast_mut::<SampleBrowserPlugHandle>())
+            {
+                self.unreported_underruns += handle.take_underruns();
+            }
+
+            self.unreported_underruns += engine_handles.timeline_tracks.take_underruns();
+        }
+
+        if self.unreported_underruns == 0 {
+            return;
+        }
+
+        let due = self
+            .last_underrun_report
+            .map(|last| last.elapsed() >= UNDERRUN_REPORT_INTERVAL)
+            .unwrap_or(true);
+        if due {
+            let msg = format!(
+                "Streaming audio from disk could not keep up ({} underruns)",
+                self.unreported_underruns
             );
+            log::warn!("{}", &msg);
+            self.notification_log.push(NotificationLogType::Error(msg));
+
+            self.unreported_underruns = 0;
+            self.last_underrun_report = Some(Instant::now());
+        }
+    }
 
-            log::debug!("{:?}", &engine_handle.internal_plugins_res);
+    /// Replace the system IO stream with a new one using the current config,
+    /// and reactivate the engine in it.
+    fn restart_system_io(&mut self) {
+        // Close the old stream before opening the device again.
+        self.system_io_stream_handle = None;
 
-            let sample_rate = system_io_stream_handle.sample_rate();
+        let res = system_io::spawn_stream(&self.system_io_config).or_else(|e| {
+            log::error!("Failed to start audio stream: {}", e);
+            self.notification_log
+                .push(NotificationLogType::Error(format!("Failed to start audio stream: {}", e)));
 
-            engine_handle.send(DSEngineRequest::ActivateEngine(Box::new(ActivateEngineSettings {
-                sample_rate,
-                min_frames: MIN_FRAMES,
-                max_frames: MAX_FRAMES,
-                num_audio_in_channels: GRAPH_IN_CHANNELS,
-                num_audio_out_channels: GRAPH_OUT_CHANNELS,
-                ..ActivateEngineSettings::default()
-            })));
+            if self.system_io_config == SystemIOConfig::default() {
+                return Err(e);
+            }
 
-            engine_handle.send(DSEngineRequest::RescanPluginDirectories);
+            // Fall back to the default device. The user's choice is kept, so it
+            // will be used again the next time the stream is reconnected.
+            let res = system_io::spawn_stream(&SystemIOConfig::default());
+            if res.is_ok() {
+                self.notification_log.push(NotificationLogType::Info(String::from(
+                    "Using the default audio device instead",
+                )));
+            }
+            res
+        });
 
-            self.engine_handles = Some((
-                EngineHandles {
-                    ds_handle: engine_handle,
-                    activated_info: None,
-                    sample_browser_plug_handle: None,
-                },
-                engine_rx,
-            ));
-        } else {
-            log::warn!("Cannot activate engine until a system IO stream is started");
+        match res {
+            Ok(system_io_stream_handle) => {
+                if self.system_io_config.input_enabled && !system_io_stream_handle.has_input() {
+                    self.notification_log.push(NotificationLogType::Error(String::from(
+                        "Failed to start audio input, continuing without input",
+                    )));
+                }
+
+                self.resource_loader.set_project_sample_rate(system_io_stream_handle.sample_rate());
+                self.last_clicked_browser_key = None;
+                self.pending_browser_load = None;
+                self.timeline_sources.clear();
+
+                self.system_io_stream_handle = Some(system_io_stream_handle);
+
+                self.activate_engine();
+            }
+            Err(e) => {
+                log::error!("Failed to start the default audio stream: {}", e);
+
+                self.system_io_retry_at = Some(Instant::now() + SYSTEM_IO_RETRY_INTERVAL);
+            }
         }
     }
 
     pub fn poll_engine(&mut self) {
+        let stream_errors = self
+            .system_io_stream_handle
+            .as_mut()
+            .map(|handle| handle.take_errors())
+            .unwrap_or_default();
+        if !stream_errors.is_empty() {
+            self.on_system_io_errors(stream_errors);
+        }
+
+        if let Some(retry_at) = self.system_io_retry_at {
+            if Instant::now() >= retry_at {
+                self.system_io_retry_at = None;
+                self.restart_system_io();
+            }
+        }
+
         let Self { state, system_io_stream_handle, engine_handles, resource_loader, .. } = self;
 
+        let mut new_save_state = None;
+        let mut restart_system_io = false;
+        let mut start_render_stream = false;
+        let mut audio_graph_modified = false;
+        let mut unmatched_plugins = Vec::new();
+
         if let Some((engine_handles, engine_rx)) = engine_handles {
             //let EngineHandles { handle, rx, activated_info, sample_browser_plug_handle } = engine_handle;
 
@@ -305,28 +701,49 @@ impl UiData {
                     }
                     // TODO: Hint to the compiler that this is the next most likely event?
                     DSEngineEvent::AudioGraphModified(event) => {
-                        state.on_audio_graph_modified(event, engine_handles);
+                        unmatched_plugins
+                            .extend(state.on_audio_graph_modified(event, engine_handles));
+                        audio_graph_modified = true;
                     }
                     DSEngineEvent::Plugin(PluginEvent::Activated {
                         plugin_id,
                         new_handle,
                         new_param_values,
                     }) => {
-                        state.on_plugin_activated(plugin_id, new_handle, new_param_values);
+                        state.on_plugin_activated(
+                            plugin_id,
+                            new_handle,
+                            new_param_values,
+                            engine_handles,
+                        );
                     }
                     DSEngineEvent::Plugin(PluginEvent::Deactivated { plugin_id, status }) => {
-                        state.on_plugin_deactivated(plugin_id, status);
+                        state.on_plugin_deactivated(plugin_id, status, engine_handles);
                     }
                     DSEngineEvent::EngineDeactivated(event) => {
                         self.engine_running = false;
+                        // The save state for the snapshot will not arrive
+                        // anymore. A new snapshot is written on the next
+                        // interval.
+                        self.pending_autosave = None;
                         state.on_engine_deactivated(event, engine_handles, system_io_stream_handle);
+
+                        if self.restart_system_io_on_deactivate {
+                            self.restart_system_io_on_deactivate = false;
+                            restart_system_io = true;
+                        } else if matches!(self.render, Some(RenderJob::Deactivating { .. })) {
+                            start_render_stream = true;
+                        }
                     }
                     DSEngineEvent::EngineActivated(event) => {
                         self.engine_running = true;
                         state.on_engine_activated(event, engine_handles, system_io_stream_handle);
                     }
                     DSEngineEvent::AudioGraphCleared => {
-                        state.on_audio_graph_cleared();
+                        state.on_audio_graph_cleared(engine_handles);
+                    }
+                    DSEngineEvent::NewSaveState(save_state) => {
+                        new_save_state = Some(save_state);
                     }
                     DSEngineEvent::PluginScanner(PluginScannerEvent::ClapScanPathAdded(path)) => {
                         state.on_clap_scan_path_added(path);
@@ -344,11 +761,530 @@ impl UiData {
             }
         }
 
-        // Clean up loaded resources that are no longer being used.
-        //
-        // TODO: Only call this periodically (i.e. every 3 seconds or so), because
-        // this can get expensive when a lot of resources are loaded in the project.
-        resource_loader.collect();
+        let mut timeline_samples_loaded = false;
+        for event in resource_loader.poll() {
+            match event {
+                ResourceLoaderEvent::LoadStarted { handle, path, file_size } => {
+                    if self.pending_browser_load == Some(handle)
+                        && file_size >= LOADING_NOTICE_FILE_BYTES
+                    {
+                        self.notification_log.push(NotificationLogType::Info(format!(
+                            "Loading {} ({:.1} MB)...",
+                            path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default(),
+                            file_size as f64 / (1024.0 * 1024.0)
+                        )));
+                    }
+                }
+                ResourceLoaderEvent::Loaded { handle, pcm } => {
+                    if self.timeline_sources.on_loaded(handle) {
+                        timeline_samples_loaded = true;
+                    }
+
+                    if self.pending_browser_load == Some(handle) {
+                        self.pending_browser_load = None;
+
+                        if let Some(browser_plug_handle) = engine_handles
+                            .as_mut()
+                            .and_then(|(h, _)| h.sample_browser_plug_handle.as_mut())
+                        {
+                            browser_plug_handle
+                                .internal
+                                .as_mut()
+                                .unwrap()
+                                .downcast_mut::<SampleBrowserPlugHandle>()
+                                .unwrap()
+                                .play_sample(pcm);
+                        }
+                    }
+                }
+                ResourceLoaderEvent::LoadFailed { handle, path, error } => {
+                    self.timeline_sources.on_load_failed(handle);
+
+                    if self.pending_browser_load == Some(handle) {
+                        log::error!("Failed to load pcm resource {:?}: {}", &path, &error);
+
+                        self.pending_browser_load = None;
+                        self.last_clicked_browser_key = None;
+                    }
+                }
+                ResourceLoaderEvent::StreamOpened { handle, stream } => {
+                    if self.pending_browser_load == Some(handle) {
+                        self.pending_browser_load = None;
+
+                        if let Some(browser_plug_handle) = engine_handles
+                            .as_mut()
+                            .and_then(|(h, _)| h.sample_browser_plug_handle.as_mut())
+                        {
+                            browser_plug_handle
+                                .internal
+                                .as_mut()
+                                .unwrap()
+                                .downcast_mut::<SampleBrowserPlugHandle>()
+                                .unwrap()
+                                .play_stream(stream);
+                        }
+                    } else if self.timeline_sources.on_stream_opened(handle, stream) {
+                        timeline_samples_loaded = true;
+                    }
+                }
+                ResourceLoaderEvent::StreamFailed { handle, error } => {
+                    let needs_resample = matches!(error, DiskStreamError::NeedsResample { .. });
+
+                    if self.pending_browser_load == Some(handle) {
+                        self.pending_browser_load = None;
+
+                        match &self.last_clicked_browser_key {
+                            // Load the whole file into RAM instead.
+                            Some(key) if needs_resample => {
+                                self.pending_browser_load = Some(resource_loader.request_pcm(key));
+                            }
+                            _ => {
+                                self.notification_log
+                                    .push(NotificationLogType::Error(error.to_string()));
+                                self.last_clicked_browser_key = None;
+                            }
+                        }
+                    } else if self.timeline_sources.on_stream_failed(handle) {
+                        // The clip is left out of its timeline track, unless
+                        // the file can be loaded into RAM instead.
+                        timeline_samples_loaded = true;
+                    }
+                }
+            }
+        }
+
+        // Clean up loaded resources that are no longer being used. This only
+        // runs periodically, because it can get expensive when a lot of
+        // resources are loaded in the project.
+        resource_loader.collect_if_due();
+        self.resource_stats = resource_loader.stats();
+
+        let newly_missing: Vec<MissingFile> = resource_loader
+            .take_missing_files()
+            .into_iter()
+            .filter(|path| !self.missing_files.iter().any(|m| &m.path == path))
+            .map(|path| {
+                let hash = self
+                    .state
+                    .audio_clips()
+                    .find(|clip| clip.pcm_path == path)
+                    .and_then(|clip| clip.pcm_hash);
+                MissingFile { path, hash }
+            })
+            .collect();
+        if !newly_missing.is_empty() {
+            self.report_missing_files(&newly_missing);
+            self.missing_files.extend(newly_missing);
+        }
+
+        if let Some(save_state) = new_save_state {
+            if let Some(request) = self.pending_save.take() {
+                self.write_project(request, Some(&save_state));
+            }
+            if self.pending_autosave.take().is_some() {
+                self.write_snapshot(Some(&save_state));
+            }
+            // The save state and the `EngineDeactivated` event may arrive in
+            // different polls, so keep it as long as the engine is being
+            // deactivated to restart it.
+            let deactivating = restart_system_io
+                || self.restart_system_io_on_deactivate
+                || matches!(self.render, Some(RenderJob::Deactivating { .. }));
+            if deactivating {
+                if let Some((engine_handles, _)) = &mut self.engine_handles {
+                    engine_handles.restore_on_activate = Some(save_state);
+                }
+            }
+        }
+
+        if restart_system_io {
+            self.restart_system_io();
+        } else if start_render_stream {
+            self.spawn_render_stream();
+        }
+
+        if !unmatched_plugins.is_empty() {
+            let msg = format!(
+                "{} plugin(s) in the audio graph did not match an effect in the project and were added to the master channel: {}",
+                unmatched_plugins.len(),
+                unmatched_plugins.join(", ")
+            );
+            log::warn!("{}", &msg);
+            self.notification_log.push(NotificationLogType::Error(msg));
+        }
+
+        if audio_graph_modified {
+            self.sync_metronome();
+            self.sync_timeline_tracks();
+        } else if timeline_samples_loaded {
+            self.sync_timeline_clips();
+        }
+
+        // The render waits until every timeline track is in the audio graph.
+        let tracks_ready =
+            self.engine_handles.as_ref().map(|(h, _)| h.timeline_tracks.is_ready()).unwrap_or(true);
+        self.poll_render(audio_graph_modified && tracks_ready);
+        self.poll_underruns();
+        self.poll_collect();
+        self.poll_relink();
+
+        if let Some(requested_at) = self.pending_autosave {
+            if requested_at.elapsed() >= AUTOSAVE_SAVE_STATE_TIMEOUT {
+                log::warn!("Engine did not send a save state for the autosave snapshot in time");
+                self.pending_autosave = None;
+            }
+        }
+
+        if self.autosaver.is_due() && self.pending_autosave.is_none() {
+            self.autosave();
+        }
+    }
+
+    /// Write a recovery snapshot of the project.
+    ///
+    /// If the engine is running, then the snapshot is written once the engine
+    /// sends back the latest save state of the audio graph.
+    fn autosave(&mut self) {
+        self.autosaver.reset_timer();
+
+        if let Some((engine_handles, _)) = &mut self.engine_handles {
+            if engine_handles.activated_info.is_some() {
+                self.pending_autosave = Some(Instant::now());
+                engine_handles.ds_handle.send(DSEngineRequest::RequestLatestSaveState);
+                return;
+            }
+        }
+
+        self.write_snapshot(None);
+    }
+
+    fn set_autosave_settings(&mut self, settings: AutosaveSettings) {
+        if settings == self.autosave_settings {
+            return;
+        }
+
+        if let Err(e) = settings.save(&AutosaveSettings::default_path()) {
+            log::error!("Failed to save autosave settings: {}", e);
+            self.notification_log.push(NotificationLogType::Error(format!(
+                "Failed to save autosave settings: {}",
+                e
+            )));
+        }
+
+        self.autosaver.set_settings(settings.clone());
+        self.autosave_settings = settings;
+    }
+
+    fn set_memory_settings(&mut self, settings: MemorySettings) {
+        if settings == self.memory_settings {
+            return;
+        }
+
+        if let Err(e) = settings.save(&MemorySettings::default_path()) {
+            log::error!("Failed to save memory settings: {}", e);
+            self.notification_log
+                .push(NotificationLogType::Error(format!("Failed to save memory settings: {}", e)));
+        }
+
+        self.resource_loader.set_memory_budget(settings.memory_budget_bytes());
+        self.resource_stats = self.resource_loader.stats();
+        self.memory_settings = settings;
+    }
+
+    fn write_snapshot(&mut self, engine_save_state: Option<&DSSaveState>) {
+        match project::serialize_project(&self.state, engine_save_state, None) {
+            Ok(contents) => self.autosaver.write_snapshot(contents),
+            Err(e) => log::error!("Failed to autosave project: {}", e),
+        }
+    }
+
+    fn report_missing_files(&mut self, missing: &[MissingFile]) {
+        let paths: Vec<String> = missing.iter().map(|m| format!("{:?}", &m.path)).collect();
+
+        log::error!("Missing samples: {}", paths.join(", "));
+
+        self.notification_log.push(NotificationLogType::Error(format!(
+            "{} sample(s) could not be found: {}. Use \"RELINK\" to search for them.",
+            missing.len(),
+            paths.join(", ")
+        )));
+    }
+
+    /// Search the given folders for the missing samples on the relink thread.
+    /// The clips that use them are pointed to their new location once the
+    /// search has finished.
+    pub fn relink_missing_files(&mut self, search_dirs: &[PathBuf]) {
+        if self.missing_files.is_empty() || self.relink_job.is_some() {
+            return;
+        }
+
+        self.relink_job = Some(project::spawn_search_for_missing_files(
+            self.missing_files.clone(),
+            search_dirs.to_vec(),
+        ));
+
+        self.notification_log.push(NotificationLogType::Info(format!(
+            "Searching for {} missing sample(s)...",
+            self.missing_files.len()
+        )));
+    }
+
+    fn poll_relink(&mut self) {
+        let relinked = match self.relink_job.as_ref().and_then(|job| job.poll()) {
+            Some(relinked) => relinked,
+            None => return,
+        };
+        self.relink_job = None;
+
+        project::relink_files(&mut self.state, &relinked);
+        self.missing_files.retain(|m| !relinked.contains_key(&m.path));
+        self.sync_timeline_clips();
+
+        log::info!("Relinked {} samples", relinked.len());
+
+        self.notification_log.push(NotificationLogType::Info(format!(
+            "Relinked {} sample(s), {} still missing",
+            relinked.len(),
+            self.missing_files.len()
+        )));
+    }
+
+    /// If there is a recovery snapshot that is newer than the last time the
+    /// project was saved, ask the user whether to restore it.
+    fn offer_recovery(&mut self) {
+        if let Some(snapshot) = project::newest_snapshot(&self.autosaver.settings().recovery_dir) {
+            let restore = rfd::MessageDialog::new()
+                .set_title("Recover project")
+                .set_description(
+                    "An autosaved project that is newer than the last save was found. Do you want to restore it?",
+                )
+                .set_buttons(rfd::MessageButtons::YesNo)
+                .show();
+
+            if restore && self.open_project(&snapshot) {
+                self.notification_log.push(NotificationLogType::Info(format!(
+                    "Restored autosaved project {:?}",
+                    &snapshot
+                )));
+            }
+        }
+    }
+
+    /// Save the project to the given file.
+    ///
+    /// If the engine is running, then the project is written once the engine
+    /// sends back the latest save state of the audio graph.
+    pub fn save_project(&mut self, path: PathBuf) {
+        self.request_save(SaveRequest { path, collect_samples: false, archive_path: None });
+    }
+
+    /// Copy every sample used by the project into the project folder, and then
+    /// save the project to the given file.
+    ///
+    /// If `archive_path` is given, then the project and its samples are also
+    /// written to a single compressed archive.
+    pub fn collect_and_save_project(&mut self, path: PathBuf, archive_path: Option<PathBuf>) {
+        self.request_save(SaveRequest { path, collect_samples: true, archive_path });
+    }
+
+    fn request_save(&mut self, request: SaveRequest) {
+        if let Some((engine_handles, _)) = &mut self.engine_handles {
+            if engine_handles.activated_info.is_some() {
+                self.pending_save = Some(request);
+                engine_handles.ds_handle.send(DSEngineRequest::RequestLatestSaveState);
+                return;
+            }
+        }
+
+        log::warn!("Engine is not running, saving project without the audio graph");
+
+        self.write_project(request, None);
+    }
+
+    fn write_project(&mut self, request: SaveRequest, engine_save_state: Option<&DSSaveState>) {
+        let SaveRequest { path, collect_samples, archive_path } = request;
+
+        project::update_sample_hashes(&mut self.state);
+
+        if collect_samples {
+            self.start_collect(path, archive_path, engine_save_state);
+            return;
+        }
+
+        if let Err(e) = project::save_project(&path, &self.state, engine_save_state) {
+            log::error!("{}", e);
+            self.notification_log.push(NotificationLogType::Error(e.to_string()));
+            return;
+        }
+
+        self.on_project_saved(path);
+    }
+
+    /// Copy every sample used by the project into the project folder on the
+    /// collect thread, and then write the project file (and the archive, if
+    /// any) from there.
+    fn start_collect(
+        &mut self,
+        path: PathBuf,
+        archive_path: Option<PathBuf>,
+        engine_save_state: Option<&DSSaveState>,
+    ) {
+        if self.collect_job.is_some() {
+            self.notification_log.push(NotificationLogType::Error(String::from(
+                "Cannot save the project while its samples are still being collected",
+            )));
+            return;
+        }
+
+        let plan = project::plan_collect(&path, &self.state);
+
+        if !plan.missing.is_empty() {
+            self.notification_log.push(NotificationLogType::Error(format!(
+                "Could not collect missing samples: {:?}",
+                &plan.missing
+            )));
+        }
+
+        // The project file points to the samples in their new location.
+        let mut collected_state = self.state.clone();
+        project::relink_files(&mut collected_state, &plan.new_paths);
+
+        let contents =
+            match project::serialize_project(&collected_state, engine_save_state, path.parent()) {
+                Ok(contents) => contents,
+                Err(e) => {
+                    log::error!("{}", e);
+                    self.notification_log.push(NotificationLogType::Error(e.to_string()));
+                    return;
+                }
+            };
+
+        let mut sample_paths: Vec<PathBuf> = Vec::new();
+        for clip in collected_state.audio_clips() {
+            if !sample_paths.contains(&clip.pcm_path) {
+                sample_paths.push(clip.pcm_path.clone());
+            }
+        }
+
+        self.collect_job = Some(project::spawn_collect(
+            plan,
+            CollectedProject { project_path: path, contents, archive_path, sample_paths },
+        ));
+    }
+
+    fn poll_collect(&mut self) {
+        let result = match self.collect_job.as_ref().and_then(|job| job.poll()) {
+            Some(result) => result,
+            None => return,
+        };
+        let job = self.collect_job.take().unwrap();
+
+        match result {
+            Ok(report) => {
+                log::info!("Collected {} samples", report.num_copied);
+
+                project::relink_files(&mut self.state, &job.new_paths);
+                self.sync_timeline_clips();
+                self.on_project_saved(job.project_path);
+            }
+            Err(e) => {
+                log::error!("{}", e);
+                self.notification_log.push(NotificationLogType::Error(e.to_string()));
+            }
+        }
+    }
+
+    fn on_project_saved(&mut self, path: PathBuf) {
+        log::info!("Saved project to {:?}", &path);
+
+        // The snapshots are now older than the saved project.
+        self.autosaver.clear_snapshots();
+
+        self.project_path = Some(path);
+    }
+
+    pub fn load_project(&mut self, path: PathBuf) {
+        if self.open_project(&path) {
+            self.project_path = Some(path);
+        }
+    }
+
+    /// Replace the current project with the one in the given file. Returns
+    /// `false` if the file could not be loaded.
+    fn open_project(&mut self, path: &Path) -> bool {
+        match project::load_project(path) {
+            Ok(LoadedProject { mut state, engine_save_state }) => {
+                log::info!("Loaded project from {:?}", path);
+
+                // The browser is not part of the project.
+                std::mem::swap(&mut state.browser, &mut self.state.browser);
+
+                self.state = state;
+                self.timeline_sources.clear();
+
+                if let Some((engine_handles, _)) = &mut self.engine_handles {
+                    // The effect plugins of the previous project no longer
+                    // belong to any slot in the effect racks. When the project
+                    // does not replace the audio graph, remove them from it.
+                    let old_effects: Vec<PluginInstanceID> =
+                        engine_handles.effect_plug_locations.keys().cloned().collect();
+                    engine_handles.effect_plug_handles.clear();
+                    engine_handles.effect_plug_locations.clear();
+
+                    if engine_save_state.is_none()
+                        && engine_handles.activated_info.is_some()
+                        && !old_effects.is_empty()
+                    {
+                        engine_handles.ds_handle.send(DSEngineRequest::ModifyGraph(
+                            ModifyGraphRequest {
+                                add_plugin_instances: vec![],
+                                remove_plugin_instances: old_effects,
+                                connect_new_edges: vec![],
+                                disconnect_edges: vec![],
+                            },
+                        ));
+                    }
+
+                    self.state
+                        .transport
+                        .restore(&self.state.timeline_grid.tempo_map, engine_handles);
+                }
+                self.sync_metronome();
+
+                // The search was for the samples of the previous project.
+                self.relink_job = None;
+                self.missing_files = project::find_missing_files(&self.state);
+                if !self.missing_files.is_empty() {
+                    self.report_missing_files(&self.missing_files.clone());
+                }
+
+                if let Some(save_state) = engine_save_state {
+                    if let Some((engine_handles, _)) = &mut self.engine_handles {
+                        if engine_handles.activated_info.is_some() {
+                            engine_handles
+                                .ds_handle
+                                .send(DSEngineRequest::RestoreFromSaveState(save_state));
+                        } else {
+                            engine_handles.restore_on_activate = Some(save_state);
+                        }
+                    } else {
+                        log::warn!("Cannot restore the audio graph until the engine is started");
+                    }
+                } else {
+                    // Otherwise the tracks are synced once the audio graph has
+                    // been restored.
+                    self.sync_timeline_tracks();
+                }
+
+                true
+            }
+            Err(e) => {
+                log::error!("{}", e);
+                self.notification_log.push(NotificationLogType::Error(e.to_string()));
+
+                false
+            }
+        }
     }
 }
 
@@ -358,15 +1294,185 @@ impl Model for UiData {
         event.map(|program_event, _| match program_event {
             UiEvent::PollEngine => {
                 self.poll_engine();
+                if self.poll_transport() {
+                    cx.needs_redraw();
+                }
             }
             UiEvent::SaveProject => {
-                //let save_state = serde_json::to_string(&self.state).unwrap();
-                //std::fs::write("project.json", save_state).unwrap();
+                let path = self.project_path.clone().or_else(|| {
+                    rfd::FileDialog::new()
+                        .add_filter(PROJECT_FILE_FILTER_NAME, &[PROJECT_FILE_EXTENSION])
+                        .set_file_name("project.json")
+                        .save_file()
+                });
+
+                if let Some(path) = path {
+                    self.save_project(path);
+                }
+            }
+            UiEvent::CollectAndSaveProject => {
+                if let Some(path) = rfd::FileDialog::new()
+                    .add_filter(PROJECT_FILE_FILTER_NAME, &[PROJECT_FILE_EXTENSION])
+                    .set_file_name("project.json")
+                    .save_file()
+                {
+                    self.collect_and_save_project(path, None);
+                }
+            }
+            UiEvent::ExportProjectArchive => {
+                let path = self.project_path.clone().or_else(|| {
+                    rfd::FileDialog::new()
+                        .add_filter(PROJECT_FILE_FILTER_NAME, &[PROJECT_FILE_EXTENSION])
+                        .set_file_name("project.json")
+                        .save_file()
+                });
+
+                if let Some(path) = path {
+                    if let Some(archive_path) = rfd::FileDialog::new()
+                        .add_filter("Zip Archive", &["zip"])
+                        .set_file_name("project.zip")
+                        .save_file()
+                    {
+                        self.collect_and_save_project(path, Some(archive_path));
+                    }
+                }
+            }
+            UiEvent::RelinkMissingFiles => {
+                if let Some(search_dirs) = rfd::FileDialog::new().pick_folders() {
+                    self.relink_missing_files(&search_dirs);
+                }
             }
             UiEvent::LoadProject => {
-                //let save_state = std::fs::read_to_string("project.json").unwrap();
-                //let project_state = serde_json::from_str(&save_state).unwrap();
-                //self.state = project_state;
+                if let Some(path) = rfd::FileDialog::new()
+                    .add_filter(PROJECT_FILE_FILTER_NAME, &[PROJECT_FILE_EXTENSION])
+                    .pick_file()
+                {
+                    self.load_project(path);
+                }
+            }
+            UiEvent::RenderProject => {
+                if let Some(path) = rfd::FileDialog::new()
+                    .add_filter("WAV", &["wav"])
+                    .add_filter("FLAC", &["flac"])
+                    .set_file_name("render.wav")
+                    .save_file()
+                {
+                    self.start_render(path);
+                }
+            }
+            UiEvent::ExportStems => {
+                if let Some(dir) = rfd::FileDialog::new().pick_folder() {
+                    self.start_stem_export(dir);
+                }
+            }
+            UiEvent::CancelRender => {
+                self.cancel_render();
+            }
+            UiEvent::InsertMarkerAtPlayhead => {
+                let markers = &mut self.state.timeline_grid.markers;
+                let name = markers.new_marker_name();
+                markers.insert_marker(name, self.state.transport.playhead.get());
+                cx.needs_redraw();
+            }
+            UiEvent::InsertRegionFromLoopRange => {
+                let transport = &self.state.transport;
+                let markers = &mut self.state.timeline_grid.markers;
+                let name = markers.new_region_name();
+                markers.insert_region(name, transport.loop_start.get(), transport.loop_end.get());
+                cx.needs_redraw();
+            }
+            UiEvent::GoToNextLocator => {
+                let playhead = self.state.transport.playhead.get();
+                if let Some(position) = self.state.timeline_grid.markers.next_locator(playhead) {
+                    self.on_transport_event(&TransportEvent::Seek(position));
+                }
+            }
+            UiEvent::GoToPreviousLocator => {
+                let playhead = self.state.transport.playhead.get();
+                if let Some(position) = self.state.timeline_grid.markers.previous_locator(playhead)
+                {
+                    self.on_transport_event(&TransportEvent::Seek(position));
+                }
+            }
+            UiEvent::LoopRegionAtPlayhead => {
+                let playhead = self.state.transport.playhead.get();
+                if let Some(region) = self.state.timeline_grid.markers.region_at(playhead) {
+                    let (start, end) = (region.start.get(), region.end.get());
+                    self.on_transport_event(&TransportEvent::SetLoopRange(start, end));
+                    self.on_transport_event(&TransportEvent::SetLoopEnabled(true));
+                }
+            }
+            UiEvent::ExportRegions => {
+                if let Some(dir) = rfd::FileDialog::new().pick_folder() {
+                    self.start_region_export(dir);
+                }
+            }
+            UiEvent::ReconnectAudio => {
+                self.reconnect_system_io();
+            }
+            UiEvent::RefreshAudioDevices => {
+                self.audio_settings.refresh(&self.system_io_config);
+            }
+            UiEvent::SetAudioBackend(backend) => {
+                let config = SystemIOConfig { backend: *backend, ..self.system_io_config.clone() };
+                self.set_system_io_config(config);
+            }
+            UiEvent::SetAudioHost(host) => {
+                let config = SystemIOConfig {
+                    host: host.clone(),
+                    // Device names are not shared between hosts.
+                    output_device: None,
+                    input_device: None,
+                    ..self.system_io_config.clone()
+                };
+                self.set_system_io_config(config);
+            }
+            UiEvent::SetAudioOutputDevice(device) => {
+                let config = SystemIOConfig {
+                    output_device: device.clone(),
+                    ..self.system_io_config.clone()
+                };
+                self.set_system_io_config(config);
+            }
+            UiEvent::SetAudioInputEnabled(enabled) => {
+                let config =
+                    SystemIOConfig { input_enabled: *enabled, ..self.system_io_config.clone() };
+                self.set_system_io_config(config);
+            }
+            UiEvent::SetAudioInputDevice(device) => {
+                let config = SystemIOConfig {
+                    input_device: device.clone(),
+                    ..self.system_io_config.clone()
+                };
+                self.set_system_io_config(config);
+            }
+            UiEvent::SetAudioSampleRate(sample_rate) => {
+                let config =
+                    SystemIOConfig { sample_rate: *sample_rate, ..self.system_io_config.clone() };
+                self.set_system_io_config(config);
+            }
+            UiEvent::SetAudioBufferSize(buffer_size) => {
+                let config =
+                    SystemIOConfig { buffer_size: *buffer_size, ..self.system_io_config.clone() };
+                self.set_system_io_config(config);
+            }
+            UiEvent::SetAutosaveEnabled(enabled) => {
+                let settings =
+                    AutosaveSettings { enabled: *enabled, ..self.autosave_settings.clone() };
+                self.set_autosave_settings(settings);
+            }
+            UiEvent::CycleAutosaveInterval => {
+                let settings = AutosaveSettings {
+                    interval_secs: self.autosave_settings.next_interval_secs(),
+                    ..self.autosave_settings.clone()
+                };
+                self.set_autosave_settings(settings);
+            }
+            UiEvent::CycleMemoryBudget => {
+                let settings = MemorySettings {
+                    memory_budget_mib: self.memory_settings.next_memory_budget_mib(),
+                };
+                self.set_memory_settings(settings);
             }
             UiEvent::BrowserFileClicked(path) => {
                 if let Some((engine_handles, _)) = &mut self.engine_handles {
@@ -381,32 +1487,40 @@ impl Model for UiData {
                             .unwrap();
 
                         // TODO: Only play audio files.
-                        let already_loaded =
-                            if let Some(last_path) = &self.last_clicked_browser_file {
-                                last_path == path
-                            } else {
-                                false
-                            };
-
-                        if already_loaded {
-                            browser_plug_handle.replay_sample();
-                        } else {
-                            let (pcm, res) = self.resource_loader.load_pcm(&PcmKey {
-                                path: path.clone(),
-                                resample_to_project_sr: true,
-                                resample_quality: ResampleQuality::Linear,
-                            });
-
-                            match res {
-                                Ok(()) => {
-                                    browser_plug_handle.play_sample(pcm);
-                                    self.last_clicked_browser_file = Some(path.clone());
-                                }
-                                Err(e) => {
-                                    log::error!("Failed to load pcm resource: {}", e);
-                                    self.last_clicked_browser_file = None;
-                                }
+                        let key = PcmKey {
+                            path: path.clone(),
+                            resample_to_project_sr: true,
+                            resample_quality: ResampleQuality::Linear,
+                            doppler_stretch_ratio: self.state.browser.audition_rate(),
+                        };
+
+                        // The sample is loaded again if the audition pitch
+                        // has changed.
+                        let already_loaded = self.last_clicked_browser_key.as_ref() == Some(&key);
+
+                        if self.resource_loader.should_stream(&key) {
+                            // Long files are streamed from disk instead. A
+                            // stream is opened again to replay it.
+                            browser_plug_handle.stop();
+
+                            // The stream will start playing once it has been
+                            // opened in `poll_engine()`.
+                            self.pending_browser_load =
+                                Some(self.resource_loader.request_stream(&key, 0));
+                            self.last_clicked_browser_key = Some(key);
+                        } else if already_loaded {
+                            if self.pending_browser_load.is_none() {
+                                browser_plug_handle.replay_sample();
                             }
+                        } else {
+                            // Stop the previous sample while the new one is loading.
+                            browser_plug_handle.stop();
+
+                            // The sample will start playing once it has been
+                            // loaded in `poll_engine()`.
+                            self.pending_browser_load =
+                                Some(self.resource_loader.request_pcm(&key));
+                            self.last_clicked_browser_key = Some(key);
                         }
                     }
                 }
@@ -423,7 +1537,8 @@ impl Model for UiData {
                             .downcast_mut::<SampleBrowserPlugHandle>()
                             .unwrap();
 
-                        self.last_clicked_browser_file = None;
+                        self.last_clicked_browser_key = None;
+                        self.pending_browser_load = None;
                         browser_plug_handle.stop();
                     }
                 }
@@ -431,11 +1546,44 @@ impl Model for UiData {
             _ => {}
         });
 
+        event.map(|window_event, _| {
+            if let WindowEvent::WindowClose = window_event {
+                // This is a clean exit, so the recovery snapshots are no
+                // longer needed.
+                self.autosaver.shutdown();
+            }
+        });
+
+        event.map(|transport_event, _| {
+            self.on_transport_event(transport_event);
+        });
+
+        event.map(|metronome_event, _| {
+            self.on_metronome_event(metronome_event);
+        });
+
+        self.render_settings.event(cx, event);
         self.state.event(cx, event);
+
+        // These are handled by `UiState` above. The clips are placed on the
+        // timeline tracks in frames, so moving the tempo map moves them too.
+        event.map(|channel_event, _| {
+            if let ChannelEvent::AddChannel = channel_event {
+                self.sync_timeline_tracks();
+            }
+        });
+
+        event.map(|_: &ClipEvent, _| {
+            self.sync_timeline_clips();
+        });
+        event.map(|_: &TempoEvent, _| {
+            self.sync_timeline_clips();
+            self.sync_transport_tempo();
+        });
     }
 }
 
-#[derive(Debug, Lens, Clone)]
+#[derive(Debug, Lens, Clone, Serialize, Deserialize)]
 pub struct UiState {
     /// A "channel" refers to a mixer channel.
     ///
@@ -443,6 +1591,7 @@ pub struct UiState {
     pub channels: Vec<ChannelState>,
 
     // Index of channel being dragged
+    #[serde(skip)]
     pub dragging_channel: Option<usize>,
 
     pub clips: Vec<ClipState>,
@@ -452,6 +1601,14 @@ pub struct UiState {
     /// (This does not contain the state of the clips.)
     pub timeline_grid: TimelineGridState,
 
+    pub transport: TransportState,
+
+    pub metronome: MetronomeState,
+
+    /// The swing and accents of the piano roll clips.
+    pub groove: GrooveState,
+
+    #[serde(skip)]
     pub browser: BrowserState,
 
     /// State of the UI panels.
@@ -461,6 +1618,26 @@ pub struct UiState {
 }
 
 impl UiState {
+    /// Returns an iterator over every audio clip in the project.
+    pub fn audio_clips(&self) -> impl Iterator<Item = &AudioClipState> {
+        self.channels.iter().flat_map(|c| c.audio_clips.iter()).chain(self.clips.iter().filter_map(
+            |clip| match &clip.type_ {
+                ClipType::Audio(audio_clip) => Some(audio_clip),
+                _ => None,
+            },
+        ))
+    }
+
+    /// Returns a mutable iterator over every audio clip in the project.
+    pub fn audio_clips_mut(&mut self) -> impl Iterator<Item = &mut AudioClipState> {
+        self.channels.iter_mut().flat_map(|c| c.audio_clips.iter_mut()).chain(
+            self.clips.iter_mut().filter_map(|clip| match &mut clip.type_ {
+                ClipType::Audio(audio_clip) => Some(audio_clip),
+                _ => None,
+            }),
+        )
+    }
+
     /// Sent whenever the engine is deactivated.
     ///
     /// The DSEngineAudioThread sent in a previous EngineActivated event is now
@@ -479,6 +1656,12 @@ impl UiState {
     ) {
         engine_handles.activated_info = None;
         engine_handles.sample_browser_plug_handle = None;
+        engine_handles.metronome_plug_handle = None;
+        engine_handles.timeline_tracks.clear();
+        engine_handles.effect_plug_handles.clear();
+        engine_handles.effect_plug_locations.clear();
+
+        self.transport.is_playing = false;
 
         if let Some(system_io_stream_handle) = system_io_stream_handle.as_mut() {
             system_io_stream_handle.engine_deactivated();
@@ -507,45 +1690,54 @@ impl UiState {
 
         // Collect the keys for the internal plugins.
         let mut sample_browser_plug_key = None;
+        let mut metronome_plug_key = None;
         for p in engine_handles.ds_handle.internal_plugins_res.iter() {
             if let Ok(key) = p {
                 if &key.rdn == SAMPLE_BROWSER_PLUG_RDN {
                     sample_browser_plug_key = Some(key.clone());
+                } else if &key.rdn == METRONOME_PLUG_RDN {
+                    metronome_plug_key = Some(key.clone());
                 }
             }
         }
         let sample_browser_plug_key = sample_browser_plug_key.unwrap();
+        let metronome_plug_key = metronome_plug_key.unwrap();
 
         system_io_stream_handle.as_mut().unwrap().engine_activated(event.audio_thread);
 
-        // Add the sample-browser plugin and connect it directly to the output.
-        engine_handles.ds_handle.send(DSEngineRequest::ModifyGraph(ModifyGraphRequest {
-            add_plugin_instances: vec![PluginSaveState::new_with_default_preset(
-                sample_browser_plug_key,
-            )],
-            remove_plugin_instances: vec![],
-            connect_new_edges: vec![
-                EdgeReq {
-                    edge_type: PortType::Audio,
-                    src_plugin_id: PluginIDReq::Added(0),
-                    dst_plugin_id: PluginIDReq::Existing(event.graph_out_node_id.clone()),
-                    src_port_id: EdgeReqPortID::Main,
-                    src_port_channel: 0,
-                    dst_port_id: EdgeReqPortID::Main,
-                    dst_port_channel: 0,
-                    log_error_on_fail: true,
-                },
-                EdgeReq {
+        self.transport.restore(&self.timeline_grid.tempo_map, engine_handles);
+
+        // The sample-browser and metronome plugins are already part of a saved
+        // audio graph.
+        if let Some(save_state) = engine_handles.restore_on_activate.take() {
+            engine_handles.ds_handle.send(DSEngineRequest::RestoreFromSaveState(save_state));
+            return;
+        }
+
+        // Add the sample-browser and metronome plugins and connect them directly
+        // to the output.
+        let mut connect_new_edges = Vec::new();
+        for plugin_index in 0..2 {
+            for channel in 0..2 {
+                connect_new_edges.push(EdgeReq {
                     edge_type: PortType::Audio,
-                    src_plugin_id: PluginIDReq::Added(0),
+                    src_plugin_id: PluginIDReq::Added(plugin_index),
                     dst_plugin_id: PluginIDReq::Existing(event.graph_out_node_id.clone()),
                     src_port_id: EdgeReqPortID::Main,
-                    src_port_channel: 1,
+                    src_port_channel: channel,
                     dst_port_id: EdgeReqPortID::Main,
-                    dst_port_channel: 1,
+                    dst_port_channel: channel,
                     log_error_on_fail: true,
-                },
+                });
+            }
+        }
+        engine_handles.ds_handle.send(DSEngineRequest::ModifyGraph(ModifyGraphRequest {
+            add_plugin_instances: vec![
+                PluginSaveState::new_with_default_preset(sample_browser_plug_key),
+                PluginSaveState::new_with_default_preset(metronome_plug_key),
             ],
+            remove_plugin_instances: vec![],
+            connect_new_edges,
             disconnect_edges: vec![],
         }));
     }
@@ -558,52 +1750,163 @@ impl UiState {
     ///
     /// If the audio graph is in an invalid state as a result of restoring from
     /// the save state, then the `EngineDeactivated` event will be sent instead.
-    fn on_audio_graph_cleared(&mut self) {
-        // TODO
+    fn on_audio_graph_cleared(&mut self, engine_handles: &mut EngineHandles) {
+        engine_handles.sample_browser_plug_handle = None;
+        engine_handles.metronome_plug_handle = None;
+        engine_handles.timeline_tracks.clear();
+        engine_handles.effect_plug_handles.clear();
+        engine_handles.effect_plug_locations.clear();
+
+        // The effects will be marked as activated again once the audio graph
+        // has been repopulated.
+        for channel in self.channels.iter_mut() {
+            for effect in channel.effects.iter_mut() {
+                if let HRackEffectState::External(effect) = effect {
+                    effect.status = ActivatedStatus::Deactivated;
+                }
+            }
+        }
     }
 
     /// This message is sent whenever the audio graph has been modified.
     ///
     /// Be sure to update your UI from this new state.
+    ///
+    /// Returns the reverse-domain-names of the new plugins that did not match
+    /// any effect in the effect racks.
     fn on_audio_graph_modified(
         &mut self,
         mut event: ModifyGraphRes,
         engine_handles: &mut EngineHandles,
-    ) {
+    ) -> Vec<String> {
+        let mut unmatched = Vec::new();
+
         for new_plugin in event.new_plugins.drain(..) {
-            match new_plugin.status {
+            let rdn = new_plugin.plugin_id.rdn();
+
+            // There is only ever one sample browser plugin.
+            if rdn.as_str() == SAMPLE_BROWSER_PLUG_RDN {
+                if let PluginActivationStatus::Activated { new_handle, .. } = new_plugin.status {
+                    if engine_handles.sample_browser_plug_handle.is_none() {
+                        engine_handles.sample_browser_plug_handle = Some(new_handle);
+                        // TODO: Update state of the gain parameter for this plugin.
+                    }
+                }
+                continue;
+            }
+
+            // There is only ever one metronome plugin.
+            if rdn.as_str() == METRONOME_PLUG_RDN {
+                if let PluginActivationStatus::Activated { new_handle, .. } = new_plugin.status {
+                    if engine_handles.metronome_plug_handle.is_none() {
+                        engine_handles.metronome_plug_handle = Some(new_handle);
+                    }
+                }
+                continue;
+            }
+
+            if rdn.as_str() == TIMELINE_TRACK_PLUG_RDN {
+                engine_handles
+                    .timeline_tracks
+                    .on_track_added(new_plugin.plugin_id.clone(), new_plugin.status);
+                continue;
+            }
+
+            let (channel_i, effect_i) = match self.find_effect_slot(rdn.as_str(), engine_handles) {
+                Some(location) => location,
+                None => {
+                    unmatched.push(rdn.to_string());
+                    self.append_master_effect(rdn.as_str())
+                }
+            };
+            engine_handles
+                .effect_plug_locations
+                .insert(new_plugin.plugin_id.clone(), (channel_i, effect_i));
+
+            let status = match new_plugin.status {
                 // This means the plugin successfully activated and returned
                 // its new audio/event port configuration and its new
                 // parameter configuration.
                 PluginActivationStatus::Activated { new_handle, new_param_values } => {
-                    // There is only ever one sample browser plugin.
-                    if engine_handles.sample_browser_plug_handle.is_none() {
-                        if new_plugin.plugin_id.rdn().as_str() == SAMPLE_BROWSER_PLUG_RDN {
-                            engine_handles.sample_browser_plug_handle = Some(new_handle);
-                            // TODO: Update state of the gain parameter for this plugin.
-                        }
-                    }
-
-                    // TODO: Handle other plugins.
+                    engine_handles
+                        .effect_plug_handles
+                        .insert(new_plugin.plugin_id.clone(), new_handle);
+                    ActivatedStatus::Activated
                 }
                 // This means that the plugin loaded but did not activate yet. This
                 // can happen when the user loads a project with a deactivated
                 // plugin.
-                PluginActivationStatus::Inactive => {
-                    // TODO: Deactivate plugin in UI.
-                }
+                PluginActivationStatus::Inactive => ActivatedStatus::Deactivated,
                 // There was an error loading the plugin.
                 PluginActivationStatus::LoadError(e) => {
-                    // TODO: Display error to user in UI.
+                    log::error!("Failed to load plugin {}: {}", rdn.as_str(), e);
+                    ActivatedStatus::DeactivatedDueToError { error_msg: e.to_string() }
                 }
                 // There was an error activating the plugin.
                 PluginActivationStatus::ActivationError(e) => {
-                    // TODO: Display error to user in UI.
+                    log::error!("Failed to activate plugin {}: {}", rdn.as_str(), e);
+                    ActivatedStatus::DeactivatedDueToError { error_msg: e.to_string() }
+                }
+            };
+
+            self.set_effect_status(channel_i, effect_i, status);
+        }
+
+        unmatched
+    }
+
+    /// Find the slot in the effect racks that a plugin from the audio graph
+    /// belongs to.
+    ///
+    /// When restoring from a save state, the plugins are matched up in order
+    /// with the effects of the same plugin type that were loaded from the
+    /// project.
+    fn find_effect_slot(
+        &self,
+        rdn: &str,
+        engine_handles: &EngineHandles,
+    ) -> Option<(usize, usize)> {
+        for (channel_i, channel) in self.channels.iter().enumerate() {
+            for (effect_i, effect) in channel.effects.iter().enumerate() {
+                if let HRackEffectState::External(effect) = effect {
+                    if effect.rdn == rdn
+                        && !engine_handles
+                            .effect_plug_locations
+                            .values()
+                            .any(|location| *location == (channel_i, effect_i))
+                    {
+                        return Some((channel_i, effect_i));
+                    }
                 }
             }
         }
 
-        // TODO
+        None
+    }
+
+    /// Add an effect for a plugin that has no slot in the effect racks to the
+    /// end of the master channel, so that it can still be seen and removed.
+    fn append_master_effect(&mut self, rdn: &str) -> (usize, usize) {
+        if self.channels.is_empty() {
+            self.channels.push(ChannelState {
+                name: String::from("Master"),
+                parent_channel: None,
+                ..Default::default()
+            });
+        }
+
+        let master = &mut self.channels[0];
+        master.effects.push(HRackEffectState::External(ExternalEffectState::new(rdn)));
+
+        (0, master.effects.len() - 1)
+    }
+
+    fn set_effect_status(&mut self, channel_i: usize, effect_i: usize, status: ActivatedStatus) {
+        if let Some(HRackEffectState::External(effect)) =
+            self.channels.get_mut(channel_i).and_then(|c| c.effects.get_mut(effect_i))
+        {
+            effect.status = status;
+        }
     }
 
     /// Sent whenever a plugin becomes activated after being deactivated or
@@ -615,8 +1918,14 @@ impl UiState {
         plugin_id: PluginInstanceID,
         new_handle: PluginHandle,
         new_param_values: FnvHashMap<ParamID, f64>,
+        engine_handles: &mut EngineHandles,
     ) {
-        // TODO
+        if let Some((channel_i, effect_i)) =
+            engine_handles.effect_plug_locations.get(&plugin_id).copied()
+        {
+            engine_handles.effect_plug_handles.insert(plugin_id, new_handle);
+            self.set_effect_status(channel_i, effect_i, ActivatedStatus::Activated);
+        }
     }
 
     /// Sent whenever a plugin becomes deactivated. When a plugin is deactivated
@@ -630,8 +1939,19 @@ impl UiState {
         // If this is `Err(e)`, then it means the plugin became deactivated
         // because it failed to restart.
         status: Result<(), ActivatePluginError>,
+        engine_handles: &mut EngineHandles,
     ) {
-        // TODO
+        engine_handles.effect_plug_handles.remove(&plugin_id);
+
+        if let Some((channel_i, effect_i)) =
+            engine_handles.effect_plug_locations.get(&plugin_id).copied()
+        {
+            let status = match status {
+                Ok(()) => ActivatedStatus::Deactivated,
+                Err(e) => ActivatedStatus::DeactivatedDueToError { error_msg: e.to_string() },
+            };
+            self.set_effect_status(channel_i, effect_i, status);
+        }
     }
 
     fn on_plugin_params_modified(
@@ -712,8 +2032,32 @@ impl Model for UiState {
             ChannelEvent::RemoveChannel => {}
         });
 
+        event.map(|clip_event, _| {
+            match clip_event {
+                ClipEvent::MoveClip { index, lane_index, timeline_start } => {
+                    if let Some(clip) = self.clips.get_mut(*index) {
+                        clip.timeline_start =
+                            ClipStart::OnLane(OnLane::new(*lane_index, *timeline_start));
+                    }
+                }
+                ClipEvent::ResizeClipStart { index, timeline_start } => {
+                    if let Some(clip) = self.clips.get_mut(*index) {
+                        clip.resize_start(*timeline_start, &self.timeline_grid.tempo_map);
+                    }
+                }
+                ClipEvent::ResizeClipEnd { index, timeline_end } => {
+                    if let Some(clip) = self.clips.get_mut(*index) {
+                        clip.resize_end(*timeline_end);
+                    }
+                }
+            }
+
+            self.timeline_grid.update_extent(&self.clips, self.transport.loop_end.get());
+        });
+
         self.panels.event(cx, event);
         self.timeline_grid.event(cx, event);
+        self.groove.event(cx, event);
         self.browser.event(cx, event);
     }
 }
@@ -1,13 +1,22 @@
+use serde::{Deserialize, Serialize};
 use vizia::prelude::*;
 
 // TODO - Move this to its own file with other local UI state
-#[derive(Debug, Lens, Clone)]
+#[derive(Debug, Lens, Clone, Serialize, Deserialize)]
 pub struct PanelState {
     pub channel_rack_orientation: ChannelRackOrientation,
     pub hide_clips: bool,
     pub hide_piano_roll: bool,
     pub browser_width: f32,
     pub hide_browser: bool,
+
+    /// Whether the groove dialog is open.
+    #[serde(skip)]
+    pub show_groove: bool,
+
+    /// Whether the settings dialog is open.
+    #[serde(skip)]
+    pub show_settings: bool,
 }
 
 pub enum PanelEvent {
@@ -17,6 +26,8 @@ pub enum PanelEvent {
     TogglePianoRoll,
     SetBrowserWidth(f32),
     ToggleBrowser,
+    ToggleGroove,
+    ToggleSettings,
 }
 
 impl Model for PanelState {
@@ -55,11 +66,19 @@ impl Model for PanelState {
             PanelEvent::ToggleBrowser => {
                 self.hide_browser ^= true;
             }
+
+            PanelEvent::ToggleGroove => {
+                self.show_groove ^= true;
+            }
+
+            PanelEvent::ToggleSettings => {
+                self.show_settings ^= true;
+            }
         });
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Data)]
+#[derive(Debug, Clone, Copy, PartialEq, Data, Serialize, Deserialize)]
 pub enum ChannelRackOrientation {
     Horizontal,
     Vertical,
@@ -0,0 +1,257 @@
+use crossbeam::channel::{self, Sender};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use vizia::prelude::*;
+
+static SNAPSHOT_PREFIX: &str = "autosave-";
+static SNAPSHOT_EXTENSION: &str = "json";
+
+static AUTOSAVE_SETTINGS_FILE_NAME: &str = "autosave.json";
+
+/// The intervals between snapshots that are offered in the settings, in
+/// seconds.
+pub const AUTOSAVE_INTERVALS_SECS: [u64; 5] = [30, 60, 120, 300, 600];
+
+#[derive(Debug, Lens, Clone, PartialEq, Data, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AutosaveSettings {
+    /// Set this to `false` to disable autosaving.
+    pub enabled: bool,
+
+    /// The time between each snapshot, in seconds.
+    pub interval_secs: u64,
+
+    /// The folder the recovery snapshots are written to.
+    pub recovery_dir: PathBuf,
+
+    /// The maximum number of snapshots to keep in the recovery folder. The
+    /// oldest snapshots are deleted first.
+    pub max_snapshots: usize,
+}
+
+impl Default for AutosaveSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_secs: 60,
+            recovery_dir: crate::util::config_dir().join("recovery"),
+            max_snapshots: 5,
+        }
+    }
+}
+
+impl AutosaveSettings {
+    /// The file the settings are persisted to.
+    pub fn default_path() -> PathBuf {
+        crate::util::config_dir().join(AUTOSAVE_SETTINGS_FILE_NAME)
+    }
+
+    /// Load the settings from `path`, or return the default settings if they
+    /// do not exist or could not be read.
+    pub fn load_or_default(path: &Path) -> Self {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(_) => return Self::default(),
+        };
+
+        match serde_json::from_str(&contents) {
+            Ok(settings) => settings,
+            Err(e) => {
+                log::error!("Failed to parse autosave settings {:?}: {}", path, e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs.max(1))
+    }
+
+    /// The next interval in `AUTOSAVE_INTERVALS_SECS` after the current one.
+    pub fn next_interval_secs(&self) -> u64 {
+        AUTOSAVE_INTERVALS_SECS
+            .iter()
+            .copied()
+            .find(|secs| *secs > self.interval_secs)
+            .unwrap_or(AUTOSAVE_INTERVALS_SECS[0])
+    }
+}
+
+enum AutosaveMsg {
+    WriteSnapshot(String),
+    ClearSnapshots,
+    SetSettings(AutosaveSettings),
+    Shutdown,
+}
+
+/// Periodically writes recovery snapshots of the project from a background
+/// thread.
+///
+/// The project is serialized on the UI thread (since that is where the state
+/// lives), but all of the file IO happens on the autosave thread.
+pub struct Autosaver {
+    settings: AutosaveSettings,
+    to_worker_tx: Sender<AutosaveMsg>,
+    worker: Option<JoinHandle<()>>,
+    last_snapshot: Instant,
+}
+
+impl Autosaver {
+    pub fn new(settings: AutosaveSettings) -> Self {
+        let (to_worker_tx, from_ui_rx) = channel::unbounded::<AutosaveMsg>();
+
+        let mut worker_settings = settings.clone();
+        let worker = std::thread::spawn(move || {
+            // This thread exits once the `Autosaver` is shut down or dropped.
+            for msg in from_ui_rx.iter() {
+                match msg {
+                    AutosaveMsg::WriteSnapshot(contents) => {
+                        if let Err(e) = write_snapshot(&worker_settings, &contents) {
+                            log::error!("Failed to write autosave snapshot: {}", e);
+                        }
+                    }
+                    AutosaveMsg::ClearSnapshots => {
+                        for path in list_snapshots(&worker_settings.recovery_dir) {
+                            if let Err(e) = std::fs::remove_file(&path) {
+                                log::error!("Failed to remove autosave snapshot {:?}: {}", path, e);
+                            }
+                        }
+                    }
+                    AutosaveMsg::SetSettings(settings) => {
+                        worker_settings = settings;
+                    }
+                    AutosaveMsg::Shutdown => break,
+                }
+            }
+        });
+
+        Self { settings, to_worker_tx, worker: Some(worker), last_snapshot: Instant::now() }
+    }
+
+    pub fn settings(&self) -> &AutosaveSettings {
+        &self.settings
+    }
+
+    /// Replace the settings. Snapshots that were already written stay in the
+    /// previous recovery folder.
+    pub fn set_settings(&mut self, settings: AutosaveSettings) {
+        self.settings = settings.clone();
+        let _ = self.to_worker_tx.send(AutosaveMsg::SetSettings(settings));
+    }
+
+    /// Returns `true` if it is time to take a new snapshot.
+    pub fn is_due(&self) -> bool {
+        self.settings.enabled && self.last_snapshot.elapsed() >= self.settings.interval()
+    }
+
+    /// Restart the timer until the next snapshot is due.
+    pub fn reset_timer(&mut self) {
+        self.last_snapshot = Instant::now();
+    }
+
+    /// Write the given serialized project to a new snapshot.
+    pub fn write_snapshot(&mut self, contents: String) {
+        self.reset_timer();
+        let _ = self.to_worker_tx.send(AutosaveMsg::WriteSnapshot(contents));
+    }
+
+    /// Delete all snapshots. Call this after the project was manually saved,
+    /// so that any snapshot left in the recovery folder is always newer than
+    /// the last manual save.
+    pub fn clear_snapshots(&mut self) {
+        self.reset_timer();
+        let _ = self.to_worker_tx.send(AutosaveMsg::ClearSnapshots);
+    }
+
+    /// Delete all snapshots and stop the autosave thread, blocking until it
+    /// has finished. Call this when the app exits cleanly.
+    ///
+    /// No more snapshots are written after this.
+    pub fn shutdown(&mut self) {
+        let _ = self.to_worker_tx.send(AutosaveMsg::ClearSnapshots);
+        let _ = self.to_worker_tx.send(AutosaveMsg::Shutdown);
+
+        if let Some(worker) = self.worker.take() {
+            if worker.join().is_err() {
+                log::error!("Autosave thread panicked");
+            }
+        }
+    }
+}
+
+/// Returns the newest snapshot in the recovery folder (if there is one).
+pub fn newest_snapshot(recovery_dir: &Path) -> Option<PathBuf> {
+    // The file names contain the timestamp, so they sort chronologically.
+    list_snapshots(recovery_dir).into_iter().last()
+}
+
+/// Returns all of the snapshots in the recovery folder, sorted from oldest to
+/// newest.
+fn list_snapshots(recovery_dir: &Path) -> Vec<PathBuf> {
+    let mut snapshots: Vec<PathBuf> = match std::fs::read_dir(recovery_dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                let is_snapshot = path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .map(|name| name.starts_with(SNAPSHOT_PREFIX))
+                    .unwrap_or(false);
+
+                is_snapshot
+                    && path.extension().and_then(|ext| ext.to_str()) == Some(SNAPSHOT_EXTENSION)
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+
+    snapshots.sort();
+    snapshots
+}
+
+/// Atomically write a new snapshot by first writing it to a temporary file and
+/// then renaming it, so a crash in the middle of writing never leaves behind a
+/// half-written snapshot.
+fn write_snapshot(settings: &AutosaveSettings, contents: &str) -> std::io::Result<()> {
+    std::fs::create_dir_all(&settings.recovery_dir)?;
+
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    let file_name = format!("{}{:020}", SNAPSHOT_PREFIX, timestamp);
+
+    let path = settings.recovery_dir.join(&file_name).with_extension(SNAPSHOT_EXTENSION);
+    let tmp_path = settings.recovery_dir.join(&file_name).with_extension("tmp");
+
+    {
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+    }
+
+    std::fs::rename(&tmp_path, &path)?;
+
+    log::debug!("Wrote autosave snapshot {:?}", &path);
+
+    // Remove the oldest snapshots.
+    let snapshots = list_snapshots(&settings.recovery_dir);
+    let num_to_remove = snapshots.len().saturating_sub(settings.max_snapshots.max(1));
+    for old_path in snapshots.iter().take(num_to_remove) {
+        if let Err(e) = std::fs::remove_file(old_path) {
+            log::error!("Failed to remove autosave snapshot {:?}: {}", old_path, e);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,284 @@
+use crossbeam::channel::{self, Receiver, TryRecvError};
+use fnv::{FnvHashMap, FnvHashSet};
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::ui::state::UiState;
+
+/// The folder (relative to the project file) that samples are collected into.
+pub static COLLECTED_SAMPLES_DIR: &str = "samples";
+
+#[derive(Debug)]
+pub enum CollectError {
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    Archive {
+        path: PathBuf,
+        error: zip::result::ZipError,
+    },
+    Save {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    /// The collect thread stopped before it could finish.
+    Interrupted,
+}
+
+impl Error for CollectError {}
+
+impl fmt::Display for CollectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CollectError::Io { path, error } => {
+                write!(f, "Failed to collect sample {:?}: {}", path, error)
+            }
+            CollectError::Archive { path, error } => {
+                write!(f, "Failed to write project archive {:?}: {}", path, error)
+            }
+            CollectError::Save { path, error } => {
+                write!(f, "Failed to write project file {:?}: {}", path, error)
+            }
+            CollectError::Interrupted => write!(f, "Collecting the samples was interrupted"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CollectReport {
+    /// The number of samples that were copied into the project folder.
+    pub num_copied: usize,
+}
+
+/// Where each sample used by the project is copied to when it is collected.
+///
+/// This is worked out on the UI thread, since it only looks at file names.
+/// The copying itself is done by `spawn_collect()`.
+#[derive(Debug, Default)]
+pub struct CollectPlan {
+    /// The samples to copy, and where to copy them to.
+    copies: Vec<(PathBuf, PathBuf)>,
+
+    /// A map from the old path of every sample that is copied to its new path
+    /// in the samples folder.
+    pub new_paths: FnvHashMap<PathBuf, PathBuf>,
+
+    /// The samples that could not be found.
+    pub missing: Vec<PathBuf>,
+}
+
+/// Work out where to copy every sample used by the audio clips of the project
+/// so that they all end up in the `samples/` folder next to the project file.
+///
+/// Samples that are already in that folder are left where they are. Samples
+/// are never copied over a file that is already in that folder.
+pub fn plan_collect(project_path: &Path, state: &UiState) -> CollectPlan {
+    let project_dir = project_path.parent().unwrap_or_else(|| Path::new(""));
+    let samples_dir = project_dir.join(COLLECTED_SAMPLES_DIR);
+
+    let mut sources: Vec<PathBuf> = Vec::new();
+    let mut seen: FnvHashSet<PathBuf> = FnvHashSet::default();
+    for path in state.audio_clips().map(|clip| clip.pcm_path.clone()) {
+        if !path.as_os_str().is_empty() && seen.insert(path.clone()) {
+            sources.push(path);
+        }
+    }
+
+    // File names are compared without case, since the file system may not
+    // tell them apart.
+    let mut used_names: FnvHashSet<String> = match std::fs::read_dir(&samples_dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().to_lowercase())
+            .collect(),
+        Err(_) => FnvHashSet::default(),
+    };
+
+    let mut plan = CollectPlan::default();
+
+    for src in sources.into_iter().filter(|path| !path.starts_with(&samples_dir)) {
+        if !src.is_file() {
+            log::warn!("Could not collect missing sample {:?}", &src);
+            plan.missing.push(src);
+            continue;
+        }
+
+        let file_name = unique_file_name(&src, &used_names);
+        used_names.insert(file_name.to_string_lossy().to_lowercase());
+
+        let dst = samples_dir.join(&file_name);
+        plan.new_paths.insert(src.clone(), dst.clone());
+        plan.copies.push((src, dst));
+    }
+
+    plan
+}
+
+/// Returns the file name of `src`, with a number appended to it if that name
+/// is already used in the samples folder.
+fn unique_file_name(src: &Path, used_names: &FnvHashSet<String>) -> PathBuf {
+    let file_name = PathBuf::from(src.file_name().unwrap_or_else(|| OsStr::new("sample")));
+    if !used_names.contains(&file_name.to_string_lossy().to_lowercase()) {
+        return file_name;
+    }
+
+    let stem = src.file_stem().and_then(|s| s.to_str()).unwrap_or("sample");
+    let extension = src.extension().and_then(|s| s.to_str());
+
+    let mut i = 1;
+    loop {
+        let candidate = match extension {
+            Some(extension) => format!("{}-{}.{}", stem, i, extension),
+            None => format!("{}-{}", stem, i),
+        };
+
+        if !used_names.contains(&candidate.to_lowercase()) {
+            return PathBuf::from(candidate);
+        }
+
+        i += 1;
+    }
+}
+
+/// The project file to write once the samples have been collected.
+pub struct CollectedProject {
+    pub project_path: PathBuf,
+
+    /// The serialized project, which already points to the collected samples.
+    pub contents: String,
+
+    /// If set, the project and the samples in `sample_paths` are also written
+    /// to this archive.
+    pub archive_path: Option<PathBuf>,
+
+    /// The samples used by the project once it has been collected.
+    pub sample_paths: Vec<PathBuf>,
+}
+
+/// A collect that is running on its own thread.
+pub struct CollectJob {
+    pub project_path: PathBuf,
+
+    /// A map from the old path of every sample that is being copied to its
+    /// new path in the samples folder.
+    pub new_paths: FnvHashMap<PathBuf, PathBuf>,
+
+    result_rx: Receiver<Result<CollectReport, CollectError>>,
+}
+
+impl CollectJob {
+    /// Returns the result once the collect has finished.
+    pub fn poll(&self) -> Option<Result<CollectReport, CollectError>> {
+        match self.result_rx.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(CollectError::Interrupted)),
+        }
+    }
+}
+
+/// Copy the samples in the plan and then write the project file (and the
+/// archive, if any) on a new thread.
+///
+/// The project file is only written once every sample has been copied, so it
+/// never points to a sample that is not in the samples folder.
+pub fn spawn_collect(plan: CollectPlan, project: CollectedProject) -> CollectJob {
+    let (result_tx, result_rx) = channel::bounded(1);
+
+    let project_path = project.project_path.clone();
+
+    let copies = plan.copies;
+    std::thread::spawn(move || {
+        let _ = result_tx.send(run_collect(&copies, &project));
+    });
+
+    CollectJob { project_path, new_paths: plan.new_paths, result_rx }
+}
+
+fn run_collect(
+    copies: &[(PathBuf, PathBuf)],
+    project: &CollectedProject,
+) -> Result<CollectReport, CollectError> {
+    let mut report = CollectReport::default();
+
+    for (src, dst) in copies.iter() {
+        if let Some(samples_dir) = dst.parent() {
+            std::fs::create_dir_all(samples_dir)
+                .map_err(|error| CollectError::Io { path: samples_dir.to_owned(), error })?;
+        }
+
+        std::fs::copy(src, dst).map_err(|error| CollectError::Io { path: src.clone(), error })?;
+
+        log::debug!("Collected sample {:?} into {:?}", src, dst);
+
+        report.num_copied += 1;
+    }
+
+    std::fs::write(&project.project_path, &project.contents)
+        .map_err(|error| CollectError::Save { path: project.project_path.clone(), error })?;
+
+    if let Some(archive_path) = &project.archive_path {
+        write_archive(&project.project_path, &project.sample_paths, archive_path)?;
+    }
+
+    Ok(report)
+}
+
+/// Write the project file along with the given samples into a single
+/// compressed archive that can be handed to a collaborator.
+///
+/// Only the samples inside the project folder are written to the archive.
+/// This should be called after the samples were collected and after the
+/// project file itself has been written.
+pub fn write_archive(
+    project_path: &Path,
+    sample_paths: &[PathBuf],
+    archive_path: &Path,
+) -> Result<(), CollectError> {
+    let project_dir = project_path.parent().unwrap_or_else(|| Path::new(""));
+
+    let archive_file = File::create(archive_path)
+        .map_err(|error| CollectError::Io { path: archive_path.to_owned(), error })?;
+
+    let archive_err = |error| CollectError::Archive { path: archive_path.to_owned(), error };
+
+    let mut zip = ZipWriter::new(archive_file);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let mut entries: Vec<PathBuf> = vec![project_path.to_owned()];
+    for path in sample_paths.iter() {
+        if path.starts_with(project_dir) && !entries.contains(path) {
+            entries.push(path.clone());
+        }
+    }
+
+    for path in entries.iter() {
+        // Archive paths always use forward slashes.
+        let name = path
+            .strip_prefix(project_dir)
+            .unwrap_or(path)
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        zip.start_file(name, options).map_err(archive_err)?;
+
+        let mut file =
+            File::open(path).map_err(|error| CollectError::Io { path: path.clone(), error })?;
+        std::io::copy(&mut file, &mut zip)
+            .map_err(|error| CollectError::Io { path: path.clone(), error })?;
+    }
+
+    zip.finish().map_err(archive_err)?;
+
+    log::info!("Wrote project archive {:?}", archive_path);
+
+    Ok(())
+}
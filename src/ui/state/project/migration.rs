@@ -0,0 +1,182 @@
+//! Upgrades project files written by older versions of Meadowlark to the
+//! current format.
+//!
+//! Every change to the serialized shape of `UiState` must bump
+//! `PROJECT_FORMAT_VERSION`, append a step to `MIGRATIONS` that upgrades a
+//! project from the previous version, and freeze a project saved in the new
+//! format into `assets/test_files/projects/v<version>.json`.
+
+use serde_json::{json, Map, Value};
+
+use super::PROJECT_FORMAT_VERSION;
+
+/// A single step that upgrades the top-level object of a project file from
+/// one version to the next.
+type MigrationStep = fn(&mut Map<String, Value>) -> Result<(), String>;
+
+/// The migration at index `i` upgrades a project from version `i` to
+/// version `i + 1`.
+static MIGRATIONS: [MigrationStep; PROJECT_FORMAT_VERSION as usize] =
+    [v0_to_v1, v1_to_v2, v2_to_v3, v3_to_v4, v4_to_v5, v5_to_v6, v6_to_v7, v7_to_v8];
+
+/// Upgrade the given project from `from_version` to `PROJECT_FORMAT_VERSION`.
+pub fn migrate(project: &mut Value, from_version: u32) -> Result<(), String> {
+    let project = project.as_object_mut().ok_or("project is not a JSON object")?;
+
+    for (version, step) in MIGRATIONS.iter().enumerate().skip(from_version as usize) {
+        log::info!("Migrating project from version {} to version {}", version, version + 1);
+
+        step(project).map_err(|e| format!("version {} to {}: {}", version, version + 1, e))?;
+    }
+
+    project.insert(String::from("version"), Value::from(PROJECT_FORMAT_VERSION));
+
+    Ok(())
+}
+
+/// Version 0 is the unversioned format from before projects were saved by
+/// the app.
+///
+/// - The browser state was serialized along with the project.
+/// - `PanelState::hide_browser` was stored as `show_browser`.
+fn v0_to_v1(project: &mut Map<String, Value>) -> Result<(), String> {
+    project.remove("browser");
+
+    let panels = project
+        .get_mut("panels")
+        .and_then(Value::as_object_mut)
+        .ok_or("missing \"panels\" object")?;
+
+    let show_browser = panels.remove("show_browser").and_then(|v| v.as_bool()).unwrap_or(true);
+    panels.insert(String::from("hide_browser"), Value::from(!show_browser));
+
+    Ok(())
+}
+
+/// - `AudioClipState::pcm_path` was added. Audio clips from version 1 did not
+///   reference any audio file, so they are given an empty path.
+fn v1_to_v2(project: &mut Map<String, Value>) -> Result<(), String> {
+    for_each_audio_clip(project, |audio_clip| {
+        audio_clip.insert(String::from("pcm_path"), Value::from(""));
+    })
+}
+
+/// - `AudioClipState::pcm_hash` was added. It is filled in the next time the
+///   project is saved.
+fn v2_to_v3(project: &mut Map<String, Value>) -> Result<(), String> {
+    for_each_audio_clip(project, |audio_clip| {
+        audio_clip.insert(String::from("pcm_hash"), Value::Null);
+    })
+}
+
+/// - `UiState::transport` was added. Older projects start at the beginning of
+///   the timeline with looping disabled.
+fn v3_to_v4(project: &mut Map<String, Value>) -> Result<(), String> {
+    let zero = json!({ "beats": 0, "super_beats": 0 });
+    project.insert(
+        String::from("transport"),
+        json!({
+            "seek_position": zero,
+            "loop_enabled": false,
+            "loop_start": zero,
+            "loop_end": { "beats": 16, "super_beats": 0 },
+        }),
+    );
+
+    Ok(())
+}
+
+/// - `TimelineGridState::tempo_map` was added. Older projects were always
+///   played and rendered at 120 BPM in 4/4.
+fn v4_to_v5(project: &mut Map<String, Value>) -> Result<(), String> {
+    let timeline_grid = project
+        .get_mut("timeline_grid")
+        .and_then(Value::as_object_mut)
+        .ok_or("missing \"timeline_grid\" object")?;
+
+    let zero = json!({ "beats": 0, "super_beats": 0 });
+    timeline_grid.insert(
+        String::from("tempo_map"),
+        json!({
+            "tempo_changes": [{ "position": zero, "bpm": 120.0, "curve": "Jump" }],
+            "time_signature_changes": [{ "position": zero, "numerator": 4, "denominator": 4 }],
+        }),
+    );
+
+    Ok(())
+}
+
+/// - `UiState::metronome` was added. Older projects had no metronome.
+fn v5_to_v6(project: &mut Map<String, Value>) -> Result<(), String> {
+    project.insert(
+        String::from("metronome"),
+        json!({
+            "enabled": false,
+            "gain_db": -6.0,
+            "count_in": "Off",
+            "downbeat_sample": null,
+            "beat_sample": null,
+        }),
+    );
+
+    Ok(())
+}
+
+/// - `UiState::groove` was added. Older projects played straight, without
+///   accents.
+fn v6_to_v7(project: &mut Map<String, Value>) -> Result<(), String> {
+    project.insert(
+        String::from("groove"),
+        json!({
+            "swing": 0.0,
+            "resolution": "Sixteenth",
+            "accents": vec![1.0; 16],
+        }),
+    );
+
+    Ok(())
+}
+
+/// - `TimelineGridState::markers` was added. Older projects had no markers or
+///   regions.
+fn v7_to_v8(project: &mut Map<String, Value>) -> Result<(), String> {
+    let timeline_grid = project
+        .get_mut("timeline_grid")
+        .and_then(Value::as_object_mut)
+        .ok_or("missing \"timeline_grid\" object")?;
+
+    timeline_grid.insert(String::from("markers"), json!({ "markers": [], "regions": [] }));
+
+    Ok(())
+}
+
+/// Call `f` on every `AudioClipState` object in the project.
+fn for_each_audio_clip<F>(project: &mut Map<String, Value>, mut f: F) -> Result<(), String>
+where
+    F: FnMut(&mut Map<String, Value>),
+{
+    let mut apply = |audio_clip: &mut Value| -> Result<(), String> {
+        f(audio_clip.as_object_mut().ok_or("audio clip is not an object")?);
+        Ok(())
+    };
+
+    if let Some(channels) = project.get_mut("channels").and_then(Value::as_array_mut) {
+        for channel in channels.iter_mut() {
+            if let Some(clips) = channel.get_mut("audio_clips").and_then(Value::as_array_mut) {
+                for audio_clip in clips.iter_mut() {
+                    apply(audio_clip)?;
+                }
+            }
+        }
+    }
+
+    if let Some(clips) = project.get_mut("clips").and_then(Value::as_array_mut) {
+        for clip in clips.iter_mut() {
+            if let Some(audio_clip) = clip.get_mut("type_").and_then(|t| t.get_mut("Audio")) {
+                apply(audio_clip)?;
+            }
+        }
+    }
+
+    Ok(())
+}
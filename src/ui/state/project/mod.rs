@@ -0,0 +1,371 @@
+use dropseed::DSSaveState;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::UiState;
+
+mod autosave;
+mod collect;
+mod migration;
+mod relink;
+
+pub use autosave::{newest_snapshot, AutosaveSettings, Autosaver};
+pub use collect::{
+    plan_collect, spawn_collect, write_archive, CollectError, CollectJob, CollectPlan,
+    CollectReport, CollectedProject,
+};
+pub use relink::{
+    find_missing_files, hash_file, relink_files, search_for_missing_files,
+    spawn_search_for_missing_files, update_sample_hashes, MissingFile, RelinkJob,
+};
+
+/// The version of the on-disk project format written by this build.
+///
+/// Bump this whenever the serialized shape of `UiState` changes, and add a
+/// step to `migration::MIGRATIONS` to upgrade older projects.
+pub const PROJECT_FORMAT_VERSION: u32 = 8;
+
+/// The name and file extension shown in the save/load file dialogs.
+pub static PROJECT_FILE_FILTER_NAME: &str = "Meadowlark Project";
+pub static PROJECT_FILE_EXTENSION: &str = "json";
+
+#[derive(Serialize)]
+struct ProjectFileRef<'a> {
+    version: u32,
+
+    #[serde(flatten)]
+    state: &'a UiState,
+
+    engine_save_state: Option<&'a DSSaveState>,
+}
+
+#[derive(Deserialize)]
+struct ProjectFile {
+    #[serde(flatten)]
+    state: UiState,
+
+    /// The plugin instances, their save states, and the edges of the audio
+    /// graph.
+    ///
+    /// This is `None` if the project was saved while the engine was not
+    /// running.
+    #[serde(default)]
+    engine_save_state: Option<DSSaveState>,
+}
+
+/// The contents of a project file.
+pub struct LoadedProject {
+    pub state: UiState,
+    pub engine_save_state: Option<DSSaveState>,
+}
+
+#[derive(Debug)]
+pub enum ProjectSaveError {
+    Serialize(serde_json::Error),
+    Io { path: PathBuf, error: std::io::Error },
+}
+
+impl Error for ProjectSaveError {}
+
+impl fmt::Display for ProjectSaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProjectSaveError::Serialize(e) => write!(f, "Failed to serialize project: {}", e),
+            ProjectSaveError::Io { path, error } => {
+                write!(f, "Failed to write project file {:?}: {}", path, error)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ProjectLoadError {
+    Io { path: PathBuf, error: std::io::Error },
+    Parse { path: PathBuf, error: serde_json::Error },
+    InvalidVersion { path: PathBuf },
+    UnsupportedVersion { path: PathBuf, version: u32 },
+    Migration { path: PathBuf, version: u32, error: String },
+}
+
+impl Error for ProjectLoadError {}
+
+impl fmt::Display for ProjectLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProjectLoadError::Io { path, error } => {
+                write!(f, "Failed to read project file {:?}: {}", path, error)
+            }
+            ProjectLoadError::Parse { path, error } => {
+                write!(f, "Project file {:?} is corrupt: {}", path, error)
+            }
+            ProjectLoadError::InvalidVersion { path } => {
+                write!(f, "Project file {:?} has an invalid format version", path)
+            }
+            ProjectLoadError::UnsupportedVersion { path, version } => write!(
+                f,
+                "Project file {:?} has format version {}, but this version of Meadowlark only supports up to version {}",
+                path, version, PROJECT_FORMAT_VERSION
+            ),
+            ProjectLoadError::Migration { path, version, error } => write!(
+                f,
+                "Failed to upgrade project file {:?} from format version {}: {}",
+                path, version, error
+            ),
+        }
+    }
+}
+
+/// Serialize the given state into the current project format.
+///
+/// If `project_dir` is given, then any samples inside of that folder are
+/// stored relative to it, so the project can be moved to another location or
+/// machine along with its samples.
+pub fn serialize_project(
+    state: &UiState,
+    engine_save_state: Option<&DSSaveState>,
+    project_dir: Option<&Path>,
+) -> Result<String, ProjectSaveError> {
+    let relative_state;
+    let state = if let Some(project_dir) = project_dir {
+        let mut s = state.clone();
+        for clip in s.audio_clips_mut() {
+            if let Ok(relative_path) = clip.pcm_path.strip_prefix(project_dir) {
+                clip.pcm_path = relative_path.to_owned();
+            }
+        }
+        relative_state = s;
+        &relative_state
+    } else {
+        state
+    };
+
+    serde_json::to_string(&ProjectFileRef {
+        version: PROJECT_FORMAT_VERSION,
+        state,
+        engine_save_state,
+    })
+    .map_err(ProjectSaveError::Serialize)
+}
+
+/// Write the given state to a project file at `path`.
+pub fn save_project(
+    path: &Path,
+    state: &UiState,
+    engine_save_state: Option<&DSSaveState>,
+) -> Result<(), ProjectSaveError> {
+    let contents = serialize_project(state, engine_save_state, path.parent())?;
+
+    std::fs::write(path, contents)
+        .map_err(|error| ProjectSaveError::Io { path: path.to_owned(), error })
+}
+
+/// Read the project file at `path`, upgrading it to the current format if it
+/// was saved by an older version.
+///
+/// Note that the returned state does not contain any `BrowserState`, since
+/// that is not part of the project.
+pub fn load_project(path: &Path) -> Result<LoadedProject, ProjectLoadError> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|error| ProjectLoadError::Io { path: path.to_owned(), error })?;
+
+    let parse_err = |error| ProjectLoadError::Parse { path: path.to_owned(), error };
+
+    let mut value: serde_json::Value = serde_json::from_str(&contents).map_err(parse_err)?;
+
+    // Projects from before the format was versioned have no version field.
+    let version = match value.get("version") {
+        None => 0,
+        Some(v) => v
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| ProjectLoadError::InvalidVersion { path: path.to_owned() })?,
+    };
+
+    if version > PROJECT_FORMAT_VERSION {
+        return Err(ProjectLoadError::UnsupportedVersion { path: path.to_owned(), version });
+    }
+
+    if version < PROJECT_FORMAT_VERSION {
+        migration::migrate(&mut value, version).map_err(|error| ProjectLoadError::Migration {
+            path: path.to_owned(),
+            version,
+            error,
+        })?;
+    }
+
+    let mut project: ProjectFile = serde_json::from_value(value).map_err(parse_err)?;
+
+    if let Some(project_dir) = path.parent() {
+        for clip in project.state.audio_clips_mut() {
+            // An empty path means that the clip has no file.
+            if clip.pcm_path.is_relative() && !clip.pcm_path.as_os_str().is_empty() {
+                clip.pcm_path = project_dir.join(&clip.pcm_path);
+            }
+        }
+    }
+
+    Ok(LoadedProject { state: project.state, engine_save_state: project.engine_save_state })
+}
+
+#[cfg(test)]
+mod tests {
+    use meadowlark_core_types::time::MusicalTime;
+    use std::path::{Path, PathBuf};
+
+    use super::{load_project, PROJECT_FORMAT_VERSION};
+    use crate::ui::state::MAX_TEMPO_BPM;
+
+    fn fixtures_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/test_files/projects")
+    }
+
+    /// Every frozen project file must load, and the fields added by each
+    /// migration must have the values the migration gives them.
+    #[test]
+    fn load_every_format_version() {
+        let dir = fixtures_dir();
+
+        for version in 0..=PROJECT_FORMAT_VERSION {
+            let path = dir.join(format!("v{}.json", version));
+            let project =
+                load_project(&path).unwrap_or_else(|e| panic!("failed to load {:?}: {}", path, e));
+            let state = &project.state;
+
+            assert!(project.engine_save_state.is_none(), "v{}", version);
+            assert!(!state.panels.hide_browser, "v{}", version);
+
+            // One clip in `channels[].audio_clips` and one in
+            // `clips[].type_.Audio`.
+            let audio_clips: Vec<_> = state.audio_clips().collect();
+            assert_eq!(audio_clips.len(), 2, "v{}", version);
+            let (kick, snare) = (audio_clips[0], audio_clips[1]);
+
+            if version >= 2 {
+                assert_eq!(kick.pcm_path, dir.join("samples/kick.wav"), "v{}", version);
+                assert!(snare.pcm_path.ends_with("samples/snare.wav"), "v{}", version);
+            } else {
+                assert_eq!(kick.pcm_path, Path::new(""), "v{}", version);
+                assert_eq!(snare.pcm_path, Path::new(""), "v{}", version);
+            }
+
+            if version >= 3 {
+                assert_eq!(kick.pcm_hash, Some(8093741106215539821), "v{}", version);
+            } else {
+                assert_eq!(kick.pcm_hash, None, "v{}", version);
+            }
+            assert_eq!(snare.pcm_hash, None, "v{}", version);
+
+            // Added in version 4.
+            let transport = &state.transport;
+            let (loop_enabled, loop_start, loop_end) =
+                if version >= 4 { (true, 4, 12) } else { (false, 0, 16) };
+            assert_eq!(transport.loop_enabled, loop_enabled, "v{}", version);
+            assert_eq!(transport.loop_start.get(), MusicalTime::new(loop_start, 0), "v{}", version);
+            assert_eq!(transport.loop_end.get(), MusicalTime::new(loop_end, 0), "v{}", version);
+
+            // Added in version 5.
+            let tempo_map = &state.timeline_grid.tempo_map;
+            let (bpm, time_signature) =
+                if version >= 5 { (140.0, (7, 8)) } else { (120.0, (4, 4)) };
+            assert_eq!(tempo_map.tempo_at(MusicalTime::new(0, 0)), 120.0, "v{}", version);
+            assert_eq!(tempo_map.tempo_at(MusicalTime::new(40, 0)), bpm, "v{}", version);
+            assert_eq!(
+                tempo_map.time_signature_at(MusicalTime::new(40, 0)),
+                time_signature,
+                "v{}",
+                version
+            );
+
+            // Added in version 6.
+            assert_eq!(state.metronome.enabled, version >= 6, "v{}", version);
+
+            // Added in version 7.
+            let swing = if version >= 7 { 0.5 } else { 0.0 };
+            assert_eq!(state.groove.swing, swing, "v{}", version);
+            assert_eq!(state.groove.accents.len(), 16, "v{}", version);
+
+            // Added in version 8.
+            let markers = &state.timeline_grid.markers;
+            let (num_markers, num_regions) = if version >= 8 { (1, 2) } else { (0, 0) };
+            assert_eq!(markers.markers().len(), num_markers, "v{}", version);
+            assert_eq!(markers.regions().len(), num_regions, "v{}", version);
+        }
+    }
+
+    /// Write the v8 fixture with its timeline grid changed by `f`, and load
+    /// it.
+    fn load_with_timeline_grid(
+        name: &str,
+        f: impl FnOnce(&mut serde_json::Value),
+    ) -> Result<super::LoadedProject, super::ProjectLoadError> {
+        let contents = std::fs::read_to_string(fixtures_dir().join("v8.json")).unwrap();
+        let mut value: serde_json::Value = serde_json::from_str(&contents).unwrap();
+        f(&mut value["timeline_grid"]);
+
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, value.to_string()).unwrap();
+
+        let result = load_project(&path);
+        let _ = std::fs::remove_file(&path);
+        result
+    }
+
+    #[test]
+    fn reject_empty_tempo_map() {
+        let result = load_with_timeline_grid("meadowlark_test_empty_tempo_map.json", |grid| {
+            grid["tempo_map"]["tempo_changes"] = serde_json::json!([]);
+        });
+        assert!(matches!(result, Err(super::ProjectLoadError::Parse { .. })));
+
+        let result = load_with_timeline_grid("meadowlark_test_empty_time_signature.json", |grid| {
+            grid["tempo_map"]["time_signature_changes"] = serde_json::json!([]);
+        });
+        assert!(matches!(result, Err(super::ProjectLoadError::Parse { .. })));
+    }
+
+    #[test]
+    fn clamp_tempo_map() {
+        let project = load_with_timeline_grid("meadowlark_test_clamp_tempo_map.json", |grid| {
+            grid["tempo_map"]["tempo_changes"][0]["bpm"] = serde_json::json!(5000.0);
+            grid["tempo_map"]["time_signature_changes"][0]["numerator"] = serde_json::json!(0);
+            grid["tempo_map"]["time_signature_changes"][0]["denominator"] = serde_json::json!(3);
+        })
+        .unwrap();
+
+        let tempo_map = &project.state.timeline_grid.tempo_map;
+        assert_eq!(tempo_map.tempo_at(MusicalTime::new(0, 0)), MAX_TEMPO_BPM);
+        assert_eq!(tempo_map.time_signature_at(MusicalTime::new(0, 0)), (1, 4));
+    }
+
+    #[test]
+    fn sort_marker_track() {
+        let project = load_with_timeline_grid("meadowlark_test_sort_marker_track.json", |grid| {
+            let markers = &mut grid["markers"];
+            markers["markers"] = serde_json::json!([
+                { "name": "B", "position": { "beats": 8, "super_beats": 0 } },
+                { "name": "A", "position": { "beats": 4, "super_beats": 0 } },
+            ]);
+            markers["regions"].as_array_mut().unwrap().reverse();
+        })
+        .unwrap();
+
+        let markers = &project.state.timeline_grid.markers;
+        let marker_names: Vec<_> = markers.markers().iter().map(|m| m.name.as_str()).collect();
+        let region_names: Vec<_> = markers.regions().iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(marker_names, ["A", "B"]);
+        assert_eq!(region_names, ["Intro", "Verse"]);
+    }
+
+    #[test]
+    fn reject_newer_format_version() {
+        let path = std::env::temp_dir().join("meadowlark_test_newer_version.json");
+        std::fs::write(&path, format!("{{\"version\":{}}}", PROJECT_FORMAT_VERSION + 1)).unwrap();
+
+        let result = load_project(&path);
+        let _ = std::fs::remove_file(&path);
+
+        assert!(matches!(result, Err(super::ProjectLoadError::UnsupportedVersion { .. })));
+    }
+}
@@ -0,0 +1,208 @@
+use crossbeam::channel::{self, Receiver, TryRecvError};
+use fnv::FnvHashMap;
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use twox_hash::XxHash64;
+
+use crate::ui::state::UiState;
+
+/// A sample used by the project that could not be found on disk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MissingFile {
+    pub path: PathBuf,
+
+    /// The hash of the contents of the file when it was last seen (if known).
+    pub hash: Option<u64>,
+}
+
+/// Returns every sample used by the project that does not exist on disk.
+pub fn find_missing_files(state: &UiState) -> Vec<MissingFile> {
+    let mut missing: Vec<MissingFile> = Vec::new();
+
+    for clip in state.audio_clips() {
+        // Clips from before samples were referenced by path have no file.
+        if clip.pcm_path.as_os_str().is_empty() {
+            continue;
+        }
+
+        if !clip.pcm_path.is_file() && !missing.iter().any(|m| m.path == clip.pcm_path) {
+            missing.push(MissingFile { path: clip.pcm_path.clone(), hash: clip.pcm_hash });
+        }
+    }
+
+    missing
+}
+
+/// Hash the contents of the file at `path`.
+pub fn hash_file(path: &Path) -> std::io::Result<u64> {
+    let mut file = File::open(path)?;
+    let mut hasher = XxHash64::default();
+
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.write(&buf[0..n]);
+    }
+
+    Ok(hasher.finish())
+}
+
+/// Fill in the content hash of every sample that does not have one yet, so
+/// the sample can be found again by its contents if it is ever moved or
+/// renamed.
+pub fn update_sample_hashes(state: &mut UiState) {
+    let mut hashes: FnvHashMap<PathBuf, u64> = FnvHashMap::default();
+
+    for clip in state.audio_clips_mut() {
+        if clip.pcm_hash.is_some() || !clip.pcm_path.is_file() {
+            continue;
+        }
+
+        if let Some(hash) = hashes.get(&clip.pcm_path) {
+            clip.pcm_hash = Some(*hash);
+            continue;
+        }
+
+        match hash_file(&clip.pcm_path) {
+            Ok(hash) => {
+                hashes.insert(clip.pcm_path.clone(), hash);
+                clip.pcm_hash = Some(hash);
+            }
+            Err(e) => log::warn!("Failed to hash sample {:?}: {}", &clip.pcm_path, e),
+        }
+    }
+}
+
+/// Search the given folders (and their subfolders) for the missing files.
+///
+/// Files are first matched by their file name. Any file that could not be
+/// found by name is then matched by the hash of its contents.
+///
+/// Returns a map from the old path of each file that was found to its new
+/// path.
+pub fn search_for_missing_files(
+    missing: &[MissingFile],
+    search_dirs: &[PathBuf],
+) -> FnvHashMap<PathBuf, PathBuf> {
+    let mut candidates: Vec<PathBuf> = Vec::new();
+    for dir in search_dirs.iter() {
+        collect_files(dir, &mut candidates);
+    }
+
+    let mut found: FnvHashMap<PathBuf, PathBuf> = FnvHashMap::default();
+
+    // Search by file name.
+    for missing_file in missing.iter() {
+        let file_name = match missing_file.path.file_name() {
+            Some(file_name) => file_name,
+            None => continue,
+        };
+
+        let mut matches = candidates.iter().filter(|c| c.file_name() == Some(file_name));
+
+        // If the hash is known, prefer a file that is an exact match.
+        let found_path = if let Some(hash) = missing_file.hash {
+            let matches: Vec<&PathBuf> = matches.collect();
+            matches
+                .iter()
+                .find(|c| hash_file(c).ok() == Some(hash))
+                .or_else(|| matches.first())
+                .map(|c| (*c).clone())
+        } else {
+            matches.next().cloned()
+        };
+
+        if let Some(found_path) = found_path {
+            found.insert(missing_file.path.clone(), found_path);
+        }
+    }
+
+    // Search the remaining files by their contents.
+    let remaining: Vec<&MissingFile> =
+        missing.iter().filter(|m| m.hash.is_some() && !found.contains_key(&m.path)).collect();
+    if !remaining.is_empty() {
+        for candidate in candidates.iter() {
+            let hash = match hash_file(candidate) {
+                Ok(hash) => hash,
+                Err(_) => continue,
+            };
+
+            for missing_file in remaining.iter() {
+                if missing_file.hash == Some(hash) && !found.contains_key(&missing_file.path) {
+                    found.insert(missing_file.path.clone(), candidate.clone());
+                }
+            }
+
+            if found.len() == missing.len() {
+                break;
+            }
+        }
+    }
+
+    found
+}
+
+/// A search for missing files that is running on its own thread.
+pub struct RelinkJob {
+    result_rx: Receiver<FnvHashMap<PathBuf, PathBuf>>,
+}
+
+impl RelinkJob {
+    /// Returns the result of `search_for_missing_files()` once the search has
+    /// finished, or an empty map if the search thread stopped early.
+    pub fn poll(&self) -> Option<FnvHashMap<PathBuf, PathBuf>> {
+        match self.result_rx.try_recv() {
+            Ok(found) => Some(found),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(FnvHashMap::default()),
+        }
+    }
+}
+
+/// Run `search_for_missing_files()` on a new thread, since searching large
+/// folders and hashing the files in them can take a long time.
+pub fn spawn_search_for_missing_files(
+    missing: Vec<MissingFile>,
+    search_dirs: Vec<PathBuf>,
+) -> RelinkJob {
+    let (result_tx, result_rx) = channel::bounded(1);
+
+    std::thread::spawn(move || {
+        let _ = result_tx.send(search_for_missing_files(&missing, &search_dirs));
+    });
+
+    RelinkJob { result_rx }
+}
+
+/// Point every audio clip that uses one of the old paths to its new path.
+pub fn relink_files(state: &mut UiState, relinked: &FnvHashMap<PathBuf, PathBuf>) {
+    for clip in state.audio_clips_mut() {
+        if let Some(new_path) = relinked.get(&clip.pcm_path) {
+            clip.pcm_path = new_path.clone();
+        }
+    }
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::warn!("Failed to search folder {:?}: {}", dir, e);
+            return;
+        }
+    };
+
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_files(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
@@ -0,0 +1,666 @@
+use dropseed::plugin::PluginInstanceID;
+use dropseed::transport::LoopState;
+use dropseed::{
+    DSEngineRequest, EdgeReq, EdgeReqPortID, ModifyGraphRequest, PluginIDReq, PortType,
+};
+use meadowlark_core_types::time::MusicalTime;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use vizia::prelude::*;
+
+use super::core_types::WMusicalTime;
+use super::{select_channel, EngineHandles, NotificationLogType, UiData, UiState};
+use crate::backend::render::{
+    self, OfflineRenderHandle, RenderBitDepth, RenderFormat, RenderOutcome, RenderSettings,
+    RenderStem, RenderStemInput,
+};
+use crate::backend::system_io::DEFAULT_NULL_SAMPLE_RATE;
+
+/// The options for rendering the project to an audio file.
+#[derive(Debug, Lens, Clone)]
+pub struct RenderSettingsState {
+    #[lens(ignore)]
+    pub bit_depth: RenderBitDepth,
+
+    /// The sample rate to render at. If this is `None`, then the sample rate
+    /// of the current audio device is used.
+    pub sample_rate: Option<u32>,
+
+    pub dither: bool,
+
+    /// The length of time to keep rendering after the end of the range, in
+    /// seconds.
+    pub tail_seconds: f64,
+
+    /// The musical range of the timeline to render. If this is `None`, then
+    /// the whole project is rendered.
+    pub range: Option<(WMusicalTime, WMusicalTime)>,
+
+    #[lens(ignore)]
+    pub stem_format: RenderFormat,
+
+    #[lens(ignore)]
+    pub stem_mode: StemMode,
+
+    /// Export the stems before the fader, pan, and mute of each channel are
+    /// applied.
+    pub stem_pre_fader: bool,
+}
+
+/// What each exported stem contains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StemMode {
+    /// One stem for every mixer channel except the master.
+    Channels,
+    /// One stem for every channel that is routed to the master, including all
+    /// of its subchannels (i.e. "Drum Group").
+    Groups,
+}
+
+/// The mixer channels that make up a single stem.
+#[derive(Debug, Clone)]
+pub struct StemSource {
+    pub name: String,
+
+    /// The channel the stem is named after, followed by all of the channels
+    /// that are mixed into it.
+    pub channels: Vec<usize>,
+}
+
+impl Default for RenderSettingsState {
+    fn default() -> Self {
+        Self {
+            bit_depth: RenderBitDepth::Int24,
+            sample_rate: None,
+            dither: true,
+            tail_seconds: 2.0,
+            range: None,
+            stem_format: RenderFormat::Wav,
+            stem_mode: StemMode::Channels,
+            stem_pre_fader: false,
+        }
+    }
+}
+
+impl RenderSettingsState {
+    /// Returns the start and end of the range to render.
+    pub fn range(&self, state: &UiState) -> (MusicalTime, MusicalTime) {
+        match self.range {
+            Some((start, end)) => (start.get(), end.get()),
+            None => (MusicalTime::from_beats(0), state.timeline_grid.project_length.get()),
+        }
+    }
+}
+
+impl UiState {
+    /// Returns the stems to export for the given mode, named after the mixer
+    /// channels in `UiState::channels`.
+    pub fn stem_sources(&self, mode: StemMode) -> Vec<StemSource> {
+        let stem_channels: Vec<usize> = match mode {
+            StemMode::Channels => (1..self.channels.len()).collect(),
+            StemMode::Groups => {
+                self.channels.first().map(|master| master.subchannels.clone()).unwrap_or_default()
+            }
+        };
+
+        stem_channels
+            .into_iter()
+            .filter_map(|channel_i| {
+                let channel = self.channels.get(channel_i)?;
+
+                let mut channels = Vec::new();
+                match mode {
+                    StemMode::Channels => channels.push(channel_i),
+                    StemMode::Groups => select_channel(&self.channels, channel_i, &mut channels),
+                }
+
+                Some(StemSource { name: channel.name.clone(), channels })
+            })
+            .collect()
+    }
+
+    /// The mixer channels that are mixed into the given stem, along with the
+    /// gain of each output channel on their way into it.
+    ///
+    /// Every channel passes through its own fader and the faders of its
+    /// parents up to the channel the stem is named after. The fader of that
+    /// channel is left out when exporting pre-fader.
+    fn stem_inputs(
+        &self,
+        stem: &StemSource,
+        num_channels: u16,
+        pre_fader: bool,
+    ) -> Vec<(usize, Vec<f32>)> {
+        let mut inputs = Vec::new();
+
+        if let Some(&stem_channel_i) = stem.channels.first() {
+            let gains = match self.channels.get(stem_channel_i) {
+                Some(channel) if !pre_fader => channel.out_channel_gains(num_channels),
+                _ => vec![1.0; usize::from(num_channels)],
+            };
+            self.collect_stem_inputs(stem, stem_channel_i, gains, num_channels, &mut inputs);
+        }
+
+        inputs
+    }
+
+    fn collect_stem_inputs(
+        &self,
+        stem: &StemSource,
+        channel_i: usize,
+        gains: Vec<f32>,
+        num_channels: u16,
+        inputs: &mut Vec<(usize, Vec<f32>)>,
+    ) {
+        inputs.push((channel_i, gains.clone()));
+
+        let channel = match self.channels.get(channel_i) {
+            Some(channel) => channel,
+            None => return,
+        };
+
+        for subchannel_i in channel.subchannels.iter() {
+            let visited = inputs.iter().any(|(c, _)| c == subchannel_i);
+            if visited || !stem.channels.contains(subchannel_i) {
+                continue;
+            }
+
+            if let Some(subchannel) = self.channels.get(*subchannel_i) {
+                let sub_gains = subchannel
+                    .out_channel_gains(num_channels)
+                    .iter()
+                    .zip(gains.iter())
+                    .map(|(a, b)| a * b)
+                    .collect();
+                self.collect_stem_inputs(stem, *subchannel_i, sub_gains, num_channels, inputs);
+            }
+        }
+    }
+
+    /// The plugin whose output is the sound of the given channel before its
+    /// fader.
+    ///
+    /// This is the last activated effect in the channel's rack, or the
+    /// channel's timeline track if no effect in its rack is running.
+    fn stem_tap(
+        &self,
+        channel_i: usize,
+        engine_handles: &EngineHandles,
+    ) -> Option<PluginInstanceID> {
+        engine_handles
+            .effect_plug_locations
+            .iter()
+            .filter(|(plugin_id, (c, _))| {
+                *c == channel_i && engine_handles.effect_plug_handles.contains_key(*plugin_id)
+            })
+            .max_by_key(|(_, (_, effect_i))| *effect_i)
+            .map(|(plugin_id, _)| plugin_id.clone())
+            .or_else(|| engine_handles.timeline_tracks.plugin_id(channel_i).cloned())
+    }
+
+    /// The edges that route every stem input into its own channels of the
+    /// graph output, after the full mix in the first `num_channels` channels.
+    ///
+    /// `stem_inputs` holds the mixer channel of every input of every stem, in
+    /// the same order as `RenderSettings::stems`.
+    fn stem_edges(
+        &self,
+        stem_inputs: &[Vec<usize>],
+        num_channels: u16,
+        engine_handles: &EngineHandles,
+    ) -> Vec<EdgeReq> {
+        let graph_out_node_id = match &engine_handles.activated_info {
+            Some(info) => info.graph_out_node_id.clone(),
+            None => return Vec::new(),
+        };
+
+        let mut edges = Vec::new();
+        for (input_i, channel_i) in stem_inputs.iter().flatten().enumerate() {
+            let first_channel = RenderSettings::stem_input_channel(num_channels, input_i);
+
+            let tap = match self.stem_tap(*channel_i, engine_handles) {
+                Some(tap) => tap,
+                None => continue,
+            };
+
+            for ch in 0..num_channels {
+                edges.push(EdgeReq {
+                    edge_type: PortType::Audio,
+                    src_plugin_id: PluginIDReq::Existing(tap.clone()),
+                    dst_plugin_id: PluginIDReq::Existing(graph_out_node_id.clone()),
+                    src_port_id: EdgeReqPortID::Main,
+                    src_port_channel: ch,
+                    dst_port_id: EdgeReqPortID::Main,
+                    dst_port_channel: first_channel + ch,
+                    log_error_on_fail: true,
+                });
+            }
+        }
+
+        edges
+    }
+}
+
+/// Returns a file name for every stem, based on the name of its channel.
+///
+/// Characters that are not allowed in file names are replaced, and stems with
+/// the same name are numbered.
+pub fn stem_file_names(stems: &[StemSource]) -> Vec<String> {
+    unique_file_names(stems.iter().map(|stem| stem.name.as_str()), "Channel")
+}
+
+/// Returns a file name (without extension) for each of the given names.
+///
+/// Characters that are not allowed in file names are replaced, empty names
+/// are replaced with `fallback`, and names that are the same are numbered.
+fn unique_file_names<'a>(names: impl Iterator<Item = &'a str>, fallback: &str) -> Vec<String> {
+    let mut used = HashSet::new();
+
+    names
+        .map(|name| {
+            let mut base: String = name
+                .trim()
+                .chars()
+                .map(|c| if c.is_control() || "/\\:*?\"<>|".contains(c) { '_' } else { c })
+                .collect();
+            if base.is_empty() {
+                base = String::from(fallback);
+            }
+
+            let mut name = base.clone();
+            let mut n = 2;
+            while !used.insert(name.to_lowercase()) {
+                name = format!("{} ({})", base, n);
+                n += 1;
+            }
+            name
+        })
+        .collect()
+}
+
+pub enum RenderEvent {
+    SetBitDepth(RenderBitDepth),
+    SetSampleRate(Option<u32>),
+    SetDither(bool),
+    SetTailSeconds(f64),
+    SetRange(Option<(WMusicalTime, WMusicalTime)>),
+    SetStemFormat(RenderFormat),
+    SetStemMode(StemMode),
+    SetStemPreFader(bool),
+}
+
+impl Model for RenderSettingsState {
+    fn event(&mut self, _: &mut EventContext, event: &mut Event) {
+        event.map(|render_event, _| match render_event {
+            RenderEvent::SetBitDepth(bit_depth) => {
+                self.bit_depth = *bit_depth;
+            }
+            RenderEvent::SetSampleRate(sample_rate) => {
+                self.sample_rate = *sample_rate;
+            }
+            RenderEvent::SetDither(dither) => {
+                self.dither = *dither;
+            }
+            RenderEvent::SetTailSeconds(tail_seconds) => {
+                self.tail_seconds = tail_seconds.max(0.0);
+            }
+            RenderEvent::SetRange(range) => {
+                self.range = *range;
+            }
+            RenderEvent::SetStemFormat(format) => {
+                self.stem_format = *format;
+            }
+            RenderEvent::SetStemMode(mode) => {
+                self.stem_mode = *mode;
+            }
+            RenderEvent::SetStemPreFader(pre_fader) => {
+                self.stem_pre_fader = *pre_fader;
+            }
+        });
+    }
+}
+
+/// A render that has been queued behind the current one.
+pub(super) struct PendingRender {
+    path: PathBuf,
+    format: RenderFormat,
+    range: (MusicalTime, MusicalTime),
+}
+
+/// The stages of rendering the project to an audio file.
+pub(super) enum RenderJob {
+    /// Waiting for the engine to be deactivated, so that the system IO stream
+    /// can be replaced with the render stream.
+    Deactivating {
+        settings: RenderSettings,
+        start: MusicalTime,
+        stem_inputs: Vec<Vec<usize>>,
+    },
+    /// Waiting for the audio graph to be restored in the render stream. Any
+    /// stems still need to be routed to the graph output once it has been.
+    ///
+    /// `stem_inputs` holds the mixer channel of every input of every stem.
+    WaitingForGraph {
+        handle: OfflineRenderHandle,
+        start: MusicalTime,
+        stem_inputs: Vec<Vec<usize>>,
+    },
+    Rendering(OfflineRenderHandle),
+}
+
+impl UiData {
+    /// Render the project to the given audio file. The format is chosen from
+    /// the extension of the file.
+    pub(super) fn start_render(&mut self, path: PathBuf) {
+        let format = match RenderFormat::from_path(&path) {
+            Some(format) => format,
+            None => {
+                self.notification_log.push(NotificationLogType::Error(format!(
+                    "Cannot render to {:?}: only WAV and FLAC files are supported",
+                    &path
+                )));
+                return;
+            }
+        };
+
+        let range = self.render_settings.range(&self.state);
+        self.begin_render(path, format, range, Vec::new());
+    }
+
+    /// Export every mixer channel (or group) to its own file in the given
+    /// directory, in a single pass.
+    pub(super) fn start_stem_export(&mut self, dir: PathBuf) {
+        let stems = self.state.stem_sources(self.render_settings.stem_mode);
+        if stems.is_empty() {
+            self.notification_log
+                .push(NotificationLogType::Error(String::from("There are no channels to export")));
+            return;
+        }
+
+        let format = self.render_settings.stem_format;
+        let range = self.render_settings.range(&self.state);
+        self.begin_render(dir, format, range, stems);
+    }
+
+    /// Render every region on the marker track to its own file in the given
+    /// directory, one after the other.
+    pub(super) fn start_region_export(&mut self, dir: PathBuf) {
+        if self.render.is_some() {
+            log::warn!("Cannot start a render while another render is running");
+            return;
+        }
+
+        let regions = self.state.timeline_grid.markers.regions();
+        if regions.is_empty() {
+            self.notification_log
+                .push(NotificationLogType::Error(String::from("There are no regions to export")));
+            return;
+        }
+
+        // Regions are exported in the same format as stems.
+        let format = self.render_settings.stem_format;
+        let file_names = unique_file_names(regions.iter().map(|r| r.name.as_str()), "Region");
+        self.pending_region_renders = regions
+            .iter()
+            .zip(file_names)
+            .map(|(region, file_name)| PendingRender {
+                path: dir.join(format!("{}.{}", file_name, format.extension())),
+                format,
+                range: (region.start.get(), region.end.get()),
+            })
+            .collect();
+
+        self.start_next_region_render();
+    }
+
+    /// Returns `false` if there are no regions left to render.
+    fn start_next_region_render(&mut self) -> bool {
+        match self.pending_region_renders.pop_front() {
+            Some(PendingRender { path, format, range }) => {
+                self.begin_render(path, format, range, Vec::new());
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn begin_render(
+        &mut self,
+        path: PathBuf,
+        format: RenderFormat,
+        (start, end): (MusicalTime, MusicalTime),
+        stems: Vec<StemSource>,
+    ) {
+        if self.render.is_some() {
+            log::warn!("Cannot start a render while another render is running");
+            return;
+        }
+
+        let sample_rate = self.render_settings.sample_rate.unwrap_or_else(|| {
+            self.system_io_stream_handle
+                .as_ref()
+                .map(|handle| handle.sample_rate().as_u32())
+                .unwrap_or(DEFAULT_NULL_SAMPLE_RATE)
+        });
+
+        let tempo_map = &self.state.timeline_grid.tempo_map;
+
+        let mut settings = RenderSettings {
+            path,
+            format,
+            bit_depth: self.render_settings.bit_depth,
+            sample_rate,
+            num_channels: self.system_io_config.graph_out_channels,
+            dither: self.render_settings.dither,
+            num_frames: tempo_map
+                .musical_to_frames(end, sample_rate)
+                .saturating_sub(tempo_map.musical_to_frames(start, sample_rate)),
+            tail_frames: (self.render_settings.tail_seconds * f64::from(sample_rate)).round()
+                as u64,
+            stems: Vec::new(),
+        };
+
+        let file_names = stem_file_names(&stems);
+        let mut stem_inputs = Vec::with_capacity(stems.len());
+        for (stem, file_name) in stems.iter().zip(file_names) {
+            let inputs = self.state.stem_inputs(
+                stem,
+                settings.num_channels,
+                self.render_settings.stem_pre_fader,
+            );
+
+            stem_inputs.push(inputs.iter().map(|(channel_i, _)| *channel_i).collect());
+            settings.stems.push(RenderStem {
+                file_name,
+                inputs: inputs
+                    .into_iter()
+                    .map(|(_, channel_gains)| RenderStemInput { channel_gains })
+                    .collect(),
+            });
+        }
+
+        self.render = Some(RenderJob::Deactivating { settings, start, stem_inputs });
+        self.render_progress = Some(0.0);
+
+        // The engine must be deactivated before the stream it runs in can be
+        // replaced. The render stream is started once the engine has sent back
+        // the save state of the audio graph and has been deactivated.
+        if let Some((engine_handles, _)) = &mut self.engine_handles {
+            if engine_handles.activated_info.is_some() {
+                engine_handles.ds_handle.send(DSEngineRequest::RequestLatestSaveState);
+                engine_handles.ds_handle.send(DSEngineRequest::DeactivateEngine);
+                return;
+            }
+        }
+
+        self.spawn_render_stream();
+    }
+
+    /// Replace the system IO stream with the render stream, and activate the
+    /// engine in it.
+    pub(super) fn spawn_render_stream(&mut self) {
+        let (settings, start, stem_inputs) = match self.render.take() {
+            Some(RenderJob::Deactivating { settings, start, stem_inputs }) => {
+                (settings, start, stem_inputs)
+            }
+            other => {
+                self.render = other;
+                return;
+            }
+        };
+
+        // Close the live stream.
+        self.system_io_stream_handle = None;
+
+        match render::spawn_offline_render_stream(settings, self.system_io_config.graph_in_channels)
+        {
+            Ok((system_io_stream_handle, handle)) => {
+                self.resource_loader.set_project_sample_rate(system_io_stream_handle.sample_rate());
+                self.last_clicked_browser_key = None;
+                self.pending_browser_load = None;
+                self.timeline_sources.clear();
+
+                self.system_io_stream_handle = Some(system_io_stream_handle);
+                self.render = Some(RenderJob::WaitingForGraph { handle, start, stem_inputs });
+
+                self.activate_engine();
+            }
+            Err(e) => {
+                log::error!("{}", e);
+                self.notification_log.push(NotificationLogType::Error(e.to_string()));
+                self.render_progress = None;
+
+                self.restart_system_io();
+            }
+        }
+    }
+
+    /// Start the render once the audio graph is ready, update its progress,
+    /// and go back to the live stream once it has ended.
+    pub(super) fn poll_render(&mut self, audio_graph_modified: bool) {
+        if audio_graph_modified {
+            if let Some(RenderJob::WaitingForGraph { .. }) = &self.render {
+                if let Some(RenderJob::WaitingForGraph { handle, start, stem_inputs }) =
+                    self.render.take()
+                {
+                    self.on_render_graph_ready(handle, start, stem_inputs);
+                }
+            }
+        }
+
+        let outcome = match &self.render {
+            Some(RenderJob::WaitingForGraph { handle, .. })
+            | Some(RenderJob::Rendering(handle)) => {
+                self.render_progress = Some(handle.progress());
+                handle.poll()
+            }
+            _ => None,
+        };
+
+        if let Some(outcome) = outcome {
+            self.render = None;
+            self.render_progress = None;
+
+            let finished = matches!(outcome, RenderOutcome::Finished { .. });
+            match outcome {
+                RenderOutcome::Finished { path } => {
+                    self.notification_log
+                        .push(NotificationLogType::Info(format!("Rendered project to {:?}", path)));
+                }
+                RenderOutcome::Cancelled => {
+                    self.notification_log
+                        .push(NotificationLogType::Info(String::from("Render cancelled")));
+                }
+                RenderOutcome::Failed(e) => {
+                    log::error!("{}", e);
+                    self.notification_log.push(NotificationLogType::Error(e.to_string()));
+                }
+            }
+
+            if let Some(activated_info) =
+                self.engine_handles.as_mut().and_then(|(h, _)| h.activated_info.as_mut())
+            {
+                // The render turned off the loop of the project.
+                activated_info.transport_handle.set_playing(false);
+                activated_info.transport_handle.set_loop_state(
+                    self.state.transport.loop_state(&self.state.timeline_grid.tempo_map),
+                );
+            }
+
+            // Every region is rendered in a render stream of its own, so this
+            // deactivates the engine again.
+            if finished && self.start_next_region_render() {
+                return;
+            }
+            self.pending_region_renders.clear();
+
+            // Go back to the live stream.
+            self.reconnect_system_io();
+        }
+    }
+
+    fn on_render_graph_ready(
+        &mut self,
+        handle: OfflineRenderHandle,
+        start: MusicalTime,
+        stem_inputs: Vec<Vec<usize>>,
+    ) {
+        if let Some((engine_handles, _)) = &mut self.engine_handles {
+            if !stem_inputs.is_empty() {
+                let edges = self.state.stem_edges(
+                    &stem_inputs,
+                    self.system_io_config.graph_out_channels,
+                    engine_handles,
+                );
+
+                // Wait for the stems to be connected before starting.
+                if !edges.is_empty() {
+                    engine_handles.ds_handle.send(DSEngineRequest::ModifyGraph(
+                        ModifyGraphRequest {
+                            add_plugin_instances: vec![],
+                            remove_plugin_instances: vec![],
+                            connect_new_edges: edges,
+                            disconnect_edges: vec![],
+                        },
+                    ));
+                    self.render =
+                        Some(RenderJob::WaitingForGraph { handle, start, stem_inputs: Vec::new() });
+                    return;
+                }
+            }
+
+            if let Some(activated_info) = &mut engine_handles.activated_info {
+                // The loop of the project would keep the render from ever
+                // reaching its end. It is restored in `poll_render()` once the
+                // render is over.
+                activated_info.transport_handle.set_loop_state(LoopState::Inactive);
+                activated_info
+                    .transport_handle
+                    .seek_to(self.state.timeline_grid.tempo_map.timeline_to_engine(start));
+                activated_info.transport_handle.set_playing(true);
+            }
+        }
+
+        handle.start();
+        self.render = Some(RenderJob::Rendering(handle));
+    }
+
+    pub(super) fn cancel_render(&mut self) {
+        match &self.render {
+            Some(RenderJob::Deactivating { .. }) => {
+                self.pending_region_renders.clear();
+
+                // Go back to the live stream once the engine has been
+                // deactivated.
+                self.render = None;
+                self.render_progress = None;
+                self.restart_system_io_on_deactivate = true;
+            }
+            Some(RenderJob::WaitingForGraph { handle, .. })
+            | Some(RenderJob::Rendering(handle)) => {
+                handle.cancel();
+            }
+            None => {}
+        }
+    }
+}
@@ -0,0 +1,621 @@
+use meadowlark_core_types::time::{MusicalTime, Seconds};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use vizia::prelude::*;
+
+use super::core_types::WMusicalTime;
+
+pub const DEFAULT_TEMPO_BPM: f64 = 120.0;
+pub const MIN_TEMPO_BPM: f64 = 20.0;
+pub const MAX_TEMPO_BPM: f64 = 999.0;
+
+/// The fixed tempo that the transport of the engine counts musical time at.
+///
+/// The engine does not know about the tempo map, so positions are converted
+/// through seconds on their way to and from its transport. That way the
+/// playhead frame the plugins see lines up with the frames the clips and
+/// metronome clicks are placed at.
+pub const ENGINE_TEMPO_BPM: f64 = 110.0;
+
+/// A pause longer than this starts a new round of tapping.
+const TAP_TEMPO_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The number of most recent taps that the tapped tempo is averaged over.
+const TAP_TEMPO_MAX_TAPS: usize = 8;
+
+/// How the tempo moves from one tempo change to the next.
+#[derive(Debug, Clone, Copy, PartialEq, Data, Serialize, Deserialize)]
+pub enum TempoCurve {
+    /// The tempo stays the same until the next change.
+    Jump,
+    /// The tempo moves in a straight line to the tempo of the next change.
+    Linear,
+}
+
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
+pub struct TempoChange {
+    pub position: WMusicalTime,
+    pub bpm: f64,
+    pub curve: TempoCurve,
+}
+
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
+pub struct TimeSignatureChange {
+    /// This is always at the start of a bar.
+    pub position: WMusicalTime,
+    pub numerator: u32,
+    pub denominator: u32,
+}
+
+impl TimeSignatureChange {
+    /// The length of a beat in quarter notes.
+    fn beat_length(&self) -> f64 {
+        4.0 / f64::from(self.denominator)
+    }
+
+    /// The length of a bar in quarter notes.
+    fn bar_length(&self) -> f64 {
+        f64::from(self.numerator) * self.beat_length()
+    }
+}
+
+/// A bar on the timeline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bar {
+    /// The number of the bar, counting from 1.
+    pub number: u32,
+    /// The start of the bar in quarter notes.
+    pub start: f64,
+    /// The number of beats in the bar.
+    pub beats: u32,
+    /// The length of a beat in quarter notes.
+    pub beat_length: f64,
+}
+
+/// The tempo and time signature of the project over the course of the
+/// timeline.
+///
+/// Musical time is counted in quarter notes, no matter the time signature.
+#[derive(Debug, Lens, Clone, Serialize, Deserialize)]
+#[serde(try_from = "TempoMapData")]
+pub struct TempoMap {
+    /// Sorted by position. The first change is always at the start of the
+    /// timeline.
+    tempo_changes: Vec<TempoChange>,
+
+    /// Sorted by position. The first change is always at the start of the
+    /// timeline.
+    time_signature_changes: Vec<TimeSignatureChange>,
+}
+
+/// A tempo map as it is stored in a project file, before it has been checked.
+#[derive(Deserialize)]
+struct TempoMapData {
+    tempo_changes: Vec<TempoChange>,
+    time_signature_changes: Vec<TimeSignatureChange>,
+}
+
+impl TryFrom<TempoMapData> for TempoMap {
+    type Error = String;
+
+    /// Values that are out of range are clamped, the same way they are when
+    /// they are edited. The first changes are moved to the start of the
+    /// timeline, and of several changes at the same position only the first
+    /// one is kept, so every linear ramp has a length.
+    fn try_from(data: TempoMapData) -> Result<Self, Self::Error> {
+        let TempoMapData { mut tempo_changes, mut time_signature_changes } = data;
+
+        if tempo_changes.is_empty() {
+            return Err(String::from("the tempo map has no tempo"));
+        }
+        if time_signature_changes.is_empty() {
+            return Err(String::from("the tempo map has no time signature"));
+        }
+
+        for change in tempo_changes.iter_mut() {
+            change.bpm = change.bpm.clamp(MIN_TEMPO_BPM, MAX_TEMPO_BPM);
+        }
+        for change in time_signature_changes.iter_mut() {
+            change.numerator = change.numerator.max(1);
+            change.denominator = valid_denominator(change.denominator);
+        }
+
+        let by_position = |a: &WMusicalTime, b: &WMusicalTime| {
+            a.get().as_beats_f64().total_cmp(&b.get().as_beats_f64())
+        };
+        tempo_changes.sort_by(|a, b| by_position(&a.position, &b.position));
+        time_signature_changes.sort_by(|a, b| by_position(&a.position, &b.position));
+
+        tempo_changes[0].position = WMusicalTime::default();
+        time_signature_changes[0].position = WMusicalTime::default();
+        tempo_changes.dedup_by(|a, b| a.position == b.position);
+        time_signature_changes.dedup_by(|a, b| a.position == b.position);
+
+        Ok(Self { tempo_changes, time_signature_changes })
+    }
+}
+
+impl Default for TempoMap {
+    fn default() -> Self {
+        Self::new(DEFAULT_TEMPO_BPM, 4, 4)
+    }
+}
+
+impl TempoMap {
+    pub fn new(bpm: f64, numerator: u32, denominator: u32) -> Self {
+        Self {
+            tempo_changes: vec![TempoChange {
+                position: WMusicalTime::default(),
+                bpm: bpm.clamp(MIN_TEMPO_BPM, MAX_TEMPO_BPM),
+                curve: TempoCurve::Jump,
+            }],
+            time_signature_changes: vec![TimeSignatureChange {
+                position: WMusicalTime::default(),
+                numerator: numerator.max(1),
+                denominator: valid_denominator(denominator),
+            }],
+        }
+    }
+
+    pub fn tempo_changes(&self) -> &[TempoChange] {
+        &self.tempo_changes
+    }
+
+    pub fn time_signature_changes(&self) -> &[TimeSignatureChange] {
+        &self.time_signature_changes
+    }
+
+    // ----- Tempo -----
+
+    /// Add a tempo change, replacing any change at the same position.
+    pub fn insert_tempo_change(&mut self, position: MusicalTime, bpm: f64, curve: TempoCurve) {
+        let change = TempoChange {
+            position: position.into(),
+            bpm: bpm.clamp(MIN_TEMPO_BPM, MAX_TEMPO_BPM),
+            curve,
+        };
+
+        let beats = position.as_beats_f64();
+        match self.tempo_changes.iter().position(|c| c.position.get().as_beats_f64() >= beats) {
+            Some(i) if self.tempo_changes[i].position.get() == position => {
+                self.tempo_changes[i] = change;
+            }
+            Some(i) => self.tempo_changes.insert(i, change),
+            None => self.tempo_changes.push(change),
+        }
+    }
+
+    /// Remove the tempo change at `index`. The first change cannot be removed.
+    pub fn remove_tempo_change(&mut self, index: usize) {
+        if index > 0 && index < self.tempo_changes.len() {
+            self.tempo_changes.remove(index);
+        }
+    }
+
+    /// Set the tempo of the tempo change that is in effect at `position`.
+    pub fn set_tempo_at(&mut self, position: MusicalTime, bpm: f64) {
+        let i = self.tempo_change_index_at(position.as_beats_f64());
+        self.tempo_changes[i].bpm = bpm.clamp(MIN_TEMPO_BPM, MAX_TEMPO_BPM);
+    }
+
+    /// The tempo in beats per minute at `position`.
+    pub fn tempo_at(&self, position: MusicalTime) -> f64 {
+        let beats = position.as_beats_f64();
+        let i = self.tempo_change_index_at(beats);
+        let change = &self.tempo_changes[i];
+
+        match (change.curve, self.tempo_changes.get(i + 1)) {
+            (TempoCurve::Linear, Some(next)) => {
+                let start = change.position.get().as_beats_f64();
+                let end = next.position.get().as_beats_f64();
+                change.bpm + (next.bpm - change.bpm) * (beats - start) / (end - start)
+            }
+            _ => change.bpm,
+        }
+    }
+
+    pub fn musical_to_seconds(&self, time: MusicalTime) -> Seconds {
+        let beats = time.as_beats_f64();
+        let mut seconds = 0.0;
+
+        for i in 0..self.tempo_changes.len() {
+            let start = self.tempo_changes[i].position.get().as_beats_f64();
+            match self.tempo_changes.get(i + 1) {
+                Some(next) if next.position.get().as_beats_f64() < beats => {
+                    seconds += self.segment_seconds(i, next.position.get().as_beats_f64() - start);
+                }
+                _ => {
+                    seconds += self.segment_seconds(i, beats - start);
+                    break;
+                }
+            }
+        }
+
+        Seconds(seconds)
+    }
+
+    pub fn seconds_to_musical(&self, seconds: Seconds) -> MusicalTime {
+        let mut seconds_left = seconds.0.max(0.0);
+        let mut beats = 0.0;
+
+        for i in 0..self.tempo_changes.len() {
+            let start = self.tempo_changes[i].position.get().as_beats_f64();
+            if let Some(next) = self.tempo_changes.get(i + 1) {
+                let length = next.position.get().as_beats_f64() - start;
+                let segment_seconds = self.segment_seconds(i, length);
+                if segment_seconds < seconds_left {
+                    seconds_left -= segment_seconds;
+                    continue;
+                }
+            }
+
+            beats = start + self.segment_beats(i, seconds_left);
+            break;
+        }
+
+        MusicalTime::from_beats_f64(beats)
+    }
+
+    /// Convert a position on the timeline to the position of the transport of
+    /// the engine that plays the same frame.
+    pub fn timeline_to_engine(&self, time: MusicalTime) -> MusicalTime {
+        MusicalTime::from_beats_f64(self.musical_to_seconds(time).0 * ENGINE_TEMPO_BPM / 60.0)
+    }
+
+    /// Convert a position of the transport of the engine to the position on
+    /// the timeline that plays the same frame.
+    pub fn engine_to_timeline(&self, time: MusicalTime) -> MusicalTime {
+        self.seconds_to_musical(Seconds(time.as_beats_f64() * 60.0 / ENGINE_TEMPO_BPM))
+    }
+
+    /// Convert a musical time to the nearest frame at the given sample rate.
+    pub fn musical_to_frames(&self, time: MusicalTime, sample_rate: u32) -> u64 {
+        (self.musical_to_seconds(time).0 * f64::from(sample_rate)).round() as u64
+    }
+
+    fn tempo_change_index_at(&self, beats: f64) -> usize {
+        self.tempo_changes
+            .iter()
+            .rposition(|c| c.position.get().as_beats_f64() <= beats)
+            .unwrap_or(0)
+    }
+
+    /// The slope of the tempo of a linear ramp in beats per minute per beat,
+    /// or `None` if the tempo is constant.
+    fn segment_slope(&self, i: usize) -> Option<f64> {
+        let change = &self.tempo_changes[i];
+        match (change.curve, self.tempo_changes.get(i + 1)) {
+            (TempoCurve::Linear, Some(next)) if next.bpm != change.bpm => {
+                let length =
+                    next.position.get().as_beats_f64() - change.position.get().as_beats_f64();
+                Some((next.bpm - change.bpm) / length)
+            }
+            _ => None,
+        }
+    }
+
+    /// The time it takes to play `beats` beats from the start of the tempo
+    /// change at `i`.
+    fn segment_seconds(&self, i: usize, beats: f64) -> f64 {
+        let bpm = self.tempo_changes[i].bpm;
+        match self.segment_slope(i) {
+            // The integral of 60 / tempo over the ramp.
+            Some(slope) => 60.0 / slope * ((bpm + slope * beats) / bpm).ln(),
+            None => beats * 60.0 / bpm,
+        }
+    }
+
+    /// The number of beats played in `seconds` from the start of the tempo
+    /// change at `i`.
+    fn segment_beats(&self, i: usize, seconds: f64) -> f64 {
+        let bpm = self.tempo_changes[i].bpm;
+        match self.segment_slope(i) {
+            Some(slope) => (bpm * (seconds * slope / 60.0).exp() - bpm) / slope,
+            None => seconds * bpm / 60.0,
+        }
+    }
+
+    // ----- Time signature -----
+
+    /// Add a time signature change at the start of the bar that contains
+    /// `position`, replacing any change that is already there.
+    pub fn insert_time_signature_change(
+        &mut self,
+        position: MusicalTime,
+        numerator: u32,
+        denominator: u32,
+    ) {
+        let bar_start = self.bar_at(position.as_beats_f64()).start;
+        let change = TimeSignatureChange {
+            position: MusicalTime::from_beats_f64(bar_start).into(),
+            numerator: numerator.max(1),
+            denominator: valid_denominator(denominator),
+        };
+
+        match self
+            .time_signature_changes
+            .iter()
+            .position(|c| c.position.get().as_beats_f64() >= bar_start)
+        {
+            Some(i)
+                if self.time_signature_changes[i].position.get().as_beats_f64() == bar_start =>
+            {
+                self.time_signature_changes[i] = change;
+            }
+            Some(i) => self.time_signature_changes.insert(i, change),
+            None => self.time_signature_changes.push(change),
+        }
+    }
+
+    /// Remove the time signature change at `index`. The first change cannot be
+    /// removed.
+    pub fn remove_time_signature_change(&mut self, index: usize) {
+        if index > 0 && index < self.time_signature_changes.len() {
+            self.time_signature_changes.remove(index);
+        }
+    }
+
+    /// The time signature at `position` as `(numerator, denominator)`.
+    pub fn time_signature_at(&self, position: MusicalTime) -> (u32, u32) {
+        let beats = position.as_beats_f64();
+        let change = self
+            .time_signature_changes
+            .iter()
+            .rev()
+            .find(|c| c.position.get().as_beats_f64() <= beats)
+            .unwrap_or(&self.time_signature_changes[0]);
+        (change.numerator, change.denominator)
+    }
+
+    /// Returns every bar that overlaps the range from `start` to `end` in
+    /// quarter notes.
+    pub fn bars(&self, start: f64, end: f64) -> Vec<Bar> {
+        let mut bars = Vec::new();
+        let mut number = 1;
+
+        for (i, change) in self.time_signature_changes.iter().enumerate() {
+            let change_start = change.position.get().as_beats_f64();
+            let change_end = self
+                .time_signature_changes
+                .get(i + 1)
+                .map(|next| next.position.get().as_beats_f64())
+                .unwrap_or(f64::INFINITY);
+            if change_start > end {
+                break;
+            }
+
+            let bar_length = change.bar_length();
+            let mut bar_start = change_start;
+            while bar_start < change_end && bar_start <= end {
+                if bar_start + bar_length > start {
+                    bars.push(Bar {
+                        number,
+                        start: bar_start,
+                        beats: change.numerator,
+                        beat_length: change.beat_length(),
+                    });
+                }
+                bar_start += bar_length;
+                number += 1;
+            }
+        }
+
+        bars
+    }
+
+    /// The bar that contains `beats`.
+    pub fn bar_at(&self, beats: f64) -> Bar {
+        let beats = beats.max(0.0);
+        self.bars(beats, beats)
+            .into_iter()
+            .rev()
+            .find(|bar| bar.start <= beats)
+            .expect("there is always a bar at the start of the timeline")
+    }
+
+    /// Format a position on the timeline as "bar.beat.sixteenth", counting
+    /// from 1.
+    pub fn format_position(&self, time: MusicalTime) -> String {
+        let beats = time.as_beats_f64();
+        let bar = self.bar_at(beats);
+        let beat_in_bar = (beats - bar.start) / bar.beat_length;
+        let sixteenth = (beat_in_bar.fract() * bar.beat_length * 4.0).floor() as u32;
+
+        format!("{}.{}.{}", bar.number, beat_in_bar.floor() as u32 + 1, sixteenth + 1)
+    }
+}
+
+/// Time signature denominators must be a power of two.
+fn valid_denominator(denominator: u32) -> u32 {
+    denominator.clamp(1, 32).next_power_of_two()
+}
+
+/// Finds the tempo from the times the user taps it out.
+#[derive(Debug, Default)]
+pub struct TapTempo {
+    taps: Vec<Instant>,
+}
+
+impl TapTempo {
+    /// Register a tap. Returns the average tempo of the recent taps once
+    /// there have been at least two of them.
+    pub fn tap(&mut self, now: Instant) -> Option<f64> {
+        if let Some(last) = self.taps.last() {
+            if now.duration_since(*last) > TAP_TEMPO_TIMEOUT {
+                self.taps.clear();
+            }
+        }
+
+        self.taps.push(now);
+        if self.taps.len() > TAP_TEMPO_MAX_TAPS {
+            self.taps.remove(0);
+        }
+
+        let (first, last) = (self.taps.first()?, self.taps.last()?);
+        let intervals = self.taps.len() - 1;
+        if intervals == 0 {
+            return None;
+        }
+
+        let beat_seconds = last.duration_since(*first).as_secs_f64() / intervals as f64;
+        Some((60.0 / beat_seconds).clamp(MIN_TEMPO_BPM, MAX_TEMPO_BPM))
+    }
+}
+
+pub enum TempoEvent {
+    InsertTempoChange {
+        position: MusicalTime,
+        bpm: f64,
+        curve: TempoCurve,
+    },
+    RemoveTempoChange(usize),
+    /// Set the tempo that is in effect at the given position.
+    SetTempoAt {
+        position: MusicalTime,
+        bpm: f64,
+    },
+    InsertTimeSignatureChange {
+        position: MusicalTime,
+        numerator: u32,
+        denominator: u32,
+    },
+    RemoveTimeSignatureChange(usize),
+}
+
+impl Model for TempoMap {
+    fn event(&mut self, cx: &mut EventContext, event: &mut Event) {
+        event.map(|tempo_event, _| {
+            match tempo_event {
+                TempoEvent::InsertTempoChange { position, bpm, curve } => {
+                    self.insert_tempo_change(*position, *bpm, *curve);
+                }
+                TempoEvent::RemoveTempoChange(index) => {
+                    self.remove_tempo_change(*index);
+                }
+                TempoEvent::SetTempoAt { position, bpm } => {
+                    self.set_tempo_at(*position, *bpm);
+                }
+                TempoEvent::InsertTimeSignatureChange { position, numerator, denominator } => {
+                    self.insert_time_signature_change(*position, *numerator, *denominator);
+                }
+                TempoEvent::RemoveTimeSignatureChange(index) => {
+                    self.remove_time_signature_change(*index);
+                }
+            }
+            cx.needs_redraw();
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use meadowlark_core_types::time::{MusicalTime, Seconds};
+
+    use super::{TempoCurve, TempoMap, ENGINE_TEMPO_BPM};
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+    }
+
+    /// A ramp from 120 to 240 BPM over the first two bars, followed by a
+    /// steady 240 BPM.
+    fn ramp() -> TempoMap {
+        let mut tempo_map = TempoMap::new(120.0, 4, 4);
+        tempo_map.insert_tempo_change(MusicalTime::from_beats(0), 120.0, TempoCurve::Linear);
+        tempo_map.insert_tempo_change(MusicalTime::from_beats(8), 240.0, TempoCurve::Jump);
+        tempo_map
+    }
+
+    #[test]
+    fn ramp_round_trip() {
+        let tempo_map = ramp();
+
+        // The integral of 60 / tempo over the ramp.
+        let ramp_seconds = 4.0 * 2f64.ln();
+        assert_close(tempo_map.musical_to_seconds(MusicalTime::from_beats(8)).0, ramp_seconds);
+        assert_close(
+            tempo_map.musical_to_seconds(MusicalTime::from_beats(12)).0,
+            ramp_seconds + 1.0,
+        );
+
+        for i in 0..=64 {
+            let beats = f64::from(i) * 0.25;
+            let seconds = tempo_map.musical_to_seconds(MusicalTime::from_beats_f64(beats));
+            assert_close(tempo_map.seconds_to_musical(seconds).as_beats_f64(), beats);
+        }
+        for i in 0..=40 {
+            let seconds = f64::from(i) * 0.1;
+            let beats = tempo_map.seconds_to_musical(Seconds(seconds));
+            assert_close(tempo_map.musical_to_seconds(beats).0, seconds);
+        }
+    }
+
+    #[test]
+    fn bar_at() {
+        let mut tempo_map = TempoMap::new(120.0, 4, 4);
+        tempo_map.insert_time_signature_change(MusicalTime::from_beats(9), 6, 8);
+
+        // The change is moved to the start of the third bar.
+        assert_eq!(tempo_map.time_signature_changes()[1].position.get().as_beats_f64(), 8.0);
+
+        let bar = tempo_map.bar_at(0.0);
+        assert_eq!((bar.number, bar.start, bar.beats), (1, 0.0, 4));
+        let bar = tempo_map.bar_at(7.99);
+        assert_eq!((bar.number, bar.start, bar.beats), (2, 4.0, 4));
+        let bar = tempo_map.bar_at(8.0);
+        assert_eq!((bar.number, bar.start, bar.beats, bar.beat_length), (3, 8.0, 6, 0.5));
+        let bar = tempo_map.bar_at(12.0);
+        assert_eq!((bar.number, bar.start), (4, 11.0));
+
+        assert_eq!(tempo_map.format_position(MusicalTime::from_beats_f64(11.75)), "4.2.2");
+    }
+
+    #[test]
+    fn timeline_to_engine() {
+        let tempo_map = TempoMap::new(ENGINE_TEMPO_BPM, 4, 4);
+        let time = MusicalTime::from_beats(6);
+        assert_close(tempo_map.timeline_to_engine(time).as_beats_f64(), 6.0);
+
+        // Four beats at 120 BPM take two seconds.
+        let tempo_map = TempoMap::new(120.0, 4, 4);
+        let engine_time = tempo_map.timeline_to_engine(MusicalTime::from_beats(4));
+        assert_close(engine_time.as_beats_f64(), 2.0 * ENGINE_TEMPO_BPM / 60.0);
+
+        let tempo_map = ramp();
+        for i in 0..=16 {
+            let beats = f64::from(i) * 0.75;
+            let engine_time = tempo_map.timeline_to_engine(MusicalTime::from_beats_f64(beats));
+            assert_close(tempo_map.engine_to_timeline(engine_time).as_beats_f64(), beats);
+        }
+    }
+
+    /// The first changes of a loaded tempo map are moved to the start of the
+    /// timeline, and a ramp to a change at the same position is dropped.
+    #[test]
+    fn repair_loaded_tempo_map() {
+        let tempo_map: TempoMap = serde_json::from_str(
+            r#"{
+                "tempo_changes": [
+                    { "position": { "beats": 8, "super_beats": 0 }, "bpm": 90.0, "curve": "Linear" },
+                    { "position": { "beats": 8, "super_beats": 0 }, "bpm": 60.0, "curve": "Linear" },
+                    { "position": { "beats": 4, "super_beats": 0 }, "bpm": 120.0, "curve": "Jump" }
+                ],
+                "time_signature_changes": [
+                    { "position": { "beats": 4, "super_beats": 0 }, "numerator": 3, "denominator": 4 }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let positions: Vec<f64> =
+            tempo_map.tempo_changes().iter().map(|c| c.position.get().as_beats_f64()).collect();
+        assert_eq!(positions, [0.0, 8.0]);
+        assert_eq!(tempo_map.tempo_changes()[1].bpm, 90.0);
+        assert_eq!(tempo_map.time_signature_changes()[0].position.get().as_beats_f64(), 0.0);
+
+        assert_close(tempo_map.musical_to_seconds(MusicalTime::from_beats(8)).0, 4.0);
+        assert_close(tempo_map.musical_to_seconds(MusicalTime::from_beats(11)).0, 6.0);
+        assert_eq!(tempo_map.bar_at(1.0).number, 1);
+        assert_eq!(tempo_map.format_position(MusicalTime::from_beats(3)), "2.1.1");
+    }
+}
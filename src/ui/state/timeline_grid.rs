@@ -1,8 +1,10 @@
 use super::core_types::WMusicalTime;
-use super::{LaneStates, UiEvent};
+use super::{ClipState, LaneStates, MarkerTrack, TempoMap, UiEvent};
+use meadowlark_core_types::time::MusicalTime;
+use serde::{Deserialize, Serialize};
 use vizia::prelude::*;
 
-#[derive(Debug, Lens, Clone)]
+#[derive(Debug, Lens, Clone, Serialize, Deserialize)]
 pub struct TimelineGridState {
     /// 1.0 means the "default zoom level".
     ///
@@ -41,16 +43,27 @@ pub struct TimelineGridState {
     /// The list of all current lanes. (Maybe start with like 100 for a new project?)
     pub lane_states: LaneStates,
 
-    /// The time of the end of the latest clip on the timeline. This can be used to
-    /// properly set the horizontal scroll bar.
+    /// The time of the end of the latest clip on the timeline, or the end of the
+    /// loop region if that is later. Once the project has been edited, this is
+    /// never shorter than `DEFAULT_PROJECT_LENGTH_BEATS`, but a loaded project
+    /// keeps the length it was saved with. This can be used to properly set
+    /// the horizontal scroll bar.
     pub project_length: WMusicalTime,
 
     /// The index of the highest-indexed lane that currently has a clip on it. This
     /// can be used to properly set the vertical scroll bar.
     pub used_lanes: u32,
-    // TODO: Time signature
+
+    /// The tempo and time signature changes of the project.
+    pub tempo_map: TempoMap,
+
+    /// The named markers and regions on the timeline.
+    pub markers: MarkerTrack,
 }
 
+/// The length of an empty project.
+pub const DEFAULT_PROJECT_LENGTH_BEATS: u32 = 16;
+
 pub const VERTICAL_ZOOM_STEP: f64 = 0.25;
 // TODO: Horizontal zoom
 // pub const HORIZONTAL_ZOOM_STEP: f64 = 0.25;
@@ -60,6 +73,27 @@ pub const MINIMUM_LANE_HEIGHT: f64 = 0.25;
 pub const MAXIMUM_LANE_HEIGHT: f64 = 4.0;
 pub const LANE_HEIGHT_STEP: f64 = 0.25;
 
+impl TimelineGridState {
+    /// Update `project_length` and `used_lanes` from the clips that are
+    /// currently on the timeline and the end of the loop region.
+    pub fn update_extent(&mut self, clips: &[ClipState], loop_end: MusicalTime) {
+        let mut project_length =
+            loop_end.as_beats_f64().max(f64::from(DEFAULT_PROJECT_LENGTH_BEATS));
+        let mut used_lanes = 0;
+
+        for clip in clips.iter() {
+            if let (Some(on_lane), Some(end)) = (clip.timeline_start.on_lane(), clip.timeline_end())
+            {
+                project_length = end.as_beats_f64().max(project_length);
+                used_lanes = on_lane.lane_index().max(used_lanes);
+            }
+        }
+
+        self.project_length = MusicalTime::from_beats_f64(project_length).into();
+        self.used_lanes = used_lanes;
+    }
+}
+
 impl Model for TimelineGridState {
     fn event(&mut self, cx: &mut EventContext, event: &mut Event) {
         event.map(|event, _| match event {
@@ -94,5 +128,7 @@ impl Model for TimelineGridState {
             _ => {}
         });
         self.lane_states.event(cx, event);
+        self.tempo_map.event(cx, event);
+        self.markers.event(cx, event);
     }
 }
@@ -0,0 +1,478 @@
+use basedrop::Owned;
+use dropseed::plugin::{PluginInstanceID, PluginSaveState};
+use dropseed::{
+    DSEngineHandle, DSEngineRequest, EdgeReq, EdgeReqPortID, ModifyGraphRequest,
+    PluginActivationStatus, PluginHandle, PluginIDReq, PortType,
+};
+use meadowlark_core_types::time::SampleRate;
+use pcm_loader::ResampleQuality;
+use std::collections::VecDeque;
+
+use crate::backend::disk_stream::{DiskStream, PcmSource};
+use crate::backend::resource_loader::{PcmKey, PcmLoadHandle, ResourceLoader};
+use crate::backend::timeline_track::{
+    TimelineAudioClip, TimelineTrackPlugHandle, TIMELINE_TRACK_PLUG_RDN,
+};
+
+use super::{AudioClipState, ClipType, UiData, UiState};
+
+/// A timeline track plugin in the audio graph.
+struct TimelineTrack {
+    plugin_id: PluginInstanceID,
+
+    /// This is `None` if the plugin failed to load or activate.
+    handle: Option<PluginHandle>,
+}
+
+/// The timeline track plugins that play the clips of each mixer channel.
+#[derive(Default)]
+pub struct TimelineTracks {
+    /// The track of every mixer channel, by channel index.
+    tracks: Vec<Option<TimelineTrack>>,
+
+    /// The channels whose tracks have been requested but not added to the
+    /// audio graph yet, in the order they were requested.
+    pending: VecDeque<usize>,
+
+    /// The tracks that do not belong to any channel, i.e. the ones restored
+    /// from a save state. These are replaced with new tracks, so that the
+    /// channel of every track is known.
+    stale: Vec<PluginInstanceID>,
+}
+
+impl TimelineTracks {
+    /// Forget about all of the tracks, i.e. once the audio graph has been
+    /// cleared.
+    pub fn clear(&mut self) {
+        self.tracks.clear();
+        self.pending.clear();
+        self.stale.clear();
+    }
+
+    /// Keep track of a timeline track plugin that was added to the audio
+    /// graph.
+    ///
+    /// Tracks are given to the channels they were requested for in the order
+    /// they arrive. Tracks that were not requested by `update_graph()` are
+    /// removed by the next call to it.
+    pub fn on_track_added(&mut self, plugin_id: PluginInstanceID, status: PluginActivationStatus) {
+        let handle = match status {
+            PluginActivationStatus::Activated { new_handle, .. } => Some(new_handle),
+            PluginActivationStatus::Inactive => None,
+            PluginActivationStatus::LoadError(e) => {
+                log::error!("Failed to load timeline track plugin: {}", e);
+                None
+            }
+            PluginActivationStatus::ActivationError(e) => {
+                log::error!("Failed to activate timeline track plugin: {}", e);
+                None
+            }
+        };
+
+        match self.pending.pop_front() {
+            Some(channel_i) => {
+                if channel_i >= self.tracks.len() {
+                    self.tracks.resize_with(channel_i + 1, || None);
+                }
+                self.tracks[channel_i] = Some(TimelineTrack { plugin_id, handle });
+            }
+            None => self.stale.push(plugin_id),
+        }
+    }
+
+    /// Returns `true` if every track that was requested has been added to the
+    /// audio graph, and there are no tracks left to remove.
+    pub fn is_ready(&self) -> bool {
+        self.pending.is_empty() && self.stale.is_empty()
+    }
+
+    /// Add a track for every mixer channel that does not have one yet, and
+    /// remove the tracks that do not belong to a channel.
+    ///
+    /// Every track is connected straight to the graph output.
+    pub fn update_graph(
+        &mut self,
+        ds_handle: &mut DSEngineHandle,
+        graph_out_node_id: &PluginInstanceID,
+        num_channels: usize,
+    ) {
+        // Wait for the tracks that were already requested, so that no channel
+        // gets two of them.
+        if !self.pending.is_empty() {
+            return;
+        }
+
+        // The tracks of channels that were removed.
+        if self.tracks.len() > num_channels {
+            let removed = self.tracks.drain(num_channels..).flatten();
+            self.stale.extend(removed.map(|track| track.plugin_id));
+        }
+        self.tracks.resize_with(num_channels, || None);
+
+        let missing: Vec<usize> = (0..num_channels).filter(|i| self.tracks[*i].is_none()).collect();
+        if missing.is_empty() && self.stale.is_empty() {
+            return;
+        }
+
+        let track_plug_key = ds_handle
+            .internal_plugins_res
+            .iter()
+            .flatten()
+            .find(|key| &key.rdn == TIMELINE_TRACK_PLUG_RDN)
+            .cloned();
+        let track_plug_key = match track_plug_key {
+            Some(key) => key,
+            None => {
+                log::error!("The timeline track plugin is not available");
+                return;
+            }
+        };
+
+        let mut connect_new_edges = Vec::new();
+        for plugin_index in 0..missing.len() {
+            for channel in 0..2 {
+                connect_new_edges.push(EdgeReq {
+                    edge_type: PortType::Audio,
+                    src_plugin_id: PluginIDReq::Added(plugin_index),
+                    dst_plugin_id: PluginIDReq::Existing(graph_out_node_id.clone()),
+                    src_port_id: EdgeReqPortID::Main,
+                    src_port_channel: channel,
+                    dst_port_id: EdgeReqPortID::Main,
+                    dst_port_channel: channel,
+                    log_error_on_fail: true,
+                });
+            }
+        }
+
+        ds_handle.send(DSEngineRequest::ModifyGraph(ModifyGraphRequest {
+            add_plugin_instances: missing
+                .iter()
+                .map(|_| PluginSaveState::new_with_default_preset(track_plug_key.clone()))
+                .collect(),
+            remove_plugin_instances: std::mem::take(&mut self.stale),
+            connect_new_edges,
+            disconnect_edges: vec![],
+        }));
+
+        self.pending.extend(missing);
+    }
+
+    /// The plugin that plays the clips of the given mixer channel.
+    pub fn plugin_id(&self, channel_i: usize) -> Option<&PluginInstanceID> {
+        self.tracks
+            .get(channel_i)?
+            .as_ref()
+            .filter(|track| track.handle.is_some())
+            .map(|track| &track.plugin_id)
+    }
+
+    fn handle_mut(&mut self, channel_i: usize) -> Option<&mut TimelineTrackPlugHandle> {
+        self.tracks
+            .get_mut(channel_i)?
+            .as_mut()?
+            .handle
+            .as_mut()?
+            .internal
+            .as_mut()?
+            .downcast_mut::<TimelineTrackPlugHandle>()
+    }
+
+    /// Returns the number of underruns of all of the tracks since this was
+    /// last called.
+    pub fn take_underruns(&mut self) -> u32 {
+        let mut underruns = 0;
+        for channel_i in 0..self.tracks.len() {
+            if let Some(handle) = self.handle_mut(channel_i) {
+                underruns += handle.take_underruns();
+            }
+        }
+        underruns
+    }
+
+    /// Send the clips on the timeline to the track of every mixer channel.
+    ///
+    /// `source` returns the audio of a clip starting at the given frame in the
+    /// file, or `None` if it is not available, in which case the clip is left
+    /// out.
+    pub fn sync_clips(
+        &mut self,
+        state: &UiState,
+        sample_rate: SampleRate,
+        mut source: impl FnMut(&PcmKey, u64) -> Option<PcmSource>,
+    ) {
+        for channel_i in 0..self.tracks.len() {
+            if let Some(handle) = self.handle_mut(channel_i) {
+                handle.set_clips(timeline_clips(state, channel_i, sample_rate, &mut source));
+            }
+        }
+    }
+}
+
+/// The key that the audio of a clip on the timeline is loaded with.
+pub fn timeline_clip_key(clip: &AudioClipState) -> PcmKey {
+    PcmKey {
+        path: clip.pcm_path.clone(),
+        resample_to_project_sr: true,
+        resample_quality: ResampleQuality::Linear,
+        doppler_stretch_ratio: 1.0,
+    }
+}
+
+/// Returns the audio clips on the timeline that are played by the track of
+/// the given mixer channel, placed with the tempo map of the project.
+pub fn timeline_clips(
+    state: &UiState,
+    channel_i: usize,
+    sample_rate: SampleRate,
+    mut source: impl FnMut(&PcmKey, u64) -> Option<PcmSource>,
+) -> Vec<TimelineAudioClip> {
+    let tempo_map = &state.timeline_grid.tempo_map;
+
+    state
+        .clips
+        .iter()
+        .filter(|clip| clip.channel == channel_i)
+        .filter_map(|clip| {
+            let audio_clip = match &clip.type_ {
+                ClipType::Audio(audio_clip) => audio_clip,
+                _ => return None,
+            };
+
+            // An empty path means that the clip has no file.
+            if audio_clip.pcm_path.as_os_str().is_empty() {
+                return None;
+            }
+
+            let start = clip.timeline_start.on_lane()?.timeline_start();
+            let end = clip.timeline_end()?;
+
+            let pcm_start =
+                audio_clip.clip_start_offset.get().to_nearest_frame_round(sample_rate).0;
+            let source = source(&timeline_clip_key(audio_clip), pcm_start)?;
+
+            let start_frame = tempo_map.musical_to_frames(start, sample_rate.as_u32());
+            let end_frame = tempo_map.musical_to_frames(end, sample_rate.as_u32());
+
+            Some(audio_clip.to_timeline_clip(
+                source,
+                start_frame,
+                end_frame.saturating_sub(start_frame),
+                sample_rate,
+            ))
+        })
+        .collect()
+}
+
+/// The audio of the clips on the timeline that is being loaded into RAM or
+/// streamed from disk.
+///
+/// A stream can only be played by one clip, so every time the clips are sent
+/// to the timeline tracks, new streams are opened for the clips that are
+/// streamed. The clips are only sent once all of those streams are ready, so
+/// the tracks keep playing the old clips in the meantime.
+#[derive(Default)]
+pub struct TimelineSources {
+    /// The samples that are being loaded into RAM.
+    pending_loads: Vec<(PcmLoadHandle, PcmKey)>,
+
+    /// The streams that are being opened, and the frame they start at.
+    pending_streams: Vec<(PcmLoadHandle, PcmKey, u64)>,
+
+    /// The streams that have been opened but not sent to a track yet.
+    streams: Vec<(PcmKey, u64, DiskStream)>,
+
+    /// The streams that could not be opened. Their clips are left out the
+    /// next time the clips are sent, and they are tried again after that.
+    failed_streams: Vec<(PcmKey, u64)>,
+}
+
+impl TimelineSources {
+    pub fn clear(&mut self) {
+        self.pending_loads.clear();
+        self.pending_streams.clear();
+        self.streams.clear();
+        self.failed_streams.clear();
+    }
+
+    /// Request a stream for every one of the given clips that does not have
+    /// one yet.
+    ///
+    /// Returns `true` if the streams of all of them have been opened (or
+    /// failed to open), so that the clips can be sent to the tracks.
+    fn request_streams(
+        &mut self,
+        resource_loader: &mut ResourceLoader,
+        clips: &[(PcmKey, u64)],
+    ) -> bool {
+        // Clips can share a file, but not a stream.
+        let mut opened: Vec<(&PcmKey, u64)> =
+            self.streams.iter().map(|(key, start, _)| (key, *start)).collect();
+        let mut opening: Vec<(&PcmKey, u64)> =
+            self.pending_streams.iter().map(|(_, key, start)| (key, *start)).collect();
+        let mut to_request = Vec::new();
+
+        for (key, start) in clips.iter() {
+            let matches = |(k, s): &(&PcmKey, u64)| *k == key && s == start;
+
+            if let Some(i) = opened.iter().position(matches) {
+                opened.swap_remove(i);
+            } else if let Some(i) = opening.iter().position(matches) {
+                opening.swap_remove(i);
+            } else if !self.failed_streams.iter().any(|(k, s)| k == key && s == start) {
+                to_request.push((key, *start));
+            }
+        }
+
+        let ready = self.pending_streams.len() == opening.len() && to_request.is_empty();
+
+        for (key, start) in to_request {
+            let handle = resource_loader.request_stream(key, start);
+            self.pending_streams.push((handle, key.clone(), start));
+        }
+
+        ready
+    }
+
+    /// Returns the audio of a clip that starts at `pcm_start` in the file, or
+    /// requests it from the resource loader if it is not loaded yet.
+    fn source(
+        &mut self,
+        resource_loader: &mut ResourceLoader,
+        key: &PcmKey,
+        pcm_start: u64,
+    ) -> Option<PcmSource> {
+        if resource_loader.should_stream(key) {
+            let i = self.streams.iter().position(|(k, s, _)| k == key && *s == pcm_start)?;
+            let (_, _, stream) = self.streams.remove(i);
+            return Some(PcmSource::Stream(Owned::new(
+                &resource_loader.collector_handle(),
+                stream,
+            )));
+        }
+
+        if let Some(pcm) = resource_loader.get_loaded(key) {
+            return Some(PcmSource::Ram(pcm));
+        }
+
+        if !self.pending_loads.iter().any(|(_, k)| k == key) {
+            self.pending_loads.push((resource_loader.request_pcm(key), key.clone()));
+        }
+        None
+    }
+
+    /// Returns `true` if the sample was requested for a clip on the timeline.
+    pub fn on_loaded(&mut self, handle: PcmLoadHandle) -> bool {
+        let len = self.pending_loads.len();
+        self.pending_loads.retain(|(h, _)| *h != handle);
+        self.pending_loads.len() != len
+    }
+
+    /// Forget about a sample that could not be loaded. Its clips are left out
+    /// of their timeline tracks.
+    pub fn on_load_failed(&mut self, handle: PcmLoadHandle) {
+        self.pending_loads.retain(|(h, _)| *h != handle);
+    }
+
+    /// Keep the stream if it was requested for a clip on the timeline.
+    ///
+    /// Returns `true` if this was the last stream that was being opened, so
+    /// that the clips can be sent to the tracks.
+    pub fn on_stream_opened(&mut self, handle: PcmLoadHandle, stream: DiskStream) -> bool {
+        match self.pending_streams.iter().position(|(h, _, _)| *h == handle) {
+            Some(i) => {
+                let (_, key, start) = self.pending_streams.remove(i);
+                self.streams.push((key, start, stream));
+                self.pending_streams.is_empty()
+            }
+            None => false,
+        }
+    }
+
+    /// Returns `true` if the stream was requested for a clip on the timeline,
+    /// and it was the last stream that was being opened.
+    pub fn on_stream_failed(&mut self, handle: PcmLoadHandle) -> bool {
+        match self.pending_streams.iter().position(|(h, _, _)| *h == handle) {
+            Some(i) => {
+                let (_, key, start) = self.pending_streams.remove(i);
+                self.failed_streams.push((key, start));
+                self.pending_streams.is_empty()
+            }
+            None => false,
+        }
+    }
+}
+
+impl UiData {
+    /// Make sure that every mixer channel has a timeline track plugin, and
+    /// send the clips on the timeline to them.
+    pub(super) fn sync_timeline_tracks(&mut self) {
+        if let Some((engine_handles, _)) = &mut self.engine_handles {
+            if let Some(activated_info) = &engine_handles.activated_info {
+                engine_handles.timeline_tracks.update_graph(
+                    &mut engine_handles.ds_handle,
+                    &activated_info.graph_out_node_id,
+                    self.state.channels.len(),
+                );
+            }
+        }
+
+        self.sync_timeline_clips();
+    }
+
+    /// Send the clips on the timeline to the timeline track plugins.
+    ///
+    /// Samples that are not loaded yet are requested from the resource loader,
+    /// and the clips are sent again once they have been loaded. Long samples
+    /// are streamed from disk, and the clips are only sent once their streams
+    /// have been opened.
+    ///
+    /// While rendering, every sample is loaded into RAM on this thread
+    /// instead, so that the render does not start without them and never
+    /// waits on the disk.
+    pub(super) fn sync_timeline_clips(&mut self) {
+        let Self { state, engine_handles, resource_loader, timeline_sources, render, .. } = self;
+
+        let engine_handles = match engine_handles {
+            Some((engine_handles, _)) => engine_handles,
+            None => return,
+        };
+        let sample_rate = match &engine_handles.activated_info {
+            Some(activated_info) => activated_info.sample_rate,
+            None => return,
+        };
+
+        if render.is_some() {
+            engine_handles.timeline_tracks.sync_clips(state, sample_rate, |key, _| {
+                // The error has already been logged by the resource loader.
+                match resource_loader.load_pcm(key) {
+                    (pcm, Ok(())) => Some(PcmSource::Ram(pcm)),
+                    (_, Err(_)) => None,
+                }
+            });
+            return;
+        }
+
+        let mut streamed_clips = Vec::new();
+        for channel_i in 0..state.channels.len() {
+            timeline_clips(state, channel_i, sample_rate, |key, pcm_start| {
+                if resource_loader.should_stream(key) {
+                    streamed_clips.push((key.clone(), pcm_start));
+                }
+                None
+            });
+        }
+        if !timeline_sources.request_streams(resource_loader, &streamed_clips) {
+            // This is called again once the streams have been opened.
+            return;
+        }
+
+        engine_handles.timeline_tracks.sync_clips(state, sample_rate, |key, pcm_start| {
+            timeline_sources.source(resource_loader, key, pcm_start)
+        });
+
+        // Streams that were opened for clips which have since been removed,
+        // and the streams to try again next time.
+        timeline_sources.streams.clear();
+        timeline_sources.failed_streams.clear();
+    }
+}
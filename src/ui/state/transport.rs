@@ -0,0 +1,304 @@
+use dropseed::transport::LoopState;
+use meadowlark_core_types::time::MusicalTime;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+use vizia::prelude::*;
+
+use super::core_types::WMusicalTime;
+use super::{count_in_clicks, EngineHandles, TempoMap, UiData};
+
+/// The state of the transport, which controls playback of the timeline.
+#[derive(Debug, Lens, Clone, Serialize, Deserialize)]
+pub struct TransportState {
+    /// True while the timeline is playing.
+    #[serde(skip)]
+    pub is_playing: bool,
+
+    /// True while recording, including during the count-in.
+    #[serde(skip)]
+    pub is_recording: bool,
+
+    /// True while the metronome counts in before recording starts.
+    #[serde(skip)]
+    pub counting_in: bool,
+
+    /// The live position of the playhead, which is polled from the engine.
+    #[serde(skip)]
+    pub playhead: WMusicalTime,
+
+    /// The position the playhead was last moved to. Stopping the transport
+    /// returns the playhead here.
+    pub seek_position: WMusicalTime,
+
+    /// Whether playback loops around the loop range.
+    pub loop_enabled: bool,
+
+    pub loop_start: WMusicalTime,
+    pub loop_end: WMusicalTime,
+}
+
+impl Default for TransportState {
+    fn default() -> Self {
+        Self {
+            is_playing: false,
+            is_recording: false,
+            counting_in: false,
+            playhead: WMusicalTime::default(),
+            seek_position: WMusicalTime::default(),
+            loop_enabled: false,
+            loop_start: WMusicalTime::default(),
+            loop_end: MusicalTime::from_beats(16).into(),
+        }
+    }
+}
+
+impl TransportState {
+    /// The loop state to send to the transport of the engine.
+    pub(super) fn loop_state(&self, tempo_map: &TempoMap) -> LoopState {
+        if self.loop_enabled {
+            LoopState::Active {
+                loop_start: tempo_map.timeline_to_engine(self.loop_start.get()),
+                loop_end: tempo_map.timeline_to_engine(self.loop_end.get()),
+            }
+        } else {
+            LoopState::Inactive
+        }
+    }
+
+    /// Apply this state to the transport of a newly activated engine.
+    pub(super) fn restore(&mut self, tempo_map: &TempoMap, engine_handles: &mut EngineHandles) {
+        // The engine always starts out stopped.
+        self.is_playing = false;
+        self.is_recording = false;
+        self.counting_in = false;
+        self.playhead = self.seek_position;
+
+        if let Some(activated_info) = &mut engine_handles.activated_info {
+            activated_info
+                .transport_handle
+                .seek_to(tempo_map.timeline_to_engine(self.seek_position.get()));
+            activated_info.transport_handle.set_loop_state(self.loop_state(tempo_map));
+        }
+    }
+}
+
+pub enum TransportEvent {
+    Play,
+    Pause,
+    TogglePlay,
+    /// Stop playback and return the playhead to where it was last moved to.
+    Stop,
+    Seek(MusicalTime),
+    SetLoopEnabled(bool),
+    ToggleLoop,
+    /// Set the loop range. The range is ignored if it is empty.
+    SetLoopRange(MusicalTime, MusicalTime),
+    /// Tap out the tempo at the playhead.
+    TapTempo,
+    /// Start recording from the playhead, after the metronome's count-in if
+    /// it has one. Stops recording if it is already recording.
+    ToggleRecord,
+}
+
+impl UiData {
+    pub(super) fn on_transport_event(&mut self, transport_event: &TransportEvent) {
+        // The render controls the transport until it is done.
+        if self.render.is_some() {
+            return;
+        }
+
+        match transport_event {
+            TransportEvent::ToggleRecord => {
+                self.toggle_record();
+                return;
+            }
+            TransportEvent::TapTempo => {
+                if let Some(bpm) = self.tap_tempo.tap(Instant::now()) {
+                    let position = self.state.transport.playhead.get();
+                    self.state.timeline_grid.tempo_map.set_tempo_at(position, bpm);
+                    self.sync_metronome_clicks();
+                    self.sync_timeline_clips();
+                    self.sync_transport_tempo();
+                }
+                return;
+            }
+            TransportEvent::Pause | TransportEvent::TogglePlay | TransportEvent::Stop => {
+                self.stop_recording();
+            }
+            _ => {}
+        }
+
+        // Pick up any changes to the tempo map before playback starts.
+        if matches!(transport_event, TransportEvent::Play | TransportEvent::TogglePlay) {
+            self.sync_metronome_clicks();
+        }
+
+        let transport = &mut self.state.transport;
+        let transport_handle = self
+            .engine_handles
+            .as_mut()
+            .and_then(|(h, _)| h.activated_info.as_mut())
+            .map(|info| &mut info.transport_handle);
+
+        match transport_event {
+            TransportEvent::Play => {
+                transport.is_playing = true;
+            }
+            TransportEvent::Pause => {
+                transport.is_playing = false;
+            }
+            TransportEvent::TogglePlay => {
+                transport.is_playing = !transport.is_playing;
+            }
+            TransportEvent::Stop => {
+                transport.is_playing = false;
+                transport.playhead = transport.seek_position;
+            }
+            TransportEvent::Seek(position) => {
+                transport.seek_position = (*position).into();
+                transport.playhead = (*position).into();
+            }
+            TransportEvent::SetLoopEnabled(enabled) => {
+                transport.loop_enabled = *enabled;
+            }
+            TransportEvent::ToggleLoop => {
+                transport.loop_enabled = !transport.loop_enabled;
+            }
+            TransportEvent::SetLoopRange(start, end) => {
+                if start.as_beats_f64() >= end.as_beats_f64() {
+                    return;
+                }
+                transport.loop_start = (*start).into();
+                transport.loop_end = (*end).into();
+                self.state.timeline_grid.update_extent(&self.state.clips, *end);
+            }
+            TransportEvent::ToggleRecord | TransportEvent::TapTempo => {}
+        }
+
+        let transport_handle = match transport_handle {
+            Some(transport_handle) => transport_handle,
+            None => return,
+        };
+        let tempo_map = &self.state.timeline_grid.tempo_map;
+
+        match transport_event {
+            TransportEvent::Play | TransportEvent::Pause | TransportEvent::TogglePlay => {
+                transport_handle.set_playing(transport.is_playing);
+            }
+            TransportEvent::Stop => {
+                transport_handle.set_playing(false);
+                transport_handle
+                    .seek_to(tempo_map.timeline_to_engine(transport.seek_position.get()));
+            }
+            TransportEvent::Seek(position) => {
+                transport_handle.seek_to(tempo_map.timeline_to_engine(*position));
+            }
+            TransportEvent::SetLoopEnabled(_)
+            | TransportEvent::ToggleLoop
+            | TransportEvent::SetLoopRange(_, _) => {
+                transport_handle.set_loop_state(transport.loop_state(tempo_map));
+            }
+            TransportEvent::ToggleRecord | TransportEvent::TapTempo => {}
+        }
+    }
+
+    /// Send the loop range to the engine again after the tempo map has
+    /// changed, since it now starts and ends at different frames.
+    ///
+    /// While the transport is stopped, the playhead is also moved so that it
+    /// stays at the same position on the timeline.
+    pub(super) fn sync_transport_tempo(&mut self) {
+        let tempo_map = &self.state.timeline_grid.tempo_map;
+        let transport = &self.state.transport;
+        let transport_handle =
+            match self.engine_handles.as_mut().and_then(|(h, _)| h.activated_info.as_mut()) {
+                Some(activated_info) => &mut activated_info.transport_handle,
+                None => return,
+            };
+
+        transport_handle.set_loop_state(transport.loop_state(tempo_map));
+        if !transport.is_playing {
+            transport_handle.seek_to(tempo_map.timeline_to_engine(transport.playhead.get()));
+        }
+    }
+
+    fn toggle_record(&mut self) {
+        if self.state.transport.is_recording {
+            self.stop_recording();
+            self.on_transport_event(&TransportEvent::Pause);
+            return;
+        }
+
+        // TODO: Capture the audio input while recording.
+        self.state.transport.is_recording = true;
+
+        let sample_rate =
+            match self.engine_handles.as_ref().and_then(|(h, _)| h.activated_info.as_ref()) {
+                Some(activated_info) => activated_info.sample_rate.as_u32(),
+                None => return,
+            };
+
+        let bars = self.state.metronome.count_in.bars();
+        if bars > 0 && !self.state.transport.is_playing {
+            let (clicks, len_frames) = count_in_clicks(
+                &self.state.timeline_grid.tempo_map,
+                self.state.transport.playhead.get(),
+                bars,
+                sample_rate,
+            );
+
+            if let Some(handle) = self.metronome_plug_handle() {
+                handle.start_count_in(clicks, len_frames);
+                // Playback starts in `poll_transport()` once the count-in is
+                // over.
+                self.state.transport.counting_in = true;
+                return;
+            }
+        }
+
+        self.on_transport_event(&TransportEvent::Play);
+    }
+
+    fn stop_recording(&mut self) {
+        let transport = &mut self.state.transport;
+        transport.is_recording = false;
+
+        if transport.counting_in {
+            transport.counting_in = false;
+            if let Some(handle) = self.metronome_plug_handle() {
+                handle.cancel_count_in();
+            }
+        }
+    }
+
+    /// Update the playhead from the engine. Returns `true` if it moved.
+    pub(super) fn poll_transport(&mut self) -> bool {
+        if self.render.is_some() {
+            return false;
+        }
+
+        if self.state.transport.counting_in
+            && self.metronome_plug_handle().map_or(false, |h| h.take_count_in_finished())
+        {
+            self.state.transport.counting_in = false;
+            self.on_transport_event(&TransportEvent::Play);
+        }
+
+        let playhead =
+            match self.engine_handles.as_ref().and_then(|(h, _)| h.activated_info.as_ref()) {
+                Some(activated_info) => self
+                    .state
+                    .timeline_grid
+                    .tempo_map
+                    .engine_to_timeline(activated_info.transport_handle.playhead_position()),
+                None => return false,
+            };
+
+        let transport = &mut self.state.transport;
+        if transport.playhead.get() == playhead {
+            return false;
+        }
+        transport.playhead = playhead.into();
+        true
+    }
+}
@@ -0,0 +1,20 @@
+use std::path::PathBuf;
+
+/// Returns the folder Meadowlark stores its settings in.
+///
+/// This is `%APPDATA%\meadowlark` on Windows,
+/// `~/Library/Application Support/meadowlark` on MacOS, and
+/// `$XDG_CONFIG_HOME/meadowlark` (or `~/.config/meadowlark`) everywhere else.
+pub fn config_dir() -> PathBuf {
+    let env_dir = |var: &str| std::env::var_os(var).filter(|v| !v.is_empty()).map(PathBuf::from);
+
+    let base = if cfg!(target_os = "windows") {
+        env_dir("APPDATA")
+    } else if cfg!(target_os = "macos") {
+        env_dir("HOME").map(|home| home.join("Library").join("Application Support"))
+    } else {
+        env_dir("XDG_CONFIG_HOME").or_else(|| env_dir("HOME").map(|home| home.join(".config")))
+    };
+
+    base.unwrap_or_else(std::env::temp_dir).join("meadowlark")
+}
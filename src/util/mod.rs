@@ -1,3 +1,5 @@
+mod config_dir;
 mod twox_hash_map;
 
+pub use config_dir::config_dir;
 pub use twox_hash_map::TwoXHashMap;
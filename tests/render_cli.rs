@@ -0,0 +1,69 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn fixtures_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/test_files/render")
+}
+
+/// A temporary directory for the test named `name`, which is unique to this
+/// run of the tests so that they can run in parallel.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "meadowlark_test_render_cli_{}_{}",
+        std::process::id(),
+        name
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn render(project: &Path, output: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_meadowlark"))
+        .arg("render")
+        .arg(project)
+        .arg(output)
+        .args(["--sample-rate", "48000", "--tail", "0", "--no-dither"])
+        .output()
+        .expect("failed to run meadowlark")
+}
+
+/// The fixture has one kick clip on the timeline from beat 1 to beat 3, in a
+/// project that is 4 beats long at 120 bpm.
+#[test]
+fn render_project_with_clip() {
+    let dir = temp_dir("render_project_with_clip");
+    let output_path = dir.join("render.wav");
+    let output = render(&fixtures_dir().join("project.json"), &output_path);
+
+    let wav = hound::WavReader::open(&output_path).map(|mut reader| {
+        let (spec, duration) = (reader.spec(), reader.duration());
+        let samples: Vec<i32> = reader.samples::<i32>().map(|s| s.unwrap()).collect();
+        (spec, duration, samples)
+    });
+    let _ = std::fs::remove_dir_all(&dir);
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let (spec, duration, samples) = wav.unwrap();
+    assert_eq!(spec.sample_rate, 48000);
+    assert_eq!(duration, 96000);
+
+    let channels = usize::from(spec.channels);
+    let (before_clip, clip) = samples.split_at(24000 * channels);
+
+    // Nothing plays before the clip starts, and the metronome is never
+    // rendered.
+    assert!(before_clip.iter().all(|s| *s == 0));
+    assert!(clip.iter().any(|s| *s != 0));
+}
+
+#[test]
+fn render_project_with_missing_sample() {
+    let dir = temp_dir("render_project_with_missing_sample");
+    let output = render(&fixtures_dir().join("missing_sample.json"), &dir.join("render.wav"));
+
+    let _ = std::fs::remove_dir_all(&dir);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("missing.wav"));
+}